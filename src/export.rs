@@ -0,0 +1,7 @@
+pub mod arrow;
+pub mod ics;
+pub mod markdown;
+pub mod obsidian;
+pub mod sql;
+pub mod vcard;
+pub mod xlsx;
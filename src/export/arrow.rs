@@ -0,0 +1,244 @@
+use std::{io::Write, sync::Arc};
+
+use arrow::{
+    array::{
+        ArrayRef, BooleanBuilder, Float64Builder, ListBuilder, StringBuilder,
+        TimestampMillisecondBuilder,
+    },
+    datatypes::{DataType, Field, Schema, TimeUnit},
+    error::ArrowError,
+    ipc::writer::FileWriter,
+    record_batch::RecordBatch,
+};
+use chrono::DateTime;
+use parquet::{arrow::ArrowWriter, errors::ParquetError};
+
+use crate::models::{
+    blocks::Blocks,
+    database::{Column, Row},
+};
+
+use super::sql::column_names;
+
+/// The Arrow type a column's values are exported as.
+pub fn data_type(column: &Column) -> DataType {
+    match column.column_type.as_str() {
+        "number" => DataType::Float64,
+        "checkbox" => DataType::Boolean,
+        "multi_select" | "relation" => {
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)))
+        }
+        "created_time" | "last_edited_time" | "date" => {
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+        }
+        _ => DataType::Utf8,
+    }
+}
+
+/// The page id's field comes first, named as the SQL export names its key so
+/// it can't clash with a column.
+pub fn schema(columns: &[&Column]) -> Schema {
+    let names = column_names(columns);
+    Schema::new(
+        std::iter::once(Field::new(&names[0], DataType::Utf8, false))
+            .chain(
+                columns
+                    .iter()
+                    .zip(&names[1..])
+                    .map(|(column, name)| Field::new(name, data_type(column), true)),
+            )
+            .collect::<Vec<Field>>(),
+    )
+}
+
+fn timestamp_millis(value: &str) -> Option<i64> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.timestamp_millis())
+}
+
+fn column_array(column: &Column, rows: &[Row]) -> ArrayRef {
+    let blocks = rows.iter().map(|row| row.block(column));
+
+    match data_type(column) {
+        DataType::Float64 => {
+            let mut builder = Float64Builder::with_capacity(rows.len());
+            for row in rows.iter() {
+                builder.append_option(row.number(column));
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Boolean => {
+            let mut builder = BooleanBuilder::with_capacity(rows.len());
+            for block in blocks {
+                match block {
+                    Some(Blocks::Checkbox(value)) => builder.append_value(*value),
+                    _ => builder.append_null(),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::List(_) => {
+            let mut builder = ListBuilder::new(StringBuilder::new());
+            for block in blocks {
+                match block {
+                    Some(Blocks::MultiSelect(selections)) => {
+                        for selection in selections.iter() {
+                            builder.values().append_value(&selection.name);
+                        }
+                        builder.append(true);
+                    }
+                    Some(Blocks::Relation(relations)) => {
+                        for relation in relations.iter() {
+                            builder.values().append_value(&relation.id);
+                        }
+                        builder.append(true);
+                    }
+                    _ => builder.append(false),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Timestamp(_, _) => {
+            let mut builder = TimestampMillisecondBuilder::with_capacity(rows.len());
+            for (row, block) in rows.iter().zip(blocks) {
+                match block {
+                    // Last edited times aren't kept with the row's other
+                    // properties, but on the row itself.
+                    _ if column.column_type == "last_edited_time" => {
+                        builder.append_option(timestamp_millis(&row.last_edited_time))
+                    }
+                    Some(Blocks::CreatedTime(value)) => {
+                        builder.append_option(timestamp_millis(value))
+                    }
+                    // Only a range's start is kept.
+                    Some(Blocks::Date(Some(date))) => builder
+                        .append_option(date.start_time().map(|start| start.timestamp_millis())),
+                    _ => builder.append_null(),
+                }
+            }
+            Arc::new(builder.finish().with_timezone("UTC"))
+        }
+        _ => {
+            let mut builder = StringBuilder::new();
+            for block in blocks {
+                match block {
+                    Some(Blocks::PhoneNumber(None) | Blocks::Select(None)) | None => {
+                        builder.append_null()
+                    }
+                    Some(block) => builder.append_value(block.to_string()),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+    }
+}
+
+/// Converts the rows into a single record batch with one typed array per column.
+pub fn record_batch(columns: &[&Column], rows: &[Row]) -> Result<RecordBatch, ArrowError> {
+    let mut ids = StringBuilder::new();
+    for row in rows.iter() {
+        ids.append_value(&row.id);
+    }
+
+    let arrays = std::iter::once(Arc::new(ids.finish()) as ArrayRef)
+        .chain(columns.iter().map(|column| column_array(column, rows)))
+        .collect::<Vec<ArrayRef>>();
+
+    RecordBatch::try_new(Arc::new(schema(columns)), arrays)
+}
+
+pub fn write_parquet(
+    writer: impl Write + Send,
+    columns: &[&Column],
+    rows: &[Row],
+) -> Result<(), ParquetError> {
+    let batch = record_batch(columns, rows)?;
+    let mut writer = ArrowWriter::try_new(writer, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+pub fn write_ipc(writer: impl Write, columns: &[&Column], rows: &[Row]) -> Result<(), ArrowError> {
+    let batch = record_batch(columns, rows)?;
+    let mut writer = FileWriter::try_new(writer, &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use arrow::array::{Array, Float64Array, TimestampMillisecondArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::database::fixtures::{column, row};
+
+    #[test]
+    fn numbers_round_trip_through_parquet_unchanged() {
+        let column = column("a1", "Rate", "number");
+        let rows = [0.1, 2.675, 16777217.0, 1234567.89]
+            .iter()
+            .map(|number| {
+                row(json!({ "Rate": { "id": "a1", "type": "number", "number": number } }))
+            })
+            .collect::<Vec<Row>>();
+
+        let path = std::env::temp_dir().join(format!("margaret-{}.parquet", std::process::id()));
+        write_parquet(File::create(&path).unwrap(), &[&column], &rows).unwrap();
+        let batch = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let rates = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(
+            rates.values().to_vec(),
+            [0.1, 2.675, 16777217.0, 1234567.89]
+        );
+    }
+
+    #[test]
+    fn last_edited_times_come_from_the_row() {
+        let column = column("e1", "Edited", "last_edited_time");
+        let mut row = row(json!({
+            "Edited": { "id": "e1", "type": "last_edited_time",
+                "last_edited_time": "2024-01-03T04:05:00.000Z" },
+        }));
+        row.last_edited_time = "2024-01-03T04:05:00.000Z".to_string();
+
+        let batch = record_batch(&[&column], &[row]).unwrap();
+        let edited = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .unwrap();
+        assert_eq!(edited.value(0), 1_704_254_700_000);
+    }
+
+    #[test]
+    fn columns_named_like_the_key_dont_clash_with_it() {
+        let (id, other) = (
+            column("a1", "id", "number"),
+            column("a2", "ID", "rich_text"),
+        );
+        let names = schema(&[&id, &other])
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect::<Vec<String>>();
+        assert_eq!(names, ["notion_id", "id", "ID (2)"]);
+    }
+}
@@ -0,0 +1,180 @@
+use std::io::{self, Write};
+
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, Utc};
+
+use crate::models::{
+    blocks::{parse_date, Blocks, DateBlock},
+    database::{Column, Row},
+};
+
+/// Escapes text for use in an iCalendar (or vCard) property value.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Writes a content line, folding it so no line is longer than 75 octets.
+pub(crate) fn write_line(writer: &mut impl Write, line: &str) -> io::Result<()> {
+    let mut rest = line;
+    let mut limit = 75;
+    while rest.len() > limit {
+        let mut split = limit;
+        while !rest.is_char_boundary(split) {
+            split -= 1;
+        }
+        write!(writer, "{}\r\n ", &rest[..split])?;
+        rest = &rest[split..];
+        // Continuation lines start with a space, which counts towards the limit.
+        limit = 74;
+    }
+    write!(writer, "{}\r\n", rest)
+}
+
+fn utc(time: DateTime<FixedOffset>) -> String {
+    time.with_timezone(&Utc)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+fn day(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+/// `DTSTART` and `DTEND` lines for a date. All-day events end the day after
+/// their last day, since `DTEND` is exclusive.
+fn dates(date: &DateBlock) -> Option<Vec<String>> {
+    let start = date.start_time()?;
+    if date.is_all_day() {
+        let last = date.end_time().unwrap_or(start).date_naive();
+        return Some(vec![
+            format!("DTSTART;VALUE=DATE:{}", day(start.date_naive())),
+            format!(
+                "DTEND;VALUE=DATE:{}",
+                day(last.checked_add_days(Days::new(1))?)
+            ),
+        ]);
+    }
+    let mut lines = vec![format!("DTSTART:{}", utc(start))];
+    if let Some(end) = date.end_time() {
+        lines.push(format!("DTEND:{}", utc(end)));
+    }
+    Some(lines)
+}
+
+/// Writes an iCalendar file with an event for each row with a date in
+/// `date_column`. Each event's UID is its page's id, so importing the file
+/// again updates events rather than duplicating them. Returns the number of
+/// events written.
+pub fn write_ics(
+    writer: &mut impl Write,
+    name: &str,
+    date_column: &Column,
+    description_columns: &[&Column],
+    rows: &[Row],
+) -> io::Result<usize> {
+    write_line(writer, "BEGIN:VCALENDAR")?;
+    write_line(writer, "VERSION:2.0")?;
+    write_line(writer, "PRODID:-//margaret//margaret//EN")?;
+    write_line(writer, "CALSCALE:GREGORIAN")?;
+    write_line(writer, &format!("X-WR-CALNAME:{}", escape(name)))?;
+
+    let mut events = 0;
+    for row in rows.iter() {
+        let Some(Blocks::Date(Some(date))) = row.block(date_column) else {
+            continue;
+        };
+        let Some(dates) = dates(date) else {
+            continue;
+        };
+        let modified = parse_date(&row.last_edited_time)
+            .map(utc)
+            .unwrap_or_else(|| Utc::now().format("%Y%m%dT%H%M%SZ").to_string());
+        let description = description_columns
+            .iter()
+            .filter_map(|column| {
+                let block = row.block(column).filter(|block| !block.is_empty())?;
+                Some(format!("{}: {}", column.name, block.items().join(", ")))
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        write_line(writer, "BEGIN:VEVENT")?;
+        write_line(writer, &format!("UID:{}", row.id))?;
+        write_line(writer, &format!("DTSTAMP:{}", modified))?;
+        write_line(writer, &format!("LAST-MODIFIED:{}", modified))?;
+        for line in dates.iter() {
+            write_line(writer, line)?;
+        }
+        let summary = row.title().unwrap_or_else(|| "Untitled".to_string());
+        write_line(writer, &format!("SUMMARY:{}", escape(&summary)))?;
+        if !description.is_empty() {
+            write_line(writer, &format!("DESCRIPTION:{}", escape(&description)))?;
+        }
+        write_line(writer, &format!("URL:{}", row.url))?;
+        write_line(writer, "END:VEVENT")?;
+        events += 1;
+    }
+
+    write_line(writer, "END:VCALENDAR")?;
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folded(line: &str) -> String {
+        let mut out = Vec::new();
+        write_line(&mut out, line).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn long_lines_are_folded_at_75_octets() {
+        let line = "X".repeat(160);
+        let out = folded(&line);
+        let lines = out.split("\r\n").collect::<Vec<&str>>();
+        assert_eq!(
+            lines.iter().map(|line| line.len()).collect::<Vec<usize>>(),
+            [75, 75, 12, 0]
+        );
+        assert_eq!(out.replace("\r\n ", ""), line + "\r\n");
+    }
+
+    #[test]
+    fn folding_never_splits_a_character() {
+        let line = format!("SUMMARY:{}", "é".repeat(50));
+        let out = folded(&line);
+        for part in out.split("\r\n") {
+            assert!(part.len() <= 75);
+        }
+        assert_eq!(out.replace("\r\n ", ""), line + "\r\n");
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(escape("a, b; c\\d\r\ne\nf"), r"a\, b\; c\\d\ne\nf");
+    }
+
+    #[test]
+    fn all_day_events_end_the_day_after() {
+        let date: DateBlock = serde_json::from_value(serde_json::json!({
+            "start": "2024-03-05",
+            "end": "2024-03-07",
+        }))
+        .unwrap();
+        assert_eq!(
+            dates(&date).unwrap(),
+            ["DTSTART;VALUE=DATE:20240305", "DTEND;VALUE=DATE:20240308"]
+        );
+
+        let date: DateBlock = serde_json::from_value(serde_json::json!({
+            "start": "2024-03-05T09:00:00.000+10:00",
+        }))
+        .unwrap();
+        assert_eq!(dates(&date).unwrap(), ["DTSTART:20240304T230000Z"]);
+    }
+}
@@ -0,0 +1,586 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    models::{
+        blocks::{Blocks, RichText, TextTypes},
+        content::{download_images, fetch_page_content, BlockContent, ContentBlock, TableContent},
+        database::{Cell, Column, Row},
+    },
+    render::unique_path,
+};
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#' | '|'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Wraps `text` in `marker`, keeping surrounding whitespace outside it, since
+/// `** bold**` isn't bold in Markdown.
+fn wrap(text: &str, marker: &str) -> String {
+    wrap_with(text, marker, marker)
+}
+
+fn wrap_with(text: &str, open: &str, close: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let start = text.len() - text.trim_start().len();
+    let end = start + trimmed.len();
+    format!(
+        "{}{}{}{}{}",
+        &text[..start],
+        open,
+        trimmed,
+        close,
+        &text[end..]
+    )
+}
+
+/// The longest run of backticks in `text`, which a code span or block's
+/// fence has to be longer than.
+fn longest_backticks(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+/// Wraps `text` in a code span. Backticks at either end are kept apart
+/// from the fence by a space, which Markdown strips.
+fn code_span(text: &str) -> String {
+    let fence = "`".repeat(longest_backticks(text) + 1);
+    let trimmed = text.trim();
+    if trimmed.starts_with('`') || trimmed.ends_with('`') {
+        wrap_with(text, &format!("{} ", fence), &format!(" {}", fence))
+    } else {
+        wrap(text, &fence)
+    }
+}
+
+/// A link's destination, with the characters that would end it early
+/// percent-encoded.
+fn destination(url: &str) -> String {
+    url.replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('(', "%28")
+        .replace(')', "%29")
+        .replace('<', "%3C")
+        .replace('>', "%3E")
+}
+
+/// Converts rich text to inline Markdown, keeping links and formatting.
+pub fn rich_text(texts: &[RichText]) -> String {
+    texts
+        .iter()
+        .map(|text| {
+            if let (TextTypes::Equation, Some(equation)) = (&text.block_type, &text.equation) {
+                return format!("${}$", escape(&equation.expression).replace('$', "\\$"));
+            }
+            let annotations = &text.annotations;
+            let mut out = if annotations.code {
+                code_span(&text.plain_text)
+            } else {
+                escape(&text.plain_text)
+            };
+            if annotations.bold {
+                out = wrap(&out, "**");
+            }
+            if annotations.italic {
+                out = wrap(&out, "*");
+            }
+            if annotations.strikethrough {
+                out = wrap(&out, "~~");
+            }
+            match &text.href {
+                Some(href) => format!("[{}]({})", out, destination(href)),
+                None => out,
+            }
+        })
+        .collect()
+}
+
+fn plain_text(texts: &[RichText]) -> String {
+    texts.iter().map(|text| text.plain_text.as_str()).collect()
+}
+
+fn indent(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", prefix, line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Prefixes every line, blank ones included, e.g. for block quotes.
+fn prefix_lines(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|line| format!("{}{}", prefix, line).trim_end().to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn table(content: &TableContent, rows: &[ContentBlock]) -> String {
+    let rows = rows
+        .iter()
+        .filter_map(|row| match &row.content {
+            Some(BlockContent::TableRow(row)) => Some(
+                row.cells
+                    .iter()
+                    .map(|cell| rich_text(cell).replace('\n', "<br>"))
+                    .collect::<Vec<String>>(),
+            ),
+            _ => None,
+        })
+        .collect::<Vec<Vec<String>>>();
+    let line = |cells: &[String]| {
+        let mut cells = cells.to_vec();
+        cells.resize(content.table_width, String::new());
+        format!("| {} |", cells.join(" | "))
+    };
+
+    let (header, body) = match rows.split_first() {
+        Some((header, body)) if content.has_column_header => (header.clone(), body),
+        _ => (Vec::new(), rows.as_slice()),
+    };
+    let mut lines = vec![
+        line(&header),
+        format!("|{}", " --- |".repeat(content.table_width)),
+    ];
+    lines.extend(body.iter().map(|row| line(row)));
+    lines.join("\n")
+}
+
+/// Converts one block, along with its children, to Markdown. `number` is
+/// the block's position when it's part of a numbered list.
+fn block(block: &ContentBlock, number: usize) -> Option<String> {
+    let children = blocks(&block.children);
+    let with_children = |text: String, child_indent: &str| {
+        if children.is_empty() {
+            text
+        } else if child_indent.is_empty() {
+            format!("{}\n\n{}", text, children)
+        } else {
+            format!("{}\n{}", text, indent(&children, child_indent))
+        }
+    };
+
+    let markdown = match block.content.as_ref()? {
+        BlockContent::Paragraph(content) => with_children(rich_text(&content.rich_text), ""),
+        BlockContent::Heading1(content) => {
+            with_children(format!("# {}", rich_text(&content.rich_text)), "")
+        }
+        BlockContent::Heading2(content) => {
+            with_children(format!("## {}", rich_text(&content.rich_text)), "")
+        }
+        BlockContent::Heading3(content) => {
+            with_children(format!("### {}", rich_text(&content.rich_text)), "")
+        }
+        BlockContent::BulletedListItem(content) => {
+            with_children(format!("- {}", rich_text(&content.rich_text)), "  ")
+        }
+        BlockContent::NumberedListItem(content) => {
+            let marker = format!("{}. ", number);
+            let child_indent = " ".repeat(marker.len());
+            with_children(
+                format!("{}{}", marker, rich_text(&content.rich_text)),
+                &child_indent,
+            )
+        }
+        BlockContent::ToDo(content) => with_children(
+            format!(
+                "- [{}] {}",
+                if content.checked { "x" } else { " " },
+                rich_text(&content.rich_text)
+            ),
+            "  ",
+        ),
+        BlockContent::Code(content) => {
+            let code = plain_text(&content.rich_text);
+            let fence = "`".repeat(longest_backticks(&code).max(2) + 1);
+            let language = match content.language.as_str() {
+                "plain text" => "",
+                language => language,
+            };
+            format!("{}{}\n{}\n{}", fence, language, code, fence)
+        }
+        BlockContent::Quote(content) => {
+            prefix_lines(&with_children(rich_text(&content.rich_text), ""), "> ")
+        }
+        BlockContent::Callout(content) => {
+            let icon = content
+                .icon
+                .as_ref()
+                .and_then(|icon| icon.emoji.clone())
+                .map(|emoji| emoji + " ")
+                .unwrap_or_default();
+            prefix_lines(
+                &with_children(format!("{}{}", icon, rich_text(&content.rich_text)), ""),
+                "> ",
+            )
+        }
+        BlockContent::Toggle(content) => format!(
+            "<details>\n<summary>{}</summary>\n\n{}\n\n</details>",
+            rich_text(&content.rich_text),
+            children
+        ),
+        BlockContent::Table(content) => table(content, &block.children),
+        BlockContent::TableRow(_) => return None,
+        BlockContent::Image(content) => {
+            format!(
+                "![{}]({})",
+                escape(&plain_text(&content.caption)),
+                destination(content.url()?)
+            )
+        }
+        BlockContent::Divider(_) => "---".to_string(),
+        BlockContent::ChildPage(content) => format!("📄 {}", escape(&content.title)),
+    };
+    Some(markdown)
+}
+
+fn is_list_item(block: &ContentBlock) -> bool {
+    matches!(
+        block.content,
+        Some(
+            BlockContent::BulletedListItem(_)
+                | BlockContent::NumberedListItem(_)
+                | BlockContent::ToDo(_)
+        )
+    )
+}
+
+/// Converts page content to Markdown. Block types we don't understand are left out.
+pub fn blocks(blocks: &[ContentBlock]) -> String {
+    let mut out = String::new();
+    let mut number = 0;
+    let mut previous: Option<&ContentBlock> = None;
+
+    for current in blocks.iter() {
+        number = match (&current.content, previous.map(|block| &block.content)) {
+            (
+                Some(BlockContent::NumberedListItem(_)),
+                Some(Some(BlockContent::NumberedListItem(_))),
+            ) => number + 1,
+            _ => 1,
+        };
+        let Some(markdown) = block(current, number) else {
+            continue;
+        };
+
+        if let Some(previous) = previous {
+            let same_list = is_list_item(previous)
+                && is_list_item(current)
+                && previous.block_type == current.block_type;
+            out.push_str(if same_list { "\n" } else { "\n\n" });
+        }
+        out.push_str(&markdown);
+        previous = Some(current);
+    }
+    out
+}
+
+/// A property's value as it appears in front matter: numbers, booleans and
+/// lists keep their types, and everything else is a string.
+pub fn property_value(cell: &Cell) -> Value {
+    let Some(block) = &cell.block else {
+        return Value::Null;
+    };
+    match block {
+        // Notion's own value, which the f32 in `Blocks::Number` can't always
+        // hold exactly.
+        Blocks::Number(_) => match &cell.raw["number"] {
+            number @ Value::Number(_) => number.clone(),
+            _ => Value::Null,
+        },
+        Blocks::Checkbox(value) => json!(value),
+        Blocks::MultiSelect(_) | Blocks::Relation(_) | Blocks::Files(_) => json!(block.items()),
+        block if block.is_empty() => Value::Null,
+        block => json!(block.to_string()),
+    }
+}
+
+fn yaml_key(key: &str) -> String {
+    let plain = key
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == ' ' || c == '_' || c == '-')
+        && key.starts_with(|c: char| c.is_alphanumeric())
+        && !key.ends_with(' ');
+    if plain {
+        key.to_string()
+    } else {
+        json!(key).to_string()
+    }
+}
+
+/// YAML front matter, with each value written as JSON (which YAML accepts).
+pub fn front_matter(entries: &[(String, Value)]) -> String {
+    let mut out = "---\n".to_string();
+    for (key, value) in entries.iter() {
+        out.push_str(&format!("{}: {}\n", yaml_key(key), value));
+    }
+    out.push_str("---\n");
+    out
+}
+
+/// A Markdown document: front matter, then the content.
+pub fn document(entries: &[(String, Value)], content: &[ContentBlock]) -> String {
+    let body = blocks(content);
+    if body.is_empty() {
+        front_matter(entries)
+    } else {
+        format!("{}\n{}\n", front_matter(entries), body)
+    }
+}
+
+/// A row as a Markdown document: its properties as front matter, then its content.
+pub fn row_document(row: &Row, columns: &[&Column], content: &[ContentBlock]) -> String {
+    let entries = std::iter::once(("notion_id".to_string(), json!(row.id)))
+        .chain(columns.iter().map(|column| {
+            (
+                column.name.clone(),
+                row.cell(column).map(property_value).unwrap_or(Value::Null),
+            )
+        }))
+        .collect::<Vec<(String, Value)>>();
+    document(&entries, content)
+}
+
+/// Replaces characters that aren't allowed in file names on common systems
+/// (or that Obsidian treats specially in links), returning `None` if nothing's left.
+pub fn safe_file_name(name: &str) -> Option<String> {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '#' | '^' | '[' | ']' | '\n'
+            | '\r' | '\0' => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let name = name.trim().trim_start_matches('.').trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// A file name for the row's document, from its title or, failing that, its id.
+pub fn file_name(row: &Row) -> String {
+    let title = row.title().and_then(|title| safe_file_name(&title));
+    format!("{}.md", title.unwrap_or_else(|| row.id.clone()))
+}
+
+/// Fetches each row's content and writes it to its own Markdown file in `dir`,
+/// returning the paths written. Images uploaded to Notion are saved in
+/// `dir/images`.
+pub async fn write_markdown(
+    token: &str,
+    dir: &Path,
+    columns: &[&Column],
+    rows: &[Row],
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+
+    for row in rows.iter() {
+        let mut content = fetch_page_content(token, &row.id).await?;
+        download_images(&mut content, &dir.join("images"), "images").await?;
+        let path = unique_path(dir.join(file_name(row)), &written);
+        fs::write(&path, row_document(row, columns, &content))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::database::fixtures::text;
+
+    /// A single segment of rich text, with `changes` made to Notion's JSON for it.
+    fn segment(content: &str, changes: impl FnOnce(&mut Value)) -> RichText {
+        let mut segment = text(&[content])[0].clone();
+        changes(&mut segment);
+        serde_json::from_value(segment).unwrap()
+    }
+
+    fn block(block_type: &str, content: Value, children: Vec<ContentBlock>) -> ContentBlock {
+        let mut block: ContentBlock = serde_json::from_value(json!({
+            "id": "b1",
+            "type": block_type,
+            "has_children": !children.is_empty(),
+            block_type: content,
+        }))
+        .unwrap();
+        block.children = children;
+        block
+    }
+
+    fn paragraph(block_type: &str, content: &str, children: Vec<ContentBlock>) -> ContentBlock {
+        block(
+            block_type,
+            json!({ "rich_text": text(&[content]) }),
+            children,
+        )
+    }
+
+    #[test]
+    fn rich_text_keeps_formatting_and_escapes_the_rest() {
+        let bold = segment("Ada ", |segment| {
+            segment["annotations"]["bold"] = json!(true)
+        });
+        let plain = segment("said 2 * 3_ ", |_| {});
+        let code = segment("a`b", |segment| {
+            segment["annotations"]["code"] = json!(true)
+        });
+        let ticked = segment("`x`", |segment| {
+            segment["annotations"]["code"] = json!(true)
+        });
+        assert_eq!(
+            rich_text(&[bold, plain, code, ticked]),
+            "**Ada** said 2 \\* 3\\_ ``a`b```` `x` ``"
+        );
+
+        let link = segment("docs", |segment| {
+            segment["href"] = json!("https://example.com/a b (1)")
+        });
+        assert_eq!(
+            rich_text(&[link]),
+            "[docs](https://example.com/a%20b%20%281%29)"
+        );
+
+        let equation = segment("", |segment| {
+            segment["type"] = json!("equation");
+            segment["equation"] = json!({ "expression": "x_1 = $5 <b>" });
+        });
+        assert_eq!(rich_text(&[equation]), "$x\\_1 = \\$5 \\<b\\>$");
+    }
+
+    #[test]
+    fn numbered_lists_count_up_and_children_are_indented() {
+        let content = [
+            paragraph(
+                "numbered_list_item",
+                "One",
+                vec![paragraph("bulleted_list_item", "Inner", Vec::new())],
+            ),
+            paragraph("numbered_list_item", "Two", Vec::new()),
+            paragraph("paragraph", "Between", Vec::new()),
+            paragraph("numbered_list_item", "Again", Vec::new()),
+        ];
+        assert_eq!(
+            blocks(&content),
+            "1. One\n   - Inner\n2. Two\n\nBetween\n\n1. Again"
+        );
+    }
+
+    #[test]
+    fn tables_have_a_header_and_rows_as_wide_as_the_table() {
+        let row = |cells: &[&str]| {
+            let cells = cells
+                .iter()
+                .map(|cell| text(&[cell]))
+                .collect::<Vec<Value>>();
+            block("table_row", json!({ "cells": cells }), Vec::new())
+        };
+        let content = [block(
+            "table",
+            json!({ "table_width": 2, "has_column_header": true }),
+            vec![
+                row(&["Name", "Notes"]),
+                row(&["Ada", "first\nsecond | third"]),
+                row(&["Charles"]),
+            ],
+        )];
+        assert_eq!(
+            blocks(&content),
+            "| Name | Notes |\n| --- | --- |\n| Ada | first<br>second \\| third |\n| Charles |  |"
+        );
+    }
+
+    #[test]
+    fn code_fences_are_longer_than_any_backticks_inside() {
+        let code = |code: &str, language: &str| {
+            block(
+                "code",
+                json!({ "rich_text": text(&[code]), "language": language }),
+                Vec::new(),
+            )
+        };
+        assert_eq!(
+            blocks(&[code("let a = 1;", "rust")]),
+            "```rust\nlet a = 1;\n```"
+        );
+        assert_eq!(
+            blocks(&[code("say ````", "plain text")]),
+            "`````\nsay ````\n`````"
+        );
+    }
+
+    #[test]
+    fn front_matter_quotes_keys_yaml_would_misread() {
+        assert_eq!(yaml_key("Due date"), "Due date");
+        assert_eq!(yaml_key("Status: done"), r#""Status: done""#);
+        assert_eq!(yaml_key("#tag"), r##""#tag""##);
+        assert_eq!(yaml_key("Trailing "), r#""Trailing ""#);
+        assert_eq!(
+            front_matter(&[
+                ("Name".to_string(), json!("Ada \"the Countess\"")),
+                ("Score".to_string(), json!(16777217)),
+                ("Tags: all".to_string(), json!(["a", "b"])),
+            ]),
+            "---\nName: \"Ada \\\"the Countess\\\"\"\nScore: 16777217\n\"Tags: all\": [\"a\",\"b\"]\n---\n"
+        );
+    }
+
+    #[test]
+    fn property_values_keep_numbers_exact() {
+        let cell = |value: Value| -> Cell { serde_json::from_value(value).unwrap() };
+        assert_eq!(
+            property_value(&cell(
+                json!({ "id": "a", "type": "number", "number": 1234567.89 })
+            )),
+            json!(1234567.89)
+        );
+        assert_eq!(
+            property_value(&cell(
+                json!({ "id": "a", "type": "number", "number": null })
+            )),
+            Value::Null
+        );
+        assert_eq!(
+            property_value(&cell(
+                json!({ "id": "a", "type": "rich_text", "rich_text": [] })
+            )),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn file_names_lose_characters_systems_wont_take() {
+        assert_eq!(
+            safe_file_name("Q1/Q2: plans?").as_deref(),
+            Some("Q1_Q2_ plans_")
+        );
+        assert_eq!(safe_file_name("  .hidden ").as_deref(), Some("hidden"));
+        assert_eq!(
+            safe_file_name("[[Launch]] #1").as_deref(),
+            Some("__Launch__ _1")
+        );
+        assert_eq!(safe_file_name(" ... "), None);
+    }
+}
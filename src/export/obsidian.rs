@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    get_db_columns, get_db_title,
+    models::{
+        blocks::Blocks,
+        content::{download_images, fetch_page_content},
+        database::{
+            fetch_notion_database, query_notion_database, Column, DatabaseCredentials, Row,
+        },
+    },
+    render::unique_path,
+};
+
+use super::markdown::{document, file_name, property_value, safe_file_name};
+
+/// A database written into the vault, along with the folder its notes go in.
+struct VaultDatabase {
+    folder: PathBuf,
+    columns: Vec<Column>,
+    rows: Vec<Row>,
+}
+
+/// What writing a vault did.
+#[derive(Debug, Default)]
+pub struct VaultReport {
+    pub written: Vec<PathBuf>,
+    /// Related databases that couldn't be fetched, with why. Links to their
+    /// rows are left as plain ids.
+    pub unreachable: Vec<(String, String)>,
+}
+
+/// Fetches the database and the databases reachable from it through at most
+/// `depth` relation columns. The first goes in the vault's root, the rest in
+/// a folder named after each database. Related databases that can't be
+/// fetched are left out and added to `unreachable`.
+async fn fetch_databases(
+    token: &str,
+    database_id: &str,
+    depth: usize,
+    unreachable: &mut Vec<(String, String)>,
+) -> Result<Vec<VaultDatabase>, Box<dyn Error>> {
+    let mut databases = Vec::new();
+    let mut queue = vec![(database_id.to_string(), 0)];
+    let mut seen = vec![database_id.replace('-', "")];
+    let mut folders = Vec::new();
+
+    while !queue.is_empty() {
+        let (id, distance) = queue.remove(0);
+        let credentials = DatabaseCredentials {
+            id: id.clone(),
+            token: token.to_string(),
+        };
+        let fetched = async {
+            let db = fetch_notion_database(&credentials).await?;
+            let rows = query_notion_database(&credentials, None).await?;
+            Ok::<_, Box<dyn Error>>((db, rows))
+        }
+        .await;
+        let (db, rows) = match fetched {
+            Ok(fetched) => fetched,
+            // Only the database being exported has to be there.
+            Err(err) if !databases.is_empty() => {
+                unreachable.push((id, err.to_string()));
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        let columns = get_db_columns(&db.body)?.unwrap_or_default();
+
+        if distance < depth {
+            for relation in columns.iter().filter_map(|column| column.relation.as_ref()) {
+                let related = relation.database_id.replace('-', "");
+                if !seen.contains(&related) {
+                    seen.push(related);
+                    queue.push((relation.database_id.clone(), distance + 1));
+                }
+            }
+        }
+
+        let folder = if databases.is_empty() {
+            PathBuf::new()
+        } else {
+            let name = get_db_title(&db.body)?
+                .and_then(|title| safe_file_name(&title))
+                .unwrap_or(id);
+            unique_path(PathBuf::from(name), &folders)
+        };
+        folders.push(folder.clone());
+
+        databases.push(VaultDatabase {
+            folder,
+            rows,
+            columns,
+        });
+    }
+    Ok(databases)
+}
+
+/// `[[folder/note|title]]`, or just `[[note]]` when that's all it'd say.
+/// Characters that would end the link or its alias early are replaced in the
+/// title, as `safe_file_name` does for the target.
+fn wikilink(path: &Path, title: &str) -> String {
+    let target = path.with_extension("").to_string_lossy().replace('\\', "/");
+    let title = title
+        .chars()
+        .map(|c| match c {
+            '|' | '[' | ']' => '_',
+            '\n' | '\r' => ' ',
+            c => c,
+        })
+        .collect::<String>();
+    let title = title.trim();
+    if title.is_empty() || target == title {
+        format!("[[{}]]", target)
+    } else {
+        format!("[[{}|{}]]", target, title)
+    }
+}
+
+/// Writes an Obsidian vault to `vault`: a note per row of the database and
+/// those up to `depth` relations away, with properties as front matter and
+/// content as Markdown, and relations as links between notes. Relations to
+/// rows outside the vault are left as plain ids. Images uploaded to Notion are
+/// saved in an `images` folder beside each database's notes.
+pub async fn write_vault(
+    token: &str,
+    database_id: &str,
+    vault: &Path,
+    depth: usize,
+) -> Result<VaultReport, Box<dyn Error>> {
+    let mut report = VaultReport::default();
+    let databases = fetch_databases(token, database_id, depth, &mut report.unreachable).await?;
+
+    // Every note's path within the vault has to be known up front so relations
+    // can link to notes that haven't been written yet.
+    let mut paths = Vec::new();
+    let mut links = HashMap::new();
+    for database in databases.iter() {
+        for row in database.rows.iter() {
+            let path = unique_path(database.folder.join(file_name(row)), &paths);
+            let title = row.title().unwrap_or_else(|| row.id.clone());
+            links.insert(row.id.replace('-', ""), wikilink(&path, &title));
+            paths.push(path);
+        }
+    }
+
+    let mut paths = paths.into_iter();
+    for database in databases.iter() {
+        fs::create_dir_all(vault.join(&database.folder))?;
+
+        for row in database.rows.iter() {
+            let entries = std::iter::once(("notion_id".to_string(), json!(row.id)))
+                .chain(database.columns.iter().map(|column| {
+                    let value = match row.block(column) {
+                        Some(Blocks::Relation(relations)) => json!(relations
+                            .iter()
+                            .map(|relation| links
+                                .get(&relation.id.replace('-', ""))
+                                .cloned()
+                                .unwrap_or_else(|| relation.id.clone()))
+                            .collect::<Vec<String>>()),
+                        _ => row.cell(column).map(property_value).unwrap_or(Value::Null),
+                    };
+                    (column.name.clone(), value)
+                }))
+                .collect::<Vec<(String, Value)>>();
+
+            let mut content = fetch_page_content(token, &row.id).await?;
+            let images = vault.join(&database.folder).join("images");
+            download_images(&mut content, &images, "images").await?;
+            let path = vault.join(paths.next().unwrap());
+            fs::write(&path, document(&entries, &content))?;
+            report.written.push(path);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_cant_break_out_of_links() {
+        let path = Path::new("Projects/Launch.md");
+        assert_eq!(wikilink(path, "Launch"), "[[Projects/Launch|Launch]]");
+        assert_eq!(
+            wikilink(path, "Q1 | Q2 [[draft]]\nnotes"),
+            "[[Projects/Launch|Q1 _ Q2 __draft__ notes]]"
+        );
+        assert_eq!(wikilink(Path::new("Launch.md"), "Launch"), "[[Launch]]");
+        assert_eq!(wikilink(path, "\n"), "[[Projects/Launch]]");
+    }
+}
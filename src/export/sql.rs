@@ -0,0 +1,382 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+
+use crate::models::{
+    blocks::Blocks,
+    database::{Cell, Column, Row},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Dialect {
+    Postgres,
+    Mysql,
+    Sqlite,
+}
+
+impl Dialect {
+    fn quote_identifier(&self, identifier: &str) -> String {
+        match self {
+            Dialect::Mysql => format!("`{}`", identifier.replace('`', "``")),
+            Dialect::Postgres | Dialect::Sqlite => {
+                format!("\"{}\"", identifier.replace('"', "\"\""))
+            }
+        }
+    }
+
+    fn quote_string(&self, value: &str) -> String {
+        match self {
+            // MySQL treats backslashes as escapes unless NO_BACKSLASH_ESCAPES is set.
+            Dialect::Mysql => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''")),
+            Dialect::Postgres | Dialect::Sqlite => format!("'{}'", value.replace('\'', "''")),
+        }
+    }
+
+    fn column_type(&self, column: &Column) -> &'static str {
+        match (self, column.column_type.as_str()) {
+            (Dialect::Postgres, "number") => "DOUBLE PRECISION",
+            (Dialect::Mysql, "number") => "DOUBLE",
+            (Dialect::Sqlite, "number") => "REAL",
+            (Dialect::Postgres | Dialect::Mysql, "checkbox") => "BOOLEAN",
+            (Dialect::Sqlite, "checkbox") => "INTEGER",
+            (Dialect::Postgres, "multi_select" | "relation") => "TEXT[]",
+            (Dialect::Mysql, "multi_select" | "relation") => "JSON",
+            (Dialect::Postgres, "created_time" | "last_edited_time" | "date") => "TIMESTAMPTZ",
+            (Dialect::Mysql, "created_time" | "last_edited_time" | "date") => "DATETIME(3)",
+            _ => "TEXT",
+        }
+    }
+
+    fn timestamp(&self, value: &str) -> String {
+        match self {
+            // DATETIME has no notion of time zones, and Notion's are always UTC.
+            Dialect::Mysql => self.quote_string(value.replace('T', " ").trim_end_matches('Z')),
+            Dialect::Postgres | Dialect::Sqlite => self.quote_string(value),
+        }
+    }
+
+    fn array(&self, values: Vec<String>) -> String {
+        match self {
+            Dialect::Postgres => format!(
+                "ARRAY[{}]::TEXT[]",
+                values
+                    .iter()
+                    .map(|value| self.quote_string(value))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Dialect::Mysql => format!(
+                "JSON_ARRAY({})",
+                values
+                    .iter()
+                    .map(|value| self.quote_string(value))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Dialect::Sqlite => self.quote_string(&serde_json::to_string(&values).unwrap()),
+        }
+    }
+
+    fn literal(&self, cell: Option<&Cell>) -> String {
+        let Some(cell) = cell else {
+            return "NULL".to_string();
+        };
+        match &cell.block {
+            None => "NULL".to_string(),
+            Some(Blocks::Checkbox(value)) => match self {
+                Dialect::Sqlite => (*value as u8).to_string(),
+                Dialect::Postgres | Dialect::Mysql => value.to_string().to_uppercase(),
+            },
+            // From Notion's own value, which the f32 in `Blocks::Number`
+            // can't always hold exactly.
+            Some(Blocks::Number(_)) => match cell.raw["number"].as_f64() {
+                Some(number) if number.is_finite() => number.to_string(),
+                _ => "NULL".to_string(),
+            },
+            Some(block @ (Blocks::Title(_) | Blocks::RichText(_))) => {
+                self.quote_string(&block.plain_text())
+            }
+            Some(Blocks::CreatedBy(user)) => self.quote_string(user.id()),
+            Some(Blocks::CreatedTime(value)) => self.timestamp(value),
+            Some(Blocks::Date(None) | Blocks::PhoneNumber(None) | Blocks::Select(None)) => {
+                "NULL".to_string()
+            }
+            // Only a range's start is kept.
+            Some(Blocks::Date(Some(date))) => match (self, date.start_time()) {
+                (Dialect::Mysql, Some(start)) => self.quote_string(
+                    &start
+                        .naive_utc()
+                        .format("%Y-%m-%d %H:%M:%S%.3f")
+                        .to_string(),
+                ),
+                _ => self.quote_string(&date.start),
+            },
+            Some(Blocks::MultiSelect(selections)) => self.array(
+                selections
+                    .iter()
+                    .map(|selection| selection.name.clone())
+                    .collect(),
+            ),
+            Some(Blocks::Relation(relations)) => self.array(
+                relations
+                    .iter()
+                    .map(|relation| relation.id.clone())
+                    .collect(),
+            ),
+            Some(block) => self.quote_string(&block.to_string()),
+        }
+    }
+}
+
+/// Turns a database title into something usable as a table name.
+pub fn table_name(title: &str) -> String {
+    let name = title
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    let name = name
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("_");
+
+    if name.is_empty() {
+        "notion".to_string()
+    } else {
+        name
+    }
+}
+
+/// The names of the key column and of `columns`' columns in the table. MySQL
+/// and SQLite don't tell identifiers apart by case, so neither is relied on:
+/// the key is `notion_id` if a column is already called `id`, and later
+/// columns with the same name as an earlier one get a number added.
+pub(crate) fn column_names(columns: &[&Column]) -> Vec<String> {
+    let mut taken = Vec::<String>::new();
+    let mut unique = |name: &str| {
+        let mut candidate = name.to_string();
+        let mut i = 2;
+        while taken.contains(&candidate.to_lowercase()) {
+            candidate = format!("{} ({})", name, i);
+            i += 1;
+        }
+        taken.push(candidate.to_lowercase());
+        candidate
+    };
+
+    let id_taken = columns
+        .iter()
+        .any(|column| column.name.eq_ignore_ascii_case("id"));
+    let names = columns
+        .iter()
+        .map(|column| unique(&column.name))
+        .collect::<Vec<String>>();
+    let key = unique(if id_taken { "notion_id" } else { "id" });
+    std::iter::once(key).chain(names).collect()
+}
+
+/// Writes a `CREATE TABLE` statement for `columns`, followed by `INSERT`s of
+/// `batch_size` rows at a time. Each row's page id becomes the primary key.
+pub fn write_sql(
+    writer: &mut impl Write,
+    dialect: Dialect,
+    table: &str,
+    columns: &[&Column],
+    rows: &[Row],
+    batch_size: usize,
+) -> io::Result<()> {
+    let table = dialect.quote_identifier(table);
+    let id_type = match dialect {
+        Dialect::Mysql => "VARCHAR(36)",
+        Dialect::Postgres | Dialect::Sqlite => "TEXT",
+    };
+
+    let names = column_names(columns)
+        .iter()
+        .map(|name| dialect.quote_identifier(name))
+        .collect::<Vec<String>>();
+
+    writeln!(writer, "CREATE TABLE {} (", table)?;
+    write!(writer, "    {} {} PRIMARY KEY", names[0], id_type)?;
+    for (column, name) in columns.iter().zip(names.iter().skip(1)) {
+        write!(writer, ",\n    {} {}", name, dialect.column_type(column))?;
+    }
+    writeln!(writer, "\n);")?;
+
+    let column_names = names.join(", ");
+
+    for batch in rows.chunks(batch_size.max(1)) {
+        writeln!(writer, "\nINSERT INTO {} ({}) VALUES", table, column_names)?;
+        let values = batch
+            .iter()
+            .map(|row| {
+                let cells = std::iter::once(dialect.quote_string(&row.id))
+                    .chain(columns.iter().map(|column| {
+                        // Last edited times aren't kept with the row's other
+                        // properties, but on the row itself.
+                        if column.column_type == "last_edited_time" {
+                            dialect.timestamp(&row.last_edited_time)
+                        } else {
+                            dialect.literal(row.cell(column))
+                        }
+                    }))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                format!("    ({})", cells)
+            })
+            .collect::<Vec<String>>()
+            .join(",\n");
+        writeln!(writer, "{};", values)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::database::fixtures::{column, row, text};
+
+    fn cell(value: serde_json::Value) -> Cell {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn identifiers_and_strings_are_quoted_per_dialect() {
+        assert_eq!(
+            Dialect::Postgres.quote_identifier(r#"Say "hi""#),
+            r#""Say ""hi""""#
+        );
+        assert_eq!(Dialect::Mysql.quote_identifier("a`b"), "`a``b`");
+        assert_eq!(
+            Dialect::Sqlite.quote_string(r"O'Brien \n"),
+            r"'O''Brien \n'"
+        );
+        assert_eq!(
+            Dialect::Mysql.quote_string(r"O'Brien \n"),
+            r"'O''Brien \\n'"
+        );
+    }
+
+    #[test]
+    fn lists_become_each_dialects_arrays() {
+        let tags = cell(json!({ "id": "a", "type": "multi_select", "multi_select": [
+            { "id": "a", "name": "it's", "color": "red" },
+            { "id": "b", "name": "b", "color": "blue" },
+        ] }));
+        assert_eq!(
+            Dialect::Postgres.literal(Some(&tags)),
+            "ARRAY['it''s', 'b']::TEXT[]"
+        );
+        assert_eq!(
+            Dialect::Mysql.literal(Some(&tags)),
+            "JSON_ARRAY('it''s', 'b')"
+        );
+        assert_eq!(Dialect::Sqlite.literal(Some(&tags)), r#"'["it''s","b"]'"#);
+    }
+
+    #[test]
+    fn timestamps_and_checkboxes_suit_each_dialect() {
+        let created = cell(json!({ "id": "a", "type": "created_time",
+            "created_time": "2024-01-02T03:04:00.000Z" }));
+        assert_eq!(
+            Dialect::Postgres.literal(Some(&created)),
+            "'2024-01-02T03:04:00.000Z'"
+        );
+        assert_eq!(
+            Dialect::Mysql.literal(Some(&created)),
+            "'2024-01-02 03:04:00.000'"
+        );
+        let due = cell(json!({ "id": "a", "type": "date",
+            "date": { "start": "2024-03-05T09:00:00.000+10:00" } }));
+        assert_eq!(
+            Dialect::Mysql.literal(Some(&due)),
+            "'2024-03-04 23:00:00.000'"
+        );
+
+        let checked = cell(json!({ "id": "a", "type": "checkbox", "checkbox": true }));
+        assert_eq!(Dialect::Postgres.literal(Some(&checked)), "TRUE");
+        assert_eq!(Dialect::Sqlite.literal(Some(&checked)), "1");
+        assert_eq!(Dialect::Sqlite.literal(None), "NULL");
+    }
+
+    #[test]
+    fn numbers_are_written_as_entered() {
+        for number in [0.5, 0.1, 16777217.0, 1234567.89] {
+            let number_cell = cell(json!({ "id": "a", "type": "number", "number": number }));
+            assert_eq!(
+                Dialect::Postgres.literal(Some(&number_cell)),
+                number.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn numbers_that_arent_finite_are_null() {
+        let not_a_number = Cell {
+            id: "a".to_string(),
+            cell_type: "number".to_string(),
+            block: Some(Blocks::Number(f32::NAN)),
+            raw: json!({ "id": "a", "type": "number", "number": null }),
+        };
+        assert_eq!(Dialect::Postgres.literal(Some(&not_a_number)), "NULL");
+    }
+
+    #[test]
+    fn text_is_written_on_one_line() {
+        let name =
+            cell(json!({ "id": "a", "type": "title", "title": text(&["Ada ", "Lovelace"]) }));
+        assert_eq!(Dialect::Sqlite.literal(Some(&name)), "'Ada Lovelace'");
+        let notes = cell(json!({ "id": "b", "type": "rich_text",
+            "rich_text": text(&["It's ", "here"]) }));
+        assert_eq!(Dialect::Sqlite.literal(Some(&notes)), "'It''s here'");
+    }
+
+    #[test]
+    fn creators_are_written_as_their_ids() {
+        let creator = cell(json!({ "id": "a", "type": "created_by",
+            "created_by": { "object": "user", "id": "u1", "name": "Ada" } }));
+        assert_eq!(Dialect::Mysql.literal(Some(&creator)), "'u1'");
+    }
+
+    #[test]
+    fn columns_named_like_the_key_dont_clash_with_it() {
+        let (id, other_id, edited) = (
+            column("ID", "ID", "rich_text"),
+            column("id", "id", "number"),
+            column("Edited", "Edited", "last_edited_time"),
+        );
+        let mut row = row(json!({
+            "ID": { "id": "ID", "type": "rich_text", "rich_text": [] },
+            "id": { "id": "id", "type": "number", "number": 7 },
+            "Edited": { "id": "Edited", "type": "last_edited_time",
+                "last_edited_time": "2024-01-03T04:05:00.000Z" },
+        }));
+        row.last_edited_time = "2024-01-03T04:05:00.000Z".to_string();
+
+        let mut out = Vec::new();
+        write_sql(
+            &mut out,
+            Dialect::Mysql,
+            "people",
+            &[&id, &other_id, &edited],
+            &[row],
+            100,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "CREATE TABLE `people` (\n    `notion_id` VARCHAR(36) PRIMARY KEY,\n    `ID` TEXT,\n    `id (2)` DOUBLE,\n    `Edited` DATETIME(3)\n);\n\nINSERT INTO `people` (`notion_id`, `ID`, `id (2)`, `Edited`) VALUES\n    ('p1', '', 7, '2024-01-03 04:05:00.000');\n"
+        );
+    }
+
+    #[test]
+    fn table_names_are_made_safe() {
+        assert_eq!(table_name("  Team Q&A — 2024 "), "team_q_a_2024");
+        assert_eq!(table_name("!!!"), "notion");
+    }
+}
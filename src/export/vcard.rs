@@ -0,0 +1,306 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    models::database::{find_column, Column, Row},
+    render::unique_path,
+};
+
+use super::{
+    ics::{escape, write_line},
+    markdown::safe_file_name,
+};
+
+/// Which columns each vCard field is filled from, as read from a JSON file
+/// such as `{"ORG": "Company", "TEL": ["Mobile", "Work phone"]}`. Fields left
+/// out fall back to the defaults described on each.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VcardMapping {
+    /// The title column by default.
+    #[serde(rename = "FN")]
+    pub name: Option<String>,
+    /// Every `email` column by default.
+    #[serde(rename = "EMAIL")]
+    pub email: Option<Vec<String>>,
+    /// Every `phone_number` column by default.
+    #[serde(rename = "TEL")]
+    pub tel: Option<Vec<String>>,
+    #[serde(rename = "ORG")]
+    pub org: Option<String>,
+    /// Several columns are written as `Column: value` lines.
+    #[serde(rename = "NOTE")]
+    pub note: Option<Vec<String>>,
+    /// Usually `multi_select` columns, whose options each become a category.
+    #[serde(rename = "CATEGORIES")]
+    pub categories: Option<Vec<String>>,
+}
+
+/// A `VcardMapping` with its column names looked up.
+pub struct VcardColumns<'a> {
+    name: Option<&'a Column>,
+    email: Vec<&'a Column>,
+    tel: Vec<&'a Column>,
+    org: Option<&'a Column>,
+    note: Vec<&'a Column>,
+    categories: Vec<&'a Column>,
+}
+
+impl VcardMapping {
+    pub fn load(path: &Path) -> Result<VcardMapping, Box<dyn Error>> {
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+
+    pub fn columns<'a>(&self, columns: &'a [Column]) -> Result<VcardColumns<'a>, String> {
+        let find = |name: &String| {
+            find_column(columns, name)
+                .ok_or_else(|| format!("The column '{}' does not exist.", name))
+        };
+        // Without names, every column of the default type (if there is one).
+        let find_all = |names: &Option<Vec<String>>, default_type: Option<&str>| match names {
+            Some(names) => names.iter().map(find).collect::<Result<Vec<&Column>, _>>(),
+            None => Ok(columns
+                .iter()
+                .filter(|column| Some(column.column_type.as_str()) == default_type)
+                .collect()),
+        };
+        Ok(VcardColumns {
+            name: match &self.name {
+                Some(name) => Some(find(name)?),
+                None => columns.iter().find(|column| column.column_type == "title"),
+            },
+            email: find_all(&self.email, Some("email"))?,
+            tel: find_all(&self.tel, Some("phone_number"))?,
+            org: self.org.as_ref().map(find).transpose()?,
+            note: find_all(&self.note, None)?,
+            categories: find_all(&self.categories, None)?,
+        })
+    }
+}
+
+/// Writes a row's contact card.
+pub fn write_vcard(writer: &mut impl Write, row: &Row, columns: &VcardColumns) -> io::Result<()> {
+    let values = |columns: &[&Column]| {
+        columns
+            .iter()
+            .filter_map(|column| row.block(column))
+            .flat_map(|block| block.items())
+            .filter(|value| !value.trim().is_empty())
+            .collect::<Vec<String>>()
+    };
+    // FN is the only field a card has to have.
+    let name = columns
+        .name
+        .and_then(|column| row.block(column))
+        .map(|block| block.plain_text())
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| "Unnamed".to_string());
+
+    write_line(writer, "BEGIN:VCARD")?;
+    write_line(writer, "VERSION:4.0")?;
+    write_line(writer, &format!("UID:urn:uuid:{}", row.id))?;
+    write_line(writer, &format!("FN:{}", escape(&name)))?;
+    for email in values(&columns.email) {
+        write_line(writer, &format!("EMAIL:{}", escape(&email)))?;
+    }
+    for tel in values(&columns.tel) {
+        write_line(writer, &format!("TEL;VALUE=text:{}", escape(&tel)))?;
+    }
+    if let Some(org) = values(columns.org.as_slice()).first() {
+        write_line(writer, &format!("ORG:{}", escape(org)))?;
+    }
+    let note = match columns.note.as_slice() {
+        [column] => values(&[column]).join(", "),
+        note => note
+            .iter()
+            .filter_map(|column| {
+                let value = values(&[column]).join(", ");
+                (!value.is_empty()).then(|| format!("{}: {}", column.name, value))
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+    };
+    if !note.is_empty() {
+        write_line(writer, &format!("NOTE:{}", escape(&note)))?;
+    }
+    let categories = values(&columns.categories);
+    if !categories.is_empty() {
+        let categories = categories
+            .iter()
+            .map(|category| escape(category))
+            .collect::<Vec<String>>()
+            .join(",");
+        write_line(writer, &format!("CATEGORIES:{}", categories))?;
+    }
+    write_line(writer, &format!("URL:{}", row.url))?;
+    write_line(writer, "END:VCARD")
+}
+
+/// Writes every row's card to a single `.vcf` file.
+pub fn write_vcards(
+    writer: &mut impl Write,
+    rows: &[Row],
+    columns: &VcardColumns,
+) -> io::Result<()> {
+    for row in rows.iter() {
+        write_vcard(writer, row, columns)?;
+    }
+    Ok(())
+}
+
+/// Writes each row's card to its own `.vcf` file in `dir`, named after the
+/// contact. Returns the paths written.
+pub fn write_vcard_files(
+    dir: &Path,
+    rows: &[Row],
+    columns: &VcardColumns,
+) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for row in rows.iter() {
+        let name = columns
+            .name
+            .and_then(|column| row.block(column))
+            .and_then(|block| safe_file_name(&block.plain_text()))
+            .unwrap_or_else(|| row.id.clone());
+        let path = unique_path(dir.join(format!("{}.vcf", name)), &written);
+        let mut file = io::BufWriter::new(fs::File::create(&path)?);
+        write_vcard(&mut file, row, columns)?;
+        file.flush()?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::database::fixtures::{column, row, text};
+
+    fn columns() -> Vec<Column> {
+        vec![
+            column("n", "Name", "title"),
+            column("e", "Email", "email"),
+            column("t", "Phone", "phone_number"),
+            column("c", "Company", "rich_text"),
+            column("g", "Tags", "multi_select"),
+            column("o", "Notes", "rich_text"),
+        ]
+    }
+
+    fn contact(name: &str, notes: &str) -> Row {
+        row(json!({
+            "Name": { "id": "n", "type": "title", "title": text(&[name]) },
+            "Email": { "id": "e", "type": "email", "email": "ada@example.com" },
+            "Phone": { "id": "t", "type": "phone_number", "phone_number": "+44 20 7946 0000" },
+            "Company": { "id": "c", "type": "rich_text",
+                "rich_text": text(&["Babbage; Lovelace, Ltd"]) },
+            "Tags": { "id": "g", "type": "multi_select", "multi_select": [
+                { "id": "1", "name": "maths", "color": "red" },
+                { "id": "2", "name": "a,b", "color": "blue" },
+            ] },
+            "Notes": { "id": "o", "type": "rich_text", "rich_text": text(&[notes]) },
+        }))
+    }
+
+    fn card(row: &Row, mapping: &str) -> String {
+        let columns = columns();
+        let mapping: VcardMapping = serde_json::from_str(mapping).unwrap();
+        let mut out = Vec::new();
+        write_vcard(&mut out, row, &mapping.columns(&columns).unwrap()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn names_emails_and_phones_are_found_by_type() {
+        assert_eq!(
+            card(&contact("Ada Lovelace", ""), "{}"),
+            "BEGIN:VCARD\r\nVERSION:4.0\r\nUID:urn:uuid:p1\r\nFN:Ada Lovelace\r\nEMAIL:ada@example.com\r\nTEL;VALUE=text:+44 20 7946 0000\r\nURL:https://www.notion.so/p1\r\nEND:VCARD\r\n"
+        );
+    }
+
+    #[test]
+    fn mapped_values_are_escaped() {
+        let card = card(
+            &contact("Ada", r"C:\ drive"),
+            r#"{"ORG": "Company", "NOTE": ["Company", "Notes"], "CATEGORIES": ["Tags"]}"#,
+        );
+        assert!(
+            card.contains("\r\nORG:Babbage\\; Lovelace\\, Ltd\r\n"),
+            "{}",
+            card
+        );
+        assert!(
+            card.contains("\r\nNOTE:Company: Babbage\\; Lovelace\\, Ltd\\nNotes: C:\\\\ drive\r\n"),
+            "{}",
+            card
+        );
+        assert!(card.contains("\r\nCATEGORIES:maths,a\\,b\r\n"), "{}", card);
+    }
+
+    #[test]
+    fn long_values_are_folded() {
+        let notes = "X".repeat(100);
+        let card = card(&contact("Ada", &notes), r#"{"NOTE": ["Notes"]}"#);
+        for line in card.split("\r\n") {
+            assert!(line.len() <= 75, "{}", line);
+        }
+        assert!(
+            card.replace("\r\n ", "")
+                .contains(&format!("\r\nNOTE:{}\r\n", notes)),
+            "{}",
+            card
+        );
+    }
+
+    #[test]
+    fn mappings_only_name_columns_and_fields_that_exist() {
+        let columns = columns();
+        let mapping: VcardMapping = serde_json::from_str(r#"{"ORG": "Employer"}"#).unwrap();
+        assert_eq!(
+            mapping.columns(&columns).err().as_deref(),
+            Some("The column 'Employer' does not exist.")
+        );
+        assert!(serde_json::from_str::<VcardMapping>(r#"{"ADR": "Address"}"#).is_err());
+    }
+
+    #[test]
+    fn each_card_is_named_after_its_contact() {
+        let rows = ["Ada Lovelace", "Ada Lovelace", "Q1/Q2", " "]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let mut row = contact(name, "");
+                row.id = format!("p{}", i);
+                row
+            })
+            .collect::<Vec<Row>>();
+        let columns = columns();
+        let columns = VcardMapping::default().columns(&columns).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("margaret-vcards-{}", std::process::id()));
+        let written = write_vcard_files(&dir, &rows, &columns).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            written
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+                .collect::<Vec<String>>(),
+            [
+                "Ada Lovelace.vcf",
+                "Ada Lovelace (2).vcf",
+                "Q1_Q2.vcf",
+                "p3.vcf"
+            ]
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashSet;
+
+use chrono::DateTime;
+use rust_xlsxwriter::{ColNum, Format, Note, RowNum, Url, Workbook, Worksheet, XlsxError};
+
+use crate::models::{
+    blocks::Blocks,
+    database::{Cell, Column, Row},
+};
+
+/// One database's worth of rows, written to its own worksheet.
+pub struct Sheet<'a> {
+    pub name: String,
+    pub columns: Vec<&'a Column>,
+    pub rows: &'a [Row],
+}
+
+struct Formats {
+    header: Format,
+    date: Format,
+    datetime: Format,
+    wrapped: Format,
+}
+
+impl Formats {
+    fn new() -> Formats {
+        Formats {
+            header: Format::new().set_bold(),
+            date: Format::new().set_num_format("yyyy-mm-dd"),
+            datetime: Format::new().set_num_format("yyyy-mm-dd hh:mm"),
+            wrapped: Format::new().set_text_wrap(),
+        }
+    }
+}
+
+/// Excel limits sheet names to 31 characters, some of which are off limits,
+/// and they have to be unique within a workbook.
+fn sheet_name(name: &str, taken: &mut HashSet<String>) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let name = name.trim_matches('\'').trim();
+    let name = if name.is_empty() { "Sheet" } else { name };
+
+    let mut candidate = name.chars().take(31).collect::<String>();
+    let mut i = 2;
+    while !taken.insert(candidate.to_lowercase()) {
+        let suffix = format!(" ({})", i);
+        let truncated = name.chars().take(31 - suffix.len()).collect::<String>();
+        candidate = truncated.trim_end().to_string() + &suffix;
+        i += 1;
+    }
+    candidate
+}
+
+/// Excel's dates have no time zone, so every time is written as it reads in
+/// the offset Notion gave it in: UTC for created and edited times, and the
+/// offset it was entered in for dates.
+fn write_timestamp(
+    worksheet: &mut Worksheet,
+    row: RowNum,
+    col: ColNum,
+    value: &str,
+    formats: &Formats,
+) -> Result<(), XlsxError> {
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(timestamp) => {
+            worksheet.write_datetime_with_format(
+                row,
+                col,
+                timestamp.naive_local(),
+                &formats.datetime,
+            )?;
+        }
+        Err(_) => {
+            worksheet.write_string(row, col, value)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_cell(
+    worksheet: &mut Worksheet,
+    row: RowNum,
+    col: ColNum,
+    cell: &Cell,
+    formats: &Formats,
+) -> Result<(), XlsxError> {
+    let Some(block) = &cell.block else {
+        return Ok(());
+    };
+    match block {
+        // From Notion's own value, which the f32 in `Blocks::Number` can't
+        // always hold exactly.
+        Blocks::Number(value) => {
+            let number = cell.raw["number"].as_f64().unwrap_or(*value as f64);
+            worksheet.write_number(row, col, number)?;
+        }
+        Blocks::Checkbox(value) => {
+            worksheet.write_boolean(row, col, *value)?;
+        }
+        Blocks::CreatedTime(value) => write_timestamp(worksheet, row, col, value, formats)?,
+        // A range's end goes in a note, since a cell can only hold one date.
+        Blocks::Date(Some(date)) => match date.start_time() {
+            Some(start) => {
+                let format = if date.is_all_day() {
+                    &formats.date
+                } else {
+                    &formats.datetime
+                };
+                worksheet.write_datetime_with_format(row, col, start.naive_local(), format)?;
+                if let Some(end) = &date.end {
+                    worksheet.insert_note(row, col, &Note::new(format!("Ends {}", end)))?;
+                }
+            }
+            None => {
+                worksheet.write_string(row, col, block.to_string())?;
+            }
+        },
+        Blocks::Url(value) if !value.is_empty() => {
+            write_link(worksheet, row, col, Url::new(value), value)?;
+        }
+        Blocks::Email(value) if !value.is_empty() => {
+            let url = Url::new(format!("mailto:{}", value)).set_text(value);
+            write_link(worksheet, row, col, url, value)?;
+        }
+        Blocks::PhoneNumber(Some(value)) if !value.trim().is_empty() => {
+            let number = value
+                .chars()
+                .filter(|c| c.is_ascii_digit() || *c == '+')
+                .collect::<String>();
+            let url = Url::new(format!("tel:{}", number)).set_text(value);
+            write_link(worksheet, row, col, url, value)?;
+        }
+        block => {
+            let value = block.to_string();
+            if value.contains('\n') {
+                worksheet.write_string_with_format(row, col, value, &formats.wrapped)?;
+            } else {
+                worksheet.write_string(row, col, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a link, or just its text if Excel won't take it as one, e.g. a URL
+/// without a scheme or one longer than Excel allows.
+fn write_link(
+    worksheet: &mut Worksheet,
+    row: RowNum,
+    col: ColNum,
+    url: Url,
+    text: &str,
+) -> Result<(), XlsxError> {
+    if worksheet.write_url(row, col, url).is_err() {
+        worksheet.write_string(row, col, text)?;
+    }
+    Ok(())
+}
+
+fn write_sheet(
+    worksheet: &mut Worksheet,
+    sheet: &Sheet,
+    formats: &Formats,
+) -> Result<(), XlsxError> {
+    for (col, column) in sheet.columns.iter().enumerate() {
+        worksheet.write_string_with_format(0, col as ColNum, &column.name, &formats.header)?;
+    }
+    worksheet.set_freeze_panes(1, 0)?;
+
+    for (i, row) in sheet.rows.iter().enumerate() {
+        for (col, column) in sheet.columns.iter().enumerate() {
+            let (i, col) = (i as RowNum + 1, col as ColNum);
+            // Last edited times aren't kept with the row's other properties,
+            // but on the row itself.
+            if column.column_type == "last_edited_time" {
+                write_timestamp(worksheet, i, col, &row.last_edited_time, formats)?;
+            } else if let Some(cell) = row.cell(column) {
+                write_cell(worksheet, i, col, cell, formats)?;
+            }
+        }
+    }
+    worksheet.autofit();
+    Ok(())
+}
+
+/// Builds a workbook with one worksheet per database, returning its bytes.
+pub fn write_xlsx(sheets: &[Sheet]) -> Result<Vec<u8>, XlsxError> {
+    let formats = Formats::new();
+    let mut workbook = Workbook::new();
+    let mut taken = HashSet::new();
+
+    for sheet in sheets.iter() {
+        let worksheet = workbook.add_worksheet();
+        worksheet.set_name(sheet_name(&sheet.name, &mut taken))?;
+        write_sheet(worksheet, sheet, &formats)?;
+    }
+    workbook.save_to_buffer()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::models::database::fixtures::{column, row};
+
+    /// Writes `properties` down the first column of a worksheet, returning the
+    /// worksheet's XML and its shared strings, if it has any.
+    fn written(properties: &[Value]) -> (String, String) {
+        let formats = Formats::new();
+        let mut worksheet = Worksheet::new();
+        for (row, property) in properties.iter().enumerate() {
+            let cell: Cell = serde_json::from_value(property.clone()).unwrap();
+            write_cell(&mut worksheet, row as RowNum, 0, &cell, &formats).unwrap();
+        }
+        let mut workbook = Workbook::new();
+        workbook.push_worksheet(worksheet);
+        unzipped(workbook.save_to_buffer().unwrap())
+    }
+
+    /// The first worksheet's XML and the shared strings in a workbook.
+    fn unzipped(workbook: Vec<u8>) -> (String, String) {
+        let mut archive = zip::ZipArchive::new(Cursor::new(workbook)).unwrap();
+        let mut read = |name: &str| {
+            let mut text = String::new();
+            if let Ok(mut file) = archive.by_name(name) {
+                file.read_to_string(&mut text).unwrap();
+            }
+            text
+        };
+        (
+            read("xl/worksheets/sheet1.xml"),
+            read("xl/sharedStrings.xml"),
+        )
+    }
+
+    #[test]
+    fn links_excel_wont_take_are_written_as_text() {
+        let long = format!("{}@example.com", "a".repeat(3000));
+        let (sheet, strings) = written(&[
+            json!({ "id": "a", "type": "url", "url": "example.com" }),
+            json!({ "id": "a", "type": "url", "url": "https://example.com" }),
+            json!({ "id": "a", "type": "email", "email": long }),
+        ]);
+        // Every cell holds its text as a string, but only the valid URL is a link.
+        for cell in ["A1", "A2", "A3"] {
+            let start = sheet.find(&format!(r#"<c r="{}""#, cell)).unwrap();
+            let tag = &sheet[start..start + sheet[start..].find('>').unwrap()];
+            assert!(tag.contains(r#"t="s""#), "{}", tag);
+        }
+        assert!(sheet.contains(r#"<hyperlink ref="A2""#), "{}", sheet);
+        assert!(!sheet.contains(r#"<hyperlink ref="A1""#), "{}", sheet);
+        assert!(!sheet.contains(r#"<hyperlink ref="A3""#), "{}", sheet);
+        for text in ["example.com", "https://example.com", long.as_str()] {
+            assert!(strings.contains(&format!("<t>{}</t>", text)), "{}", strings);
+        }
+    }
+
+    #[test]
+    fn numbers_are_written_as_entered() {
+        let numbers = [0.1, 1234.5, 16777217.0, 1234567.89];
+        let (sheet, _) = written(
+            &numbers.map(|number| json!({ "id": "a", "type": "number", "number": number })),
+        );
+        for (cell, number) in ["A1", "A2", "A3", "A4"].iter().zip(numbers) {
+            let expected = format!(r#"<c r="{}"><v>{}</v></c>"#, cell, number);
+            assert!(sheet.contains(&expected), "{}", sheet);
+        }
+    }
+
+    #[test]
+    fn times_are_written_in_the_offset_notion_gives() {
+        let edited = column("e1", "Edited", "last_edited_time");
+        let when = column("w1", "When", "date");
+        let mut row = row(json!({
+            "Edited": { "id": "e1", "type": "last_edited_time",
+                "last_edited_time": "2024-01-03T06:00:00.000Z" },
+            "When": { "id": "w1", "type": "date",
+                "date": { "start": "2024-01-02T09:00:00.000+10:00", "end": null } },
+        }));
+        row.last_edited_time = "2024-01-03T06:00:00.000Z".to_string();
+
+        let rows = [row];
+        let (sheet, _) = unzipped(
+            write_xlsx(&[Sheet {
+                name: "Times".to_string(),
+                columns: vec![&edited, &when],
+                rows: &rows,
+            }])
+            .unwrap(),
+        );
+        // Days since 1899-12-30, with a quarter of a day for 06:00.
+        assert!(sheet.contains("<v>45294.25</v>"), "{}", sheet);
+        assert!(sheet.contains("<v>45293.375</v>"), "{}", sheet);
+    }
+
+    #[test]
+    fn sheet_names_are_cleaned_up_and_unique() {
+        let mut taken = HashSet::new();
+        assert_eq!(sheet_name("Q1/Q2: plans", &mut taken), "Q1_Q2_ plans");
+        assert_eq!(sheet_name("q1/q2: PLANS", &mut taken), "q1_q2_ PLANS (2)");
+        let long = "A database with a rather long name";
+        assert_eq!(
+            sheet_name(long, &mut taken),
+            "A database with a rather long n"
+        );
+        assert_eq!(
+            sheet_name(long, &mut taken),
+            "A database with a rather lo (2)"
+        );
+    }
+}
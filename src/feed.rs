@@ -0,0 +1,225 @@
+use std::io::{self, Write};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use clap::ValueEnum;
+
+use crate::{
+    models::{
+        blocks::{parse_date, Blocks},
+        database::{Column, Row},
+    },
+    render::escape_html,
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum FeedFormat {
+    Atom,
+    /// RSS 2.0
+    Rss,
+}
+
+pub struct FeedOptions<'a> {
+    pub title: String,
+    /// Link to the database itself, if it has one.
+    pub link: String,
+    /// The database's id, which links to it when there's no link.
+    pub database_id: String,
+    /// Column to date items by, instead of when their rows were created.
+    pub date_column: Option<&'a Column>,
+    /// Column whose text is used as each item's summary.
+    pub summary_column: Option<&'a Column>,
+    /// Most items to include, newest first.
+    pub limit: usize,
+}
+
+struct Entry {
+    id: String,
+    title: String,
+    link: String,
+    published: DateTime<FixedOffset>,
+    updated: DateTime<FixedOffset>,
+    summary: Option<String>,
+}
+
+fn entries(rows: &[Row], options: &FeedOptions) -> Vec<Entry> {
+    let mut entries = rows
+        .iter()
+        .filter_map(|row| {
+            let published = match options.date_column.map(|column| row.block(column)) {
+                Some(Some(Blocks::Date(date))) => date.as_ref()?.start_time()?,
+                Some(block) => parse_date(block?.plain_text().trim())?,
+                None => parse_date(&row.created_time)?,
+            };
+            Some(Entry {
+                id: format!("urn:uuid:{}", row.id),
+                title: row.title().unwrap_or_else(|| "Untitled".to_string()),
+                link: row.url.clone(),
+                published,
+                updated: parse_date(&row.last_edited_time).unwrap_or(published),
+                summary: options
+                    .summary_column
+                    .and_then(|column| row.block(column))
+                    .map(|block| block.plain_text())
+                    .filter(|summary| !summary.trim().is_empty()),
+            })
+        })
+        .collect::<Vec<Entry>>();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.published));
+    entries.truncate(options.limit);
+    entries
+}
+
+/// The database's link, or failing that its Notion URL, which every
+/// database has. RSS channels have to have a link and Atom feeds an id, and
+/// this serves as both.
+fn feed_link(options: &FeedOptions) -> String {
+    if !options.link.is_empty() {
+        return options.link.clone();
+    }
+    format!(
+        "https://www.notion.so/{}",
+        options.database_id.replace('-', "")
+    )
+}
+
+fn write_atom(writer: &mut impl Write, entries: &[Entry], options: &FeedOptions) -> io::Result<()> {
+    let updated = entries
+        .iter()
+        .map(|entry| entry.updated)
+        .max()
+        .unwrap_or_else(|| Utc::now().fixed_offset());
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(writer, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#)?;
+    writeln!(writer, "  <title>{}</title>", escape_html(&options.title))?;
+    let link = escape_html(&feed_link(options));
+    writeln!(writer, "  <id>{}</id>", link)?;
+    writeln!(writer, r#"  <link href="{}"/>"#, link)?;
+    writeln!(writer, "  <updated>{}</updated>", updated.to_rfc3339())?;
+    for entry in entries.iter() {
+        writeln!(writer, "  <entry>")?;
+        writeln!(writer, "    <id>{}</id>", escape_html(&entry.id))?;
+        writeln!(writer, "    <title>{}</title>", escape_html(&entry.title))?;
+        writeln!(writer, r#"    <link href="{}"/>"#, escape_html(&entry.link))?;
+        writeln!(
+            writer,
+            "    <published>{}</published>",
+            entry.published.to_rfc3339()
+        )?;
+        writeln!(
+            writer,
+            "    <updated>{}</updated>",
+            entry.updated.to_rfc3339()
+        )?;
+        if let Some(summary) = &entry.summary {
+            writeln!(writer, "    <summary>{}</summary>", escape_html(summary))?;
+        }
+        writeln!(writer, "  </entry>")?;
+    }
+    writeln!(writer, "</feed>")
+}
+
+fn write_rss(writer: &mut impl Write, entries: &[Entry], options: &FeedOptions) -> io::Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(writer, r#"<rss version="2.0">"#)?;
+    writeln!(writer, "  <channel>")?;
+    writeln!(writer, "    <title>{}</title>", escape_html(&options.title))?;
+    writeln!(
+        writer,
+        "    <link>{}</link>",
+        escape_html(&feed_link(options))
+    )?;
+    writeln!(
+        writer,
+        "    <description>{}</description>",
+        escape_html(&options.title)
+    )?;
+    for entry in entries.iter() {
+        writeln!(writer, "    <item>")?;
+        writeln!(writer, "      <title>{}</title>", escape_html(&entry.title))?;
+        writeln!(writer, "      <link>{}</link>", escape_html(&entry.link))?;
+        writeln!(
+            writer,
+            r#"      <guid isPermaLink="false">{}</guid>"#,
+            escape_html(&entry.id)
+        )?;
+        writeln!(
+            writer,
+            "      <pubDate>{}</pubDate>",
+            entry.published.to_rfc2822()
+        )?;
+        if let Some(summary) = &entry.summary {
+            writeln!(
+                writer,
+                "      <description>{}</description>",
+                escape_html(summary)
+            )?;
+        }
+        writeln!(writer, "    </item>")?;
+    }
+    writeln!(writer, "  </channel>")?;
+    writeln!(writer, "</rss>")
+}
+
+/// Writes a feed of `rows`, newest first. Rows without a date are left out.
+pub fn write_feed(
+    writer: &mut impl Write,
+    format: FeedFormat,
+    rows: &[Row],
+    options: &FeedOptions,
+) -> io::Result<()> {
+    let entries = entries(rows, options);
+    match format {
+        FeedFormat::Atom => write_atom(writer, &entries, options),
+        FeedFormat::Rss => write_rss(writer, &entries, options),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(link: &str, database_id: &str) -> FeedOptions<'static> {
+        FeedOptions {
+            title: "Posts".to_string(),
+            link: link.to_string(),
+            database_id: database_id.to_string(),
+            date_column: None,
+            summary_column: None,
+            limit: 20,
+        }
+    }
+
+    #[test]
+    fn feeds_without_a_link_link_to_their_database() {
+        let link = "https://example.com/posts";
+        assert_eq!(feed_link(&options(link, "db")), link);
+        assert_eq!(
+            feed_link(&options("", "6f1c0d1e-8e0a-4b2c-9a1d-3f5e7b9c1a2d")),
+            "https://www.notion.so/6f1c0d1e8e0a4b2c9a1d3f5e7b9c1a2d"
+        );
+
+        let written = |format: FeedFormat| {
+            let mut out = Vec::new();
+            write_feed(&mut out, format, &[], &options("", "db")).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let atom = written(FeedFormat::Atom);
+        assert!(
+            atom.contains("<id>https://www.notion.so/db</id>"),
+            "{}",
+            atom
+        );
+        assert!(
+            atom.contains(r#"<link href="https://www.notion.so/db"/>"#),
+            "{}",
+            atom
+        );
+        let rss = written(FeedFormat::Rss);
+        assert!(
+            rss.contains("<link>https://www.notion.so/db</link>"),
+            "{}",
+            rss
+        );
+    }
+}
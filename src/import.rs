@@ -0,0 +1,419 @@
+use core::fmt;
+use std::{collections::HashMap, error::Error, fs, io::Read, path::Path};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde_json::json;
+
+use crate::journal::JournalRun;
+use crate::models::{
+    blocks::DateBlock,
+    database::{find_column, query_notion_database, Column, DatabaseCredentials, Row},
+    pages::{create_page, PropertyValue},
+};
+use crate::update::{with_retries, RateLimiter};
+
+/// A problem with one of the file's lines.
+#[derive(Debug)]
+pub struct LineError {
+    pub line: u64,
+    pub message: String,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for LineError {}
+
+/// Maps CSV headers to column names, as read from a JSON file such as
+/// `{"Full name": "Name", "Notes": null}`. Headers mapped to `null` are
+/// skipped, and headers left out go to the column with the same name.
+pub type HeaderMapping = HashMap<String, Option<String>>;
+
+pub fn load_mapping(path: &Path) -> Result<HeaderMapping, Box<dyn Error>> {
+    serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+/// The column each header's values go in, or `None` for headers to skip.
+pub fn map_headers<'a>(
+    headers: &[String],
+    columns: &'a [Column],
+    mapping: &HeaderMapping,
+) -> Result<Vec<Option<&'a Column>>, String> {
+    let mut mapped: Vec<Option<&Column>> = Vec::new();
+    for header in headers.iter() {
+        let column = match mapping.get(header) {
+            Some(None) => None,
+            Some(Some(name)) => Some(
+                find_column(columns, name)
+                    .ok_or_else(|| format!("The column '{}' does not exist.", name))?,
+            ),
+            None => Some(
+                find_column(columns, header)
+                    .or_else(|| {
+                        columns
+                            .iter()
+                            .find(|column| column.name.to_lowercase() == header.to_lowercase())
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "The header '{}' doesn't match a column. Map it to one, or to null \
+                            to skip it, in a mapping file.",
+                            header
+                        )
+                    })?,
+            ),
+        };
+        if let Some(column) = column {
+            if mapped.contains(&Some(column)) {
+                return Err(format!(
+                    "More than one header goes in the column '{}'.",
+                    column.name
+                ));
+            }
+        }
+        mapped.push(column);
+    }
+    Ok(mapped)
+}
+
+/// Related rows' ids by their titles (lowercased), for each relation column.
+#[derive(Debug, Default)]
+pub struct RelationTitles(HashMap<String, HashMap<String, Vec<String>>>);
+
+impl RelationTitles {
+    /// Fetches the titles of every row in the databases `columns` relate to.
+    pub async fn fetch(token: &str, columns: &[&Column]) -> Result<RelationTitles, Box<dyn Error>> {
+        let mut titles = HashMap::new();
+        for column in columns.iter() {
+            let Some(relation) = &column.relation else {
+                continue;
+            };
+            let credentials = DatabaseCredentials {
+                id: relation.database_id.clone(),
+                token: token.to_string(),
+            };
+            let mut ids: HashMap<String, Vec<String>> = HashMap::new();
+            for row in query_notion_database(&credentials, None).await? {
+                if let Some(title) = row.title() {
+                    ids.entry(title.trim().to_lowercase())
+                        .or_default()
+                        .push(row.id);
+                }
+            }
+            titles.insert(column.id.clone(), ids);
+        }
+        Ok(RelationTitles(titles))
+    }
+
+    fn find(&self, column: &Column, title: &str) -> Option<&Vec<String>> {
+        self.0.get(&column.id)?.get(&title.trim().to_lowercase())
+    }
+}
+
+/// Accepts `YYYY-MM-DD` dates, RFC 3339 timestamps and `YYYY-MM-DD HH:MM`
+/// times, returning the value in the form Notion expects.
+fn date(text: &str) -> Option<String> {
+    let text = text.trim();
+    if NaiveDate::parse_from_str(text, "%Y-%m-%d").is_ok()
+        || DateTime::parse_from_rfc3339(text).is_ok()
+    {
+        return Some(text.to_string());
+    }
+    ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .map(|time| time.format("%Y-%m-%dT%H:%M:%S").to_string())
+}
+
+/// Reads a number written with `.` for decimals. Commas are only taken as
+/// thousands separators where they group digits in threes, so `1,234.5` is
+/// read but a decimal comma such as `1,5` isn't guessed at.
+fn number(text: &str) -> Option<f64> {
+    let (whole, fraction) = match text.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (text, None),
+    };
+    if fraction.is_some_and(|fraction| fraction.contains(',')) {
+        return None;
+    }
+    let digits = whole.trim_start_matches(['-', '+']);
+    let mut groups = digits.split(',');
+    let first = groups.next()?;
+    let grouped = digits.contains(',');
+    if grouped
+        && (first.is_empty()
+            || first.len() > 3
+            || groups.any(|group| group.len() != 3 || !group.chars().all(|c| c.is_ascii_digit())))
+    {
+        return None;
+    }
+    text.replace(',', "")
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+}
+
+/// Converts a cell to a property value for `column`, or `None` if it's empty.
+pub fn property_value(
+    column: &Column,
+    text: &str,
+    relations: &RelationTitles,
+) -> Result<Option<PropertyValue>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let value = match column.column_type.as_str() {
+        "title" => PropertyValue::Title(text.to_string()),
+        "rich_text" => PropertyValue::RichText(text.to_string()),
+        "email" => PropertyValue::Email(Some(text.to_string())),
+        "url" => PropertyValue::Url(Some(text.to_string())),
+        "phone_number" => PropertyValue::PhoneNumber(Some(text.to_string())),
+        "number" => {
+            let number = number(text).ok_or_else(|| {
+                format!(
+                    "'{}' isn't a number (with '.' for decimals and ',' between thousands)",
+                    text
+                )
+            })?;
+            PropertyValue::Number(Some(number))
+        }
+        "checkbox" => {
+            let checked = match text.to_lowercase().as_str() {
+                "true" | "yes" | "y" | "1" | "x" => true,
+                "false" | "no" | "n" | "0" => false,
+                _ => return Err(format!("'{}' isn't true or false", text)),
+            };
+            PropertyValue::Checkbox(checked)
+        }
+        "select" => PropertyValue::Select(Some(text.to_string())),
+        "status" => PropertyValue::Status(Some(text.to_string())),
+        "multi_select" => PropertyValue::MultiSelect(
+            text.split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+        ),
+        "date" => {
+            let (start, end) = match text.split_once('→') {
+                Some((start, end)) => (start, Some(end)),
+                None => (text, None),
+            };
+            let invalid = || {
+                format!(
+                    "'{}' isn't a date (YYYY-MM-DD, YYYY-MM-DD HH:MM or RFC 3339), \
+                    or a range of them separated by →",
+                    text
+                )
+            };
+            PropertyValue::Date(Some(DateBlock {
+                start: date(start).ok_or_else(invalid)?,
+                end: end.map(|end| date(end).ok_or_else(invalid)).transpose()?,
+                time_zone: None,
+            }))
+        }
+        "relation" => {
+            // Titles can have commas in them, so the whole cell is tried first.
+            let ids = match relations.find(column, text) {
+                Some(ids) => vec![(text, ids)],
+                None => text
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|title| !title.is_empty())
+                    .map(|title| {
+                        relations
+                            .find(column, title)
+                            .map(|ids| (title, ids))
+                            .ok_or_else(|| format!("There's no related row titled '{}'", title))
+                    })
+                    .collect::<Result<Vec<(&str, &Vec<String>)>, String>>()?,
+            };
+            let ids = ids
+                .into_iter()
+                .map(|(title, ids)| match ids.as_slice() {
+                    [id] => Ok(id.clone()),
+                    _ => Err(format!("More than one related row is titled '{}'", title)),
+                })
+                .collect::<Result<Vec<String>, String>>()?;
+            PropertyValue::Relation(ids)
+        }
+        column_type => {
+            return Err(format!(
+                "'{}' is a {} column, which can't be set",
+                column.name, column_type
+            ))
+        }
+    };
+    Ok(Some(value))
+}
+
+/// A line of the file, converted to page properties.
+pub struct ImportRow {
+    pub line: u64,
+    /// Keyed by column id.
+    pub properties: HashMap<String, PropertyValue>,
+}
+
+/// Reads and converts every line of a CSV file. Lines that can't be
+/// converted are returned as errors, every problem with a line in one.
+pub fn read_csv(
+    reader: impl Read,
+    headers: &[Option<&Column>],
+    relations: &RelationTitles,
+) -> Result<(Vec<ImportRow>, Vec<LineError>), csv::Error> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            // A line that can't be read is reported like any other problem,
+            // rather than stopping the rest from being checked.
+            Err(err) => match (err.kind(), err.position()) {
+                (
+                    csv::ErrorKind::UnequalLengths {
+                        expected_len, len, ..
+                    },
+                    Some(position),
+                ) => {
+                    errors.push(LineError {
+                        line: position.line(),
+                        message: format!(
+                            "has {} fields, but the first line has {}",
+                            len, expected_len
+                        ),
+                    });
+                    continue;
+                }
+                (csv::ErrorKind::Utf8 { .. }, Some(position)) => {
+                    errors.push(LineError {
+                        line: position.line(),
+                        message: "isn't valid UTF-8".to_string(),
+                    });
+                    continue;
+                }
+                _ => return Err(err),
+            },
+        };
+        let line = record
+            .position()
+            .map(|position| position.line())
+            .unwrap_or(0);
+        let mut properties = HashMap::new();
+        let mut problems = Vec::new();
+        for (column, text) in headers.iter().zip(record.iter()) {
+            let Some(column) = column else {
+                continue;
+            };
+            match property_value(column, text, relations) {
+                Ok(Some(value)) => {
+                    properties.insert(column.reference().to_string(), value);
+                }
+                Ok(None) => {}
+                Err(problem) => problems.push(format!("{}: {}", column.name, problem)),
+            }
+        }
+        if problems.is_empty() {
+            rows.push(ImportRow { line, properties });
+        } else {
+            errors.push(LineError {
+                line,
+                message: problems.join("; "),
+            });
+        }
+    }
+    Ok((rows, errors))
+}
+
+/// Creates a page per row, at most `per_second` a second and retrying
+/// requests Notion rate limits, recording each in the journal if there's a
+/// run. Returns how many were created along with the lines that failed.
+pub async fn create_pages(
+    credentials: &DatabaseCredentials,
+    rows: &[ImportRow],
+    per_second: u32,
+    run: Option<&JournalRun<'_>>,
+) -> (usize, Vec<LineError>) {
+    let limiter = RateLimiter::per_second(per_second);
+    let mut created = 0;
+    let mut errors = Vec::new();
+    for row in rows.iter() {
+        let properties = json!(row.properties);
+        let result = with_retries(&limiter, || async {
+            Ok(create_page(&credentials.token, &credentials.id, &properties).await?)
+        })
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|response| {
+            let page: Row = serde_json::from_str(&response.body).map_err(|err| err.to_string())?;
+            match run {
+                Some(run) => run
+                    .created(&page.id, &page.last_edited_time)
+                    .map_err(|err| {
+                        format!("Created, but couldn't record it in the journal: {}", err)
+                    }),
+                None => Ok(()),
+            }
+        });
+        match result {
+            Ok(()) => created += 1,
+            Err(message) => errors.push(LineError {
+                line: row.line,
+                message,
+            }),
+        }
+    }
+    (created, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::database::fixtures::column;
+
+    #[test]
+    fn decimal_commas_arent_guessed_at() {
+        assert_eq!(number("1,234.5"), Some(1234.5));
+        assert_eq!(number("-12,345,678"), Some(-12345678.0));
+        assert_eq!(number("0.25"), Some(0.25));
+        for ambiguous in ["1,5", "1.234,5", "12,34", ",123", "1234,567", "1,2345"] {
+            assert_eq!(number(ambiguous), None, "{}", ambiguous);
+        }
+
+        let score = column("s1", "Score", "number");
+        let relations = RelationTitles(HashMap::new());
+        assert!(property_value(&score, "1,5", &relations).is_err());
+    }
+
+    #[test]
+    fn read_csv_reports_every_bad_line() {
+        let name = column("title", "Name", "title");
+        let score = column("s1", "Score", "number");
+        let headers = [Some(&name), Some(&score)];
+        let csv = "Name,Score\nAda,3\nGrace\nLinus,lots\nKen,1,2\nDennis,4\n";
+        let relations = RelationTitles(HashMap::new());
+
+        let (rows, errors) = read_csv(csv.as_bytes(), &headers, &relations).unwrap();
+        assert_eq!(
+            rows.iter().map(|row| row.line).collect::<Vec<u64>>(),
+            [2, 6]
+        );
+        assert_eq!(
+            errors
+                .iter()
+                .map(LineError::to_string)
+                .collect::<Vec<String>>(),
+            [
+                "line 3: has 1 fields, but the first line has 2",
+                "line 4: Score: 'lots' isn't a number (with '.' for decimals and ',' between thousands)",
+                "line 5: has 3 fields, but the first line has 2",
+            ]
+        );
+    }
+}
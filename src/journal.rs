@@ -0,0 +1,360 @@
+use std::{error::Error, path::Path};
+
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use serde_json::Value;
+
+use crate::models::pages::{
+    fetch_page, restore_page, trash_page, unarchive_page, update_page_properties,
+};
+
+/// A write margaret made to a page, and so how to undo it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Properties were set; undone by setting them back.
+    Update,
+    /// Undone by unarchiving the page.
+    Archive,
+    /// Undone by restoring the page from the trash.
+    Trash,
+    /// The page was created; undone by moving it to the trash.
+    Create,
+}
+
+impl Action {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Update => "update",
+            Action::Archive => "archive",
+            Action::Trash => "trash",
+            Action::Create => "create",
+        }
+    }
+
+    fn parse(action: &str) -> Option<Action> {
+        match action {
+            "update" => Some(Action::Update),
+            "archive" => Some(Action::Archive),
+            "trash" => Some(Action::Trash),
+            "create" => Some(Action::Create),
+            _ => None,
+        }
+    }
+}
+
+/// A local record of each write made to a page, along with the page's
+/// previous property values, so a run's writes can be undone.
+pub struct Journal {
+    conn: Connection,
+}
+
+/// A run of writes, such as one `update` or `import`, recorded in the journal.
+pub struct JournalRun<'a> {
+    journal: &'a Journal,
+    pub id: String,
+}
+
+pub struct RunSummary {
+    pub id: String,
+    pub command: String,
+    pub database_id: String,
+    pub started_at: String,
+    pub changes: usize,
+    pub undone: usize,
+}
+
+/// A write recorded in the journal.
+pub struct Change {
+    pub page_id: String,
+    pub action: Action,
+    /// For updates, the properties as they were before, in the shape Notion expects.
+    pub previous: Option<Value>,
+    /// The page's `last_edited_time` just after the write, or `None` if it
+    /// isn't known whether the write went through.
+    pub edited_time: Option<String>,
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> rusqlite::Result<Journal> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS runs (
+                run_id TEXT PRIMARY KEY,
+                command TEXT NOT NULL,
+                database_id TEXT NOT NULL,
+                started_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS changes (
+                run_id TEXT NOT NULL,
+                page_id TEXT NOT NULL,
+                action TEXT NOT NULL,
+                previous TEXT,
+                edited_time TEXT,
+                recorded_at TEXT NOT NULL,
+                undone_at TEXT,
+                PRIMARY KEY (run_id, page_id)
+            );",
+        )?;
+        Ok(Journal { conn })
+    }
+
+    /// Starts recording a run, with an id based on the current time. Runs
+    /// started in the same millisecond get a number added.
+    pub fn start_run(&self, command: &str, database_id: &str) -> rusqlite::Result<JournalRun<'_>> {
+        let now = Utc::now();
+        let base = now.format("%Y%m%d-%H%M%S-%3f").to_string();
+        let mut id = base.clone();
+        let mut i = 2;
+        while self.conn.execute(
+            "INSERT OR IGNORE INTO runs (run_id, command, database_id, started_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![id, command, database_id, now.to_rfc3339()],
+        )? == 0
+        {
+            id = format!("{}-{}", base, i);
+            i += 1;
+        }
+        Ok(JournalRun { journal: self, id })
+    }
+
+    /// Every run, most recent first.
+    pub fn runs(&self) -> rusqlite::Result<Vec<RunSummary>> {
+        let mut statement = self.conn.prepare(
+            "SELECT runs.run_id, command, database_id, started_at,
+                COUNT(changes.page_id), COUNT(changes.undone_at)
+             FROM runs LEFT JOIN changes ON changes.run_id = runs.run_id
+             GROUP BY runs.run_id ORDER BY started_at DESC",
+        )?;
+        let runs = statement.query_map([], |row| {
+            Ok(RunSummary {
+                id: row.get(0)?,
+                command: row.get(1)?,
+                database_id: row.get(2)?,
+                started_at: row.get(3)?,
+                changes: row.get(4)?,
+                undone: row.get(5)?,
+            })
+        })?;
+        runs.collect()
+    }
+
+    /// A run's writes that haven't been undone, most recent first.
+    pub fn changes(&self, run_id: &str) -> rusqlite::Result<Vec<Change>> {
+        let mut statement = self.conn.prepare(
+            "SELECT page_id, action, previous, edited_time FROM changes
+             WHERE run_id = ?1 AND undone_at IS NULL ORDER BY recorded_at DESC",
+        )?;
+        let changes = statement.query_map(params![run_id], |row| {
+            let action: String = row.get(1)?;
+            let previous: Option<String> = row.get(2)?;
+            Ok(Change {
+                page_id: row.get(0)?,
+                action: Action::parse(&action).unwrap_or(Action::Update),
+                previous: previous.and_then(|previous| serde_json::from_str(&previous).ok()),
+                edited_time: row.get(3)?,
+            })
+        })?;
+        changes.collect()
+    }
+
+    pub fn has_run(&self, run_id: &str) -> rusqlite::Result<bool> {
+        self.conn
+            .query_row(
+                "SELECT COUNT(*) FROM runs WHERE run_id = ?1",
+                params![run_id],
+                |row| row.get::<_, usize>(0),
+            )
+            .map(|count| count > 0)
+    }
+
+    fn mark_undone(&self, run_id: &str, page_id: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE changes SET undone_at = ?3 WHERE run_id = ?1 AND page_id = ?2",
+            params![run_id, page_id, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+}
+
+impl JournalRun<'_> {
+    /// Records a write that's about to be made, along with the properties
+    /// it'll change as they are now. Call `written` once it's gone through.
+    /// If the run has already written to the page, the values from before
+    /// its first write are kept, since those are what undoing restores.
+    pub fn before(
+        &self,
+        page_id: &str,
+        action: Action,
+        previous: Option<&Value>,
+    ) -> rusqlite::Result<()> {
+        self.journal.conn.execute(
+            "INSERT INTO changes (run_id, page_id, action, previous, recorded_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (run_id, page_id) DO NOTHING",
+            params![
+                self.id,
+                page_id,
+                action.as_str(),
+                previous.map(|previous| previous.to_string()),
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Records that a write went through, leaving the page last edited at `edited_time`.
+    pub fn written(&self, page_id: &str, edited_time: &str) -> rusqlite::Result<()> {
+        self.journal.conn.execute(
+            "UPDATE changes SET edited_time = ?3 WHERE run_id = ?1 AND page_id = ?2",
+            params![self.id, page_id, edited_time],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets a write that failed, since there's nothing to undo, unless an
+    /// earlier write to the page in this run went through.
+    pub fn failed(&self, page_id: &str) -> rusqlite::Result<()> {
+        self.journal.conn.execute(
+            "DELETE FROM changes WHERE run_id = ?1 AND page_id = ?2 AND edited_time IS NULL",
+            params![self.id, page_id],
+        )?;
+        Ok(())
+    }
+
+    /// Whether any writes have been recorded in this run, and not forgotten
+    /// as having failed.
+    pub fn has_changes(&self) -> rusqlite::Result<bool> {
+        Ok(!self.journal.changes(&self.id)?.is_empty())
+    }
+
+    /// Records a page that's been created.
+    pub fn created(&self, page_id: &str, edited_time: &str) -> rusqlite::Result<()> {
+        self.before(page_id, Action::Create, None)?;
+        self.written(page_id, edited_time)
+    }
+}
+
+/// How undoing one write went.
+pub enum Undone {
+    Restored,
+    /// The page has been edited since, at the time given, so it was left alone.
+    Conflict(String),
+    /// It isn't known whether the write went through, so it was left alone.
+    Unknown,
+    Failed(Box<dyn Error>),
+}
+
+/// Undoes a run's writes, most recent first. Pages edited since are left
+/// alone unless `force` is set. Notion only records edit times to the
+/// minute, so edits made within a minute of the write can go unnoticed.
+pub async fn undo(
+    token: &str,
+    journal: &Journal,
+    run_id: &str,
+    force: bool,
+) -> rusqlite::Result<Vec<(String, Undone)>> {
+    let mut results = Vec::new();
+    for change in journal.changes(run_id)? {
+        let outcome = match undo_change(token, &change, force).await {
+            Ok(Undone::Restored) => {
+                journal.mark_undone(run_id, &change.page_id)?;
+                Undone::Restored
+            }
+            Ok(outcome) => outcome,
+            Err(err) => Undone::Failed(err),
+        };
+        results.push((change.page_id, outcome));
+    }
+    Ok(results)
+}
+
+async fn undo_change(token: &str, change: &Change, force: bool) -> Result<Undone, Box<dyn Error>> {
+    if !force {
+        let Some(edited_time) = &change.edited_time else {
+            return Ok(Undone::Unknown);
+        };
+        let page = fetch_page(token, &change.page_id).await?;
+        if &page.last_edited_time != edited_time {
+            return Ok(Undone::Conflict(page.last_edited_time));
+        }
+    }
+    match change.action {
+        Action::Update => {
+            let previous = change
+                .previous
+                .as_ref()
+                .ok_or("The journal has no previous values for this page")?;
+            update_page_properties(token, &change.page_id, previous).await?;
+        }
+        Action::Archive => {
+            unarchive_page(token, &change.page_id).await?;
+        }
+        Action::Trash => {
+            restore_page(token, &change.page_id).await?;
+        }
+        Action::Create => {
+            trash_page(token, &change.page_id).await?;
+        }
+    }
+    Ok(Undone::Restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn runs_started_together_get_their_own_ids() {
+        let journal = Journal::open(Path::new(":memory:")).unwrap();
+        let ids = (0..3)
+            .map(|_| journal.start_run("update", "d1").unwrap().id)
+            .collect::<Vec<String>>();
+        assert_ne!(ids[0], ids[1]);
+        assert_ne!(ids[1], ids[2]);
+        assert_ne!(ids[0], ids[2]);
+        assert_eq!(journal.runs().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn a_pages_first_previous_values_are_kept() {
+        let journal = Journal::open(Path::new(":memory:")).unwrap();
+        let run = journal.start_run("update", "d1").unwrap();
+        let (first, second) = (
+            json!({ "Score": { "number": 1 } }),
+            json!({ "Score": { "number": 2 } }),
+        );
+
+        run.before("p1", Action::Update, Some(&first)).unwrap();
+        run.written("p1", "2024-01-02T03:04:00.000Z").unwrap();
+        run.before("p1", Action::Update, Some(&second)).unwrap();
+        run.written("p1", "2024-01-02T03:05:00.000Z").unwrap();
+
+        let changes = journal.changes(&run.id).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].previous, Some(first));
+        assert_eq!(
+            changes[0].edited_time.as_deref(),
+            Some("2024-01-02T03:05:00.000Z")
+        );
+    }
+
+    #[test]
+    fn failed_writes_are_forgotten_unless_an_earlier_one_went_through() {
+        let journal = Journal::open(Path::new(":memory:")).unwrap();
+        let run = journal.start_run("update", "d1").unwrap();
+        let previous = json!({ "Score": { "number": 1 } });
+
+        run.before("p1", Action::Update, Some(&previous)).unwrap();
+        run.failed("p1").unwrap();
+        assert!(!run.has_changes().unwrap());
+
+        run.before("p2", Action::Update, Some(&previous)).unwrap();
+        run.written("p2", "2024-01-02T03:04:00.000Z").unwrap();
+        run.before("p2", Action::Update, Some(&previous)).unwrap();
+        run.failed("p2").unwrap();
+        assert!(run.has_changes().unwrap());
+        assert_eq!(journal.changes(&run.id).unwrap().len(), 1);
+    }
+}
@@ -9,7 +9,18 @@ use models::{
 use reqwest::Client;
 use serde_json::Value;
 
+pub mod export;
+pub mod feed;
+pub mod import;
+pub mod journal;
+pub mod merge;
 pub mod models;
+pub mod render;
+pub mod schema;
+pub mod site;
+pub mod sync;
+pub mod template;
+pub mod update;
 
 pub fn get_db_columns(db: &str) -> Result<Option<Vec<Column>>, Box<dyn Error>> {
     let body: Value = serde_json::from_str(db)?;
@@ -30,11 +41,40 @@ pub fn get_db_columns(db: &str) -> Result<Option<Vec<Column>>, Box<dyn Error>> {
                 relation: property
                     .get("relation")
                     .map(|v| serde_json::from_value(v.to_owned()).unwrap()),
+                options: property
+                    .get(property.get("type").unwrap().as_str().unwrap())
+                    .and_then(|config| config.get("options"))
+                    .and_then(|options| serde_json::from_value(options.to_owned()).ok())
+                    .unwrap_or_default(),
+                aliases: Vec::new(),
             })
             .collect(),
     ))
 }
 
+pub fn get_db_title(db: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let body: Value = serde_json::from_str(db)?;
+    let title = body
+        .get("title")
+        .and_then(|title| title.as_array())
+        .map(|texts| {
+            texts
+                .iter()
+                .filter_map(|text| text.get("plain_text").and_then(|text| text.as_str()))
+                .collect::<String>()
+        })
+        .filter(|title| !title.is_empty());
+    Ok(title)
+}
+
+pub fn get_db_url(db: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let body: Value = serde_json::from_str(db)?;
+    Ok(body
+        .get("url")
+        .and_then(|url| url.as_str())
+        .map(|url| url.to_string()))
+}
+
 pub async fn query_column_values(
     credentials: &DatabaseCredentials,
     columns: &Vec<&Column>,
@@ -62,22 +102,12 @@ pub async fn query_column_values(
     let blocks: Vec<HashMap<String, Blocks>> = rows
         .iter()
         .map(|row: &Row| {
-            let properties = row.properties.as_ref().unwrap();
-            let mut columns_and_values = HashMap::new();
-            columns.iter().for_each(|column| {
-                let cell = properties.get(&column.name).unwrap();
-                columns_and_values.insert(column.name.clone(), cell.block
-                    .as_ref()
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "Failed to get block in column '{}' of type '{}' - do I know how to handle that type?",
-                            column.name,
-                            column.column_type
-                        )
-                    })
-                    .clone());
-            });
-            columns_and_values
+            // Cells are found by column id, so renamed columns are still found.
+            // Cells of types we can't read are left out.
+            columns
+                .iter()
+                .filter_map(|column| Some((column.name.clone(), row.block(column)?.clone())))
+                .collect()
         })
         .collect();
 
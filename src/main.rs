@@ -1,11 +1,51 @@
-use margaret::models::database::{follow_relation, Column, Relation};
+use lettre::message::Mailbox;
+use margaret::export::arrow::{write_ipc, write_parquet};
+use margaret::export::ics::write_ics;
+use margaret::export::markdown::write_markdown;
+use margaret::export::obsidian::write_vault;
+use margaret::export::sql::{table_name, write_sql, Dialect};
+use margaret::export::vcard::{write_vcard_files, write_vcards, VcardMapping};
+use margaret::export::xlsx::{write_xlsx, Sheet};
+use margaret::feed::{write_feed, FeedFormat, FeedOptions};
+use margaret::import::{create_pages, load_mapping, map_headers, read_csv, RelationTitles};
+use margaret::journal::{undo, Journal, JournalRun, Undone};
+use margaret::merge::attachments::collect_attachments;
+use margaret::merge::ledger::Ledger;
+use margaret::merge::smtp::{send_messages, transport, SendOptions, SmtpConfig, SmtpSecurity};
+use margaret::merge::suppression::SuppressionList;
+use margaret::merge::{
+    merge_row, preview_row, recipient, unknown_columns, write_eml, write_mbox, MergeOptions,
+    MergeTemplate, Unsubscribe,
+};
+use margaret::models::database::{
+    follow_relation, query_notion_database, Column, Relation, Row, SelectOption,
+};
+use margaret::models::filters::expression::parse_filter;
 use margaret::models::filters::{get_filter_conditions, RelationColumnFilter};
-use margaret::{get_db_columns, query_column_values};
-use std::io::{self, Write};
+use margaret::models::pages::UpdateError;
+use margaret::render::{render_documents, DocumentFormat};
+use margaret::schema::drift::{alias_cells, follow_renames, Renames, SchemaCache};
+use margaret::schema::plan::{plan, probable_renames, SchemaFile};
+use margaret::schema::{
+    alter_database, create_database, removed_options, DatabaseSchema, SchemaChange,
+};
+use margaret::site::{build_site, sort_rows, SiteOptions, SiteTemplates};
+use margaret::sync::{open_mirror, sync_database, SyncOptions};
+use margaret::template::Template;
+use margaret::update::{
+    archive_rows, assignment_properties, parse_assignments, trash_rows, update_rows, RowResult,
+    UpdateOptions,
+};
+use margaret::{get_db_columns, get_db_title, get_db_url, query_column_values};
+use reqwest::Client;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{collections::HashMap, error::Error};
 use struct_iterable::Iterable;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 
 use margaret::models::{
     database::{fetch_notion_database, DatabaseCredentials},
@@ -16,13 +56,424 @@ use margaret::models::{
 struct Args {
     notion_db: String,
     integration_secret: String,
+    /// Where writes are recorded so they can be undone
+    #[arg(long, global = true, default_value = "margaret-journal.db")]
+    journal: PathBuf,
+    /// Where the columns seen on the last run are kept, to report changes to them
+    #[arg(long, global = true, default_value = "margaret-schema.db")]
+    schema_cache: PathBuf,
+    /// Keep referring to renamed columns by their earlier names
+    #[arg(long, global = true)]
+    follow_renames: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Incrementally sync the database into a local SQLite mirror
+    Sync {
+        /// Path to the SQLite mirror, created if it doesn't exist
+        #[arg(long, default_value = "margaret.db")]
+        mirror: PathBuf,
+        /// Hours between full scans used to detect deleted rows
+        #[arg(long, default_value_t = 24)]
+        full_scan_hours: u64,
+        /// Do a full scan on this run regardless of when the last one was
+        #[arg(long)]
+        full_scan: bool,
+    },
+    /// Export the database's rows to a file
+    Export(ExportArgs),
+    /// Render one email per row from a template
+    Merge(Box<MergeArgs>),
+    /// Build a static site from the database
+    Site {
+        #[command(subcommand)]
+        command: SiteCommand,
+    },
+    /// Write an Atom or RSS feed of the database's rows
+    Feed(FeedArgs),
+    /// Render one document per row from a template
+    Render {
+        /// Template file, e.g. `letter.md.tmpl`
+        #[arg(long)]
+        template: PathBuf,
+        /// Where to write each row's document; placeholders pick a path per
+        /// row, e.g. `letters/{{Name}}.md`
+        #[arg(long, short)]
+        out: String,
+        /// Guessed from `--out`'s extension by default
+        #[arg(long, value_enum)]
+        format: Option<DocumentFormat>,
+        /// Only render rows matching this filter, e.g. `Attended = true`
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Create a page in the database for each line of a CSV file
+    Import {
+        /// CSV file whose first line names the columns
+        file: PathBuf,
+        /// JSON file mapping headers to column names, e.g.
+        /// `{"Full name": "Name", "Notes": null}` (null skips a header)
+        #[arg(long)]
+        mapping: Option<PathBuf>,
+        /// Check every line and report problems without creating anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Most pages created in any one second
+        #[arg(long, default_value_t = 3)]
+        per_second: u32,
+    },
+    /// Set columns on every row matching a filter
+    Update(UpdateArgs),
+    /// Archive every row matching a filter
+    Archive(RowsArgs),
+    /// Move every row matching a filter to the trash
+    Trash(RowsArgs),
+    /// Undo a run's writes, leaving alone pages edited since
+    Undo {
+        /// Run to undo, as printed after it finished
+        #[arg(required_unless_present = "list")]
+        run_id: Option<String>,
+        /// List recent runs instead
+        #[arg(long)]
+        list: bool,
+        /// Undo writes to pages that have been edited since, too
+        #[arg(long)]
+        force: bool,
+    },
+    /// Export, create or alter database schemas
+    Schema {
+        #[command(subcommand)]
+        command: SchemaCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SchemaCommand {
+    /// Write the database's title and columns as a JSON schema file
+    Export {
+        /// File to write to, standard output by default
+        #[arg(long, short)]
+        out: Option<PathBuf>,
+    },
+    /// Create a database under a page, from a schema file or like this database
+    Create {
+        /// Page to create the database under
+        #[arg(long)]
+        parent: String,
+        /// Schema file, as written by `schema export` or in YAML or TOML; this
+        /// database's schema by default
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Title of the new database, the schema's by default
+        #[arg(long)]
+        title: Option<String>,
+    },
+    /// Add, rename or remove the database's columns, or set their options
+    Alter(AlterArgs),
+    /// Show how the database differs from a YAML or TOML schema file
+    Plan {
+        /// `.yaml`, `.yml` or `.toml` file describing the expected columns
+        file: PathBuf,
+    },
+    /// Change the database to match a YAML or TOML schema file
+    Apply {
+        /// `.yaml`, `.yml` or `.toml` file describing the expected columns
+        file: PathBuf,
+        /// Make the changes without asking first
+        #[arg(long, short)]
+        yes: bool,
+        /// Remove columns even when a column of the same type is added,
+        /// which is usually a rename that `renamed_from` should describe
+        #[arg(long)]
+        allow_removals: bool,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct AlterArgs {
+    /// `Name:type` of a column to add, or `Name:relation:DATABASE_ID`
+    #[arg(long, value_name = "NAME:TYPE")]
+    add: Vec<String>,
+    /// `Old=New` column to rename
+    #[arg(long, value_name = "OLD=NEW")]
+    rename: Vec<String>,
+    /// Column to remove, along with its values
+    #[arg(long, value_name = "NAME")]
+    remove: Vec<String>,
+    /// `Name=A,B,C` to set a select or multi-select column's options to
+    #[arg(long, value_name = "NAME=OPTIONS")]
+    options: Vec<String>,
+    /// New title for the database
+    #[arg(long)]
+    title: Option<String>,
+    /// Make the changes without asking first
+    #[arg(long, short)]
+    yes: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct UpdateArgs {
+    /// Rows to update, e.g. `Status = "In review" and Reviewed = false`
+    #[arg(long)]
+    filter: String,
+    /// `Column=value` to set on each row, repeat for more than one; an empty
+    /// value clears the column
+    #[arg(long = "set", value_name = "COLUMN=VALUE", required = true)]
+    set: Vec<String>,
+    /// Update without asking first
+    #[arg(long, short)]
+    yes: bool,
+    /// Most updates in flight at once
+    #[arg(long, default_value_t = 3)]
+    concurrency: usize,
+    /// Most updates started in any one second
+    #[arg(long, default_value_t = 3)]
+    per_second: u32,
+    /// Leave rows that were edited after being read alone, showing what changed
+    #[arg(long)]
+    check_conflicts: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct RowsArgs {
+    /// Rows to change, e.g. `Status = "Cancelled"`
+    #[arg(long)]
+    filter: String,
+    /// Go ahead without asking first
+    #[arg(long, short)]
+    yes: bool,
+    /// Most requests in flight at once
+    #[arg(long, default_value_t = 3)]
+    concurrency: usize,
+    /// Most requests started in any one second
+    #[arg(long, default_value_t = 3)]
+    per_second: u32,
+}
+
+#[derive(clap::Args, Debug)]
+struct FeedArgs {
+    #[arg(long, value_enum, default_value_t = FeedFormat::Atom)]
+    format: FeedFormat,
+    /// Only include rows matching this filter, e.g. `Published = true`
+    #[arg(long)]
+    filter: Option<String>,
+    /// Column to date items by, when each row was created by default
+    #[arg(long)]
+    date_column: Option<String>,
+    /// Column to use as each item's summary
+    #[arg(long)]
+    summary_column: Option<String>,
+    /// Feed title, the database's title by default
+    #[arg(long)]
+    title: Option<String>,
+    /// Most items to include, newest first
+    #[arg(long, default_value_t = 50)]
+    limit: usize,
+    /// File to write to, standard output by default
+    #[arg(long, short)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum SiteCommand {
+    /// Write an index page, a page per row and a page per tag
+    Build(SiteArgs),
 }
 
+#[derive(clap::Args, Debug)]
+struct SiteArgs {
+    /// Directory to write the site to
+    #[arg(long, short, default_value = "site")]
+    out: PathBuf,
+    /// Site title, the database's title by default
+    #[arg(long)]
+    title: Option<String>,
+    /// Only publish rows matching this filter, e.g. `Published = true`
+    #[arg(long)]
+    filter: Option<String>,
+    /// Column to order rows by
+    #[arg(long)]
+    sort: Option<String>,
+    /// Order rows from last to first
+    #[arg(long, requires = "sort")]
+    descending: bool,
+    /// `multi_select` column to build tag pages from
+    #[arg(long)]
+    tag_column: Option<String>,
+    /// Directory of templates overriding the built-in `layout.html`,
+    /// `index.html`, `item.html`, `page.html` and `tag.html`
+    #[arg(long)]
+    templates: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    #[arg(long, value_enum, default_value_t = ExportFormat::Sql)]
+    format: ExportFormat,
+    /// SQL dialect to write when exporting with `--format sql`
+    #[arg(long, value_enum, default_value_t = Dialect::Postgres)]
+    dialect: Dialect,
+    /// Table name, derived from the database's title by default
+    #[arg(long)]
+    table: Option<String>,
+    /// Column to export, repeat for more than one (defaults to every column)
+    #[arg(long = "column", value_name = "COLUMN")]
+    columns: Vec<String>,
+    /// Rows per INSERT statement
+    #[arg(long, default_value_t = 100)]
+    batch_size: usize,
+    /// Another database to export alongside NOTION_DB into its own sheet
+    /// with `--format xlsx`, repeat for more than one
+    #[arg(long = "database", value_name = "DATABASE")]
+    databases: Vec<String>,
+    /// Write an Obsidian vault to `--out` instead: a note per row in this and
+    /// related databases, with relations as links between notes
+    #[arg(long, conflicts_with_all = ["format", "columns"])]
+    obsidian: bool,
+    /// How many relations away from this database to follow with `--obsidian`
+    #[arg(long, default_value_t = 1, requires = "obsidian")]
+    relation_depth: usize,
+    /// Write an iCalendar file instead, with an event for each row with a date
+    #[arg(long, conflicts_with_all = ["format", "columns", "obsidian"])]
+    ics: bool,
+    /// Date column to take events' dates from with `--ics`, the first one by default
+    #[arg(long, requires = "ics")]
+    date_column: Option<String>,
+    /// Column to include in events' descriptions with `--ics`, repeat for more than one
+    #[arg(long = "description-column", value_name = "COLUMN", requires = "ics")]
+    description_columns: Vec<String>,
+    /// Write contact cards (vCard 4.0) instead, one per row
+    #[arg(long, conflicts_with_all = ["format", "columns", "obsidian", "ics"])]
+    vcard: bool,
+    /// JSON file mapping vCard fields to columns with `--vcard`, such as
+    /// `{"ORG": "Company", "CATEGORIES": ["Tags"]}`
+    #[arg(long, value_name = "PATH", requires = "vcard")]
+    vcard_mapping: Option<PathBuf>,
+    /// Write each card to its own file in `--out` rather than all to one
+    #[arg(long, requires = "vcard")]
+    per_row: bool,
+    /// File to write to, standard output by default (a directory for
+    /// `--format markdown`, `--obsidian` and `--vcard --per-row`)
+    #[arg(long, short)]
+    out: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct MergeArgs {
+    /// Template file: `Header: value` lines, a blank line, then the body
+    template: PathBuf,
+    /// Only send to rows matching this filter, e.g. `Subscribed = true`
+    #[arg(long)]
+    filter: Option<String>,
+    /// Column holding each recipient's email address
+    #[arg(long, default_value = "Email")]
+    email_column: String,
+    /// Column holding each recipient's name
+    #[arg(long)]
+    name_column: Option<String>,
+    /// Sender, used when the template has no From header
+    #[arg(long)]
+    from: Option<Mailbox>,
+    /// `files` column whose files are attached to each row's message
+    #[arg(long)]
+    attach_column: Option<String>,
+    /// Local file to attach, repeat for more than one; placeholders can be
+    /// used to pick a file per row, e.g. `invoices/{{Invoice ID}}.pdf`
+    #[arg(long = "attach", value_name = "PATH")]
+    attach: Vec<String>,
+    /// Directory `--attach` paths are relative to; files outside it can't be attached
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    attach_dir: PathBuf,
+    /// Directory to write messages to
+    #[arg(long, short, default_value = "merge")]
+    out: PathBuf,
+    /// Write a single mbox file instead of one .eml file per message
+    #[arg(long)]
+    mbox: bool,
+    /// Print each message and any problems with it instead of writing or sending
+    #[arg(long, conflicts_with_all = ["send", "mbox"])]
+    preview: bool,
+    /// Send the messages over SMTP instead of writing them to files
+    #[arg(long, requires = "smtp_host")]
+    send: bool,
+    #[command(flatten)]
+    suppression: SuppressionArgs,
+    #[command(flatten)]
+    smtp: SmtpArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct SuppressionArgs {
+    /// File of addresses never to mail, one per line, repeat for more than one
+    #[arg(long = "suppress", value_name = "PATH")]
+    suppress: Vec<PathBuf>,
+    /// Database of addresses never to mail
+    #[arg(long)]
+    suppress_database: Option<String>,
+    /// Checkbox column in `--suppress-database`; only rows with it ticked are suppressed
+    #[arg(long, requires = "suppress_database")]
+    suppress_checkbox: Option<String>,
+    /// Column holding addresses in `--suppress-database`
+    #[arg(long, default_value = "Email")]
+    suppress_email_column: String,
+    /// Key for the `{{unsubscribe_token}}` given to each recipient
+    #[arg(long, env = "MARGARET_UNSUBSCRIBE_SECRET", hide_env_values = true)]
+    unsubscribe_secret: Option<String>,
+    /// Link available as `{{unsubscribe_url}}` and sent as a List-Unsubscribe
+    /// header, e.g. `https://example.com/unsubscribe?t={{unsubscribe_token}}`
+    #[arg(long, requires = "unsubscribe_secret")]
+    unsubscribe_url: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SmtpArgs {
+    #[arg(long)]
+    smtp_host: Option<String>,
+    /// Defaults to the usual port for `--smtp-security`
+    #[arg(long)]
+    smtp_port: Option<u16>,
+    #[arg(long, value_enum, default_value_t = SmtpSecurity::Starttls)]
+    smtp_security: SmtpSecurity,
+    #[arg(long, env = "MARGARET_SMTP_USERNAME")]
+    smtp_username: Option<String>,
+    #[arg(long, env = "MARGARET_SMTP_PASSWORD", hide_env_values = true)]
+    smtp_password: Option<String>,
+    /// Most messages to send per minute
+    #[arg(long)]
+    per_minute: Option<u32>,
+    /// Where to record sent messages so they aren't sent twice
+    #[arg(long, default_value = "margaret-ledger.db")]
+    ledger: PathBuf,
+    /// Name for this mailing in the ledger, the template's file name by default
+    #[arg(long)]
+    campaign: Option<String>,
+    /// Checkbox column to tick on each row once its message is sent
+    #[arg(long)]
+    mark_sent: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ExportFormat {
+    Sql,
+    Parquet,
+    /// Arrow IPC file
+    Arrow,
+    Xlsx,
+    /// One Markdown file per row, with its properties as front matter,
+    /// written to the directory given by `--out`
+    Markdown,
+}
+
+#[allow(dead_code)]
 struct RelationColumn {
     related_columns: HashMap<String, Vec<Column>>,
     relation: Relation,
 }
 
+#[allow(dead_code)]
 struct ColumnToPrint {
     column: Column,
     relation: Option<RelationColumn>,
@@ -35,7 +486,1147 @@ async fn main() -> Result<(), Box<dyn Error>> {
         id: args.notion_db,
         token: args.integration_secret,
     };
-    let db = fetch_notion_database(&credentials).await?;
+
+    // Only commands that refer to columns are affected by them changing.
+    let refers_to_columns = match &args.command {
+        Some(Command::Export(export_args)) => !export_args.obsidian,
+        Some(
+            Command::Sync { .. }
+            | Command::Merge(_)
+            | Command::Site { .. }
+            | Command::Feed(_)
+            | Command::Render { .. }
+            | Command::Import { .. }
+            | Command::Update(_)
+            | Command::Archive(_)
+            | Command::Trash(_),
+        ) => true,
+        _ => false,
+    };
+    let (renames, seen) = match refers_to_columns {
+        true => {
+            let (renames, seen) =
+                check_drift(&credentials, &args.schema_cache, args.follow_renames).await?;
+            (renames, Some(seen))
+        }
+        false => (Renames::new(), None),
+    };
+
+    let result = match args.command {
+        Some(Command::Sync {
+            mirror,
+            full_scan_hours,
+            full_scan,
+        }) => sync(&credentials, &renames, mirror, full_scan_hours, full_scan).await,
+        Some(Command::Export(export_args)) => export(&credentials, &renames, export_args).await,
+        Some(Command::Merge(merge_args)) => merge(&credentials, &renames, *merge_args).await,
+        Some(Command::Site {
+            command: SiteCommand::Build(site_args),
+        }) => build(&credentials, &renames, site_args).await,
+        Some(Command::Feed(feed_args)) => feed(&credentials, &renames, feed_args).await,
+        Some(Command::Render {
+            template,
+            out,
+            format,
+            filter,
+        }) => render(&credentials, &renames, template, out, format, filter).await,
+        Some(Command::Import {
+            file,
+            mapping,
+            dry_run,
+            per_second,
+        }) => {
+            import(
+                &credentials,
+                &renames,
+                &args.journal,
+                file,
+                mapping,
+                dry_run,
+                per_second,
+            )
+            .await
+        }
+        Some(Command::Update(update_args)) => {
+            update(&credentials, &renames, &args.journal, update_args).await
+        }
+        Some(Command::Archive(rows_args)) => {
+            remove(&credentials, &renames, &args.journal, rows_args, false).await
+        }
+        Some(Command::Trash(rows_args)) => {
+            remove(&credentials, &renames, &args.journal, rows_args, true).await
+        }
+        Some(Command::Undo {
+            run_id,
+            list,
+            force,
+        }) => undo_run(&credentials, &args.journal, run_id, list, force).await,
+        Some(Command::Schema { command }) => schema(&credentials, command).await,
+        None => interactive(&credentials).await,
+    };
+    // Remembered only once the command has worked, so a failed run reports
+    // the same changes next time.
+    if let (Ok(()), Some(mut seen)) = (&result, seen) {
+        seen.cache.remember(&credentials.id, &seen.columns)?;
+    }
+    result
+}
+
+/// The columns seen on this run, to remember in the schema cache.
+struct SeenColumns {
+    cache: SchemaCache,
+    columns: Vec<Column>,
+}
+
+/// Reports how the database's columns have changed since the last run, and
+/// returns their earlier names if renames are being followed.
+async fn check_drift(
+    credentials: &DatabaseCredentials,
+    cache: &Path,
+    follow_renames: bool,
+) -> Result<(Renames, SeenColumns), Box<dyn Error>> {
+    let db = fetch_notion_database(credentials).await?;
+    let columns = get_db_columns(&db.body)?.unwrap_or_default();
+    let cache = SchemaCache::open(cache)?;
+    if let Some(drift) = cache.drift(&credentials.id, &columns)? {
+        if !drift.is_empty() {
+            // Standard error, so it doesn't end up in exports written to standard output.
+            eprintln!("The database's columns have changed since the last run:");
+            eprintln!("{}", drift);
+            if !drift.renamed.is_empty() && !follow_renames {
+                eprintln!("Use --follow-renames to keep referring to them by their earlier names.");
+            }
+        }
+    }
+    let renames = match follow_renames {
+        true => cache.renames(&credentials.id)?,
+        false => Renames::new(),
+    };
+    Ok((renames, SeenColumns { cache, columns }))
+}
+
+/// The database's columns, answering to their earlier names too if renames
+/// are being followed.
+fn columns(body: &str, renames: &Renames) -> Result<Vec<Column>, Box<dyn Error>> {
+    let mut columns = get_db_columns(body)?.unwrap_or_default();
+    follow_renames(&mut columns, renames);
+    Ok(columns)
+}
+
+async fn sync(
+    credentials: &DatabaseCredentials,
+    renames: &Renames,
+    mirror: PathBuf,
+    full_scan_hours: u64,
+    full_scan: bool,
+) -> Result<(), Box<dyn Error>> {
+    let conn = open_mirror(&mirror)?;
+    let renamed = match renames.is_empty() {
+        true => Vec::new(),
+        false => {
+            let db = fetch_notion_database(credentials).await?;
+            columns(&db.body, renames)?
+                .into_iter()
+                .filter(|column| !column.aliases.is_empty())
+                .collect()
+        }
+    };
+    let options = SyncOptions {
+        full_scan_interval: Duration::from_secs(full_scan_hours * 60 * 60),
+        force_full_scan: full_scan,
+        renamed,
+    };
+    let report = sync_database(credentials, &conn, &options).await?;
+    println!(
+        "Synced {} into {}: {} rows updated, {} rows removed{}.",
+        credentials.id,
+        mirror.display(),
+        report.upserted,
+        report.removed,
+        if report.full_scan { " (full scan)" } else { "" }
+    );
+    Ok(())
+}
+
+/// Picks out the columns named in `names`, or every column if none are named.
+fn select_columns<'a>(
+    columns: &'a [Column],
+    names: &[String],
+) -> Result<Vec<&'a Column>, Box<dyn Error>> {
+    if names.is_empty() {
+        return Ok(columns.iter().collect());
+    }
+    names
+        .iter()
+        .map(|name| find_column(columns, name))
+        .collect()
+}
+
+fn find_column<'a>(columns: &'a [Column], name: &str) -> Result<&'a Column, Box<dyn Error>> {
+    margaret::models::database::find_column(columns, name)
+        .ok_or_else(|| format!("The column '{}' does not exist.", name).into())
+}
+
+fn open_output(out: Option<PathBuf>) -> Result<Box<dyn Write + Send>, Box<dyn Error>> {
+    Ok(match out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    })
+}
+
+async fn export(
+    credentials: &DatabaseCredentials,
+    renames: &Renames,
+    args: ExportArgs,
+) -> Result<(), Box<dyn Error>> {
+    if args.obsidian {
+        let vault = args
+            .out
+            .ok_or("--obsidian needs a directory to write to (--out)")?;
+        let report = write_vault(
+            &credentials.token,
+            &credentials.id,
+            &vault,
+            args.relation_depth,
+        )
+        .await?;
+        for (id, err) in report.unreachable.iter() {
+            eprintln!(
+                "⚠️  Couldn't read the related database {}, so links to it are plain ids: {}",
+                id, err
+            );
+        }
+        println!(
+            "Wrote {} notes to {}.",
+            report.written.len(),
+            vault.display()
+        );
+        return Ok(());
+    }
+    let db = fetch_notion_database(credentials).await?;
+    let title = get_db_title(&db.body)?;
+    let all_columns = columns(&db.body, renames)?;
+    let columns = select_columns(&all_columns, &args.columns)?;
+    let rows = query_notion_database(credentials, None).await?;
+
+    if args.ics {
+        let date_column = match &args.date_column {
+            Some(name) => find_column(&all_columns, name)?,
+            None => all_columns
+                .iter()
+                .find(|column| column.column_type == "date")
+                .ok_or("The database has no date columns.")?,
+        };
+        let description_columns = args
+            .description_columns
+            .iter()
+            .map(|name| find_column(&all_columns, name))
+            .collect::<Result<Vec<&Column>, _>>()?;
+        let mut writer = open_output(args.out)?;
+        let events = write_ics(
+            &mut writer,
+            &title.unwrap_or_else(|| credentials.id.clone()),
+            date_column,
+            &description_columns,
+            &rows,
+        )?;
+        writer.flush()?;
+        eprintln!("Wrote {} events.", events);
+        return Ok(());
+    }
+
+    if args.vcard {
+        let mapping = match &args.vcard_mapping {
+            Some(path) => VcardMapping::load(path)?,
+            None => VcardMapping::default(),
+        };
+        let columns = mapping.columns(&all_columns)?;
+        if args.per_row {
+            let dir = args
+                .out
+                .ok_or("--per-row needs a directory to write to (--out)")?;
+            let written = write_vcard_files(&dir, &rows, &columns)?;
+            println!("Wrote {} cards to {}.", written.len(), dir.display());
+        } else {
+            let mut writer = open_output(args.out)?;
+            write_vcards(&mut writer, &rows, &columns)?;
+            writer.flush()?;
+        }
+        return Ok(());
+    }
+
+    match args.format {
+        ExportFormat::Markdown => {
+            let dir = args
+                .out
+                .ok_or("--format markdown needs a directory to write to (--out)")?;
+            let written = write_markdown(&credentials.token, &dir, &columns, &rows).await?;
+            println!("Wrote {} documents to {}.", written.len(), dir.display());
+        }
+        ExportFormat::Sql => {
+            let mut writer = open_output(args.out)?;
+            let table = args
+                .table
+                .unwrap_or_else(|| table_name(&title.unwrap_or_default()));
+            write_sql(
+                &mut writer,
+                args.dialect,
+                &table,
+                &columns,
+                &rows,
+                args.batch_size,
+            )?;
+            writer.flush()?;
+        }
+        ExportFormat::Parquet => {
+            let mut writer = open_output(args.out)?;
+            write_parquet(&mut writer, &columns, &rows)?;
+            writer.flush()?;
+        }
+        ExportFormat::Arrow => {
+            let mut writer = open_output(args.out)?;
+            write_ipc(&mut writer, &columns, &rows)?;
+            writer.flush()?;
+        }
+        ExportFormat::Xlsx => {
+            let mut others = Vec::new();
+            for id in args.databases.iter() {
+                let credentials = DatabaseCredentials {
+                    id: id.clone(),
+                    token: credentials.token.clone(),
+                };
+                let db = fetch_notion_database(&credentials).await?;
+                others.push((
+                    get_db_title(&db.body)?.unwrap_or_else(|| id.clone()),
+                    get_db_columns(&db.body)?.unwrap_or_default(),
+                    query_notion_database(&credentials, None).await?,
+                ));
+            }
+
+            let sheets = std::iter::once(Sheet {
+                name: title.unwrap_or_else(|| credentials.id.clone()),
+                columns,
+                rows: &rows,
+            })
+            .chain(others.iter().map(|(name, columns, rows)| Sheet {
+                name: name.clone(),
+                columns: columns.iter().collect(),
+                rows,
+            }))
+            .collect::<Vec<Sheet>>();
+            let mut writer = open_output(args.out)?;
+            writer.write_all(&write_xlsx(&sheets)?)?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+async fn merge(
+    credentials: &DatabaseCredentials,
+    renames: &Renames,
+    args: MergeArgs,
+) -> Result<(), Box<dyn Error>> {
+    let template = MergeTemplate::parse(&fs::read_to_string(&args.template)?)?;
+    let suppression = load_suppression_list(credentials, &args.suppression).await?;
+    let options = MergeOptions {
+        email_column: args.email_column,
+        name_column: args.name_column,
+        from: args.from,
+        files_column: args.attach_column,
+        attachment_paths: args
+            .attach
+            .iter()
+            .map(|path| Template::parse(path))
+            .collect::<Result<Vec<Template>, _>>()?,
+        attachment_dir: args.attach_dir,
+        unsubscribe: match args.suppression.unsubscribe_secret {
+            Some(secret) => Some(Unsubscribe {
+                secret,
+                url: args
+                    .suppression
+                    .unsubscribe_url
+                    .as_deref()
+                    .map(Template::parse)
+                    .transpose()?,
+            }),
+            None => None,
+        },
+    };
+    let db = fetch_notion_database(credentials).await?;
+    let columns = columns(&db.body, renames)?;
+    let filter = args
+        .filter
+        .as_deref()
+        .map(|filter| parse_filter(filter, &columns))
+        .transpose()?;
+    let mut rows = query_notion_database(credentials, filter.as_ref()).await?;
+    alias_cells(&mut rows, &columns);
+    if args.preview {
+        return preview_merge(&template, &options, &suppression, &columns, &rows);
+    }
+
+    let client = Client::new();
+    let mut messages = Vec::new();
+    let mut skipped = Vec::new();
+    for row in rows.iter() {
+        let address = match recipient(row, &options) {
+            Ok(recipient) => recipient.email.to_string(),
+            Err(err) => {
+                skipped.push((row.id.clone(), err.to_string()));
+                continue;
+            }
+        };
+        if let Some(reason) = suppression.reason(&address) {
+            skipped.push((format!("{} ({})", address, row.id), reason.to_string()));
+            continue;
+        }
+
+        let message = match collect_attachments(
+            &client,
+            row,
+            options.files_column.as_deref(),
+            &options.attachment_paths,
+            &options.attachment_dir,
+        )
+        .await
+        {
+            Ok(attachments) => merge_row(&template, row, &options, &attachments),
+            Err(err) => Err(err),
+        };
+        match message {
+            Ok(message) => messages.push(message),
+            Err(err) => skipped.push((format!("{} ({})", address, row.id), err.to_string())),
+        }
+    }
+
+    if !skipped.is_empty() {
+        println!("Skipped {} rows:", skipped.len());
+        for (who, why) in skipped.iter() {
+            println!("  {}: {}", who, why);
+        }
+    }
+
+    if args.send {
+        let campaign = args.smtp.campaign.unwrap_or_else(|| {
+            args.template
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+        let transport = transport(&SmtpConfig {
+            host: args.smtp.smtp_host.unwrap_or_default(),
+            port: args.smtp.smtp_port,
+            security: args.smtp.smtp_security,
+            username: args.smtp.smtp_username,
+            password: args.smtp.smtp_password,
+        })?;
+        let ledger = Ledger::open(&args.smtp.ledger)?;
+        let options = SendOptions {
+            campaign,
+            per_minute: args.smtp.per_minute,
+            mark_sent: args.smtp.mark_sent,
+        };
+
+        let report = send_messages(&transport, credentials, &messages, &ledger, &options).await?;
+        for (row_id, err) in report.failed.iter() {
+            println!("Failed to send to {}: {}", row_id, err);
+        }
+        for (row_id, err) in report.not_marked.iter() {
+            println!(
+                "Sent to {}, but couldn't tick '{}': {}",
+                row_id,
+                options.mark_sent.as_deref().unwrap_or_default(),
+                err
+            );
+        }
+        println!(
+            "Sent {} messages ({} already sent, {} failed).",
+            report.sent,
+            report.already_sent,
+            report.failed.len()
+        );
+    } else if args.mbox {
+        fs::create_dir_all(&args.out)?;
+        let path = args.out.join("merge.mbox");
+        let mut writer = BufWriter::new(File::create(&path)?);
+        write_mbox(&mut writer, &messages)?;
+        writer.flush()?;
+        println!("Wrote {} messages to {}.", messages.len(), path.display());
+    } else {
+        write_eml(&args.out, &messages)?;
+        println!(
+            "Wrote {} messages to {}.",
+            messages.len(),
+            args.out.display()
+        );
+    }
+    Ok(())
+}
+
+async fn build(
+    credentials: &DatabaseCredentials,
+    renames: &Renames,
+    args: SiteArgs,
+) -> Result<(), Box<dyn Error>> {
+    let templates = SiteTemplates::load(args.templates.as_deref())?;
+    let db = fetch_notion_database(credentials).await?;
+    let columns = columns(&db.body, renames)?;
+    let tag_column = args
+        .tag_column
+        .as_deref()
+        .map(|name| find_column(&columns, name))
+        .transpose()?;
+    if let Some(column) = tag_column.filter(|column| column.column_type != "multi_select") {
+        return Err(format!("'{}' isn't a multi_select column", column.name).into());
+    }
+
+    let filter = args
+        .filter
+        .as_deref()
+        .map(|filter| parse_filter(filter, &columns))
+        .transpose()?;
+    let mut rows = query_notion_database(credentials, filter.as_ref()).await?;
+    if let Some(sort) = &args.sort {
+        sort_rows(&mut rows, find_column(&columns, sort)?, args.descending);
+    }
+
+    let options = SiteOptions {
+        title: args
+            .title
+            .or(get_db_title(&db.body)?)
+            .unwrap_or_else(|| credentials.id.clone()),
+        tag_column,
+    };
+    let written = build_site(&credentials.token, &args.out, &rows, &options, &templates).await?;
+    println!("Wrote {} pages to {}.", written.len(), args.out.display());
+    Ok(())
+}
+
+async fn feed(
+    credentials: &DatabaseCredentials,
+    renames: &Renames,
+    args: FeedArgs,
+) -> Result<(), Box<dyn Error>> {
+    let db = fetch_notion_database(credentials).await?;
+    let columns = columns(&db.body, renames)?;
+    let filter = args
+        .filter
+        .as_deref()
+        .map(|filter| parse_filter(filter, &columns))
+        .transpose()?;
+    let rows = query_notion_database(credentials, filter.as_ref()).await?;
+
+    let options = FeedOptions {
+        title: args
+            .title
+            .or(get_db_title(&db.body)?)
+            .unwrap_or_else(|| credentials.id.clone()),
+        link: get_db_url(&db.body)?.unwrap_or_default(),
+        database_id: credentials.id.clone(),
+        date_column: args
+            .date_column
+            .as_deref()
+            .map(|name| find_column(&columns, name))
+            .transpose()?,
+        summary_column: args
+            .summary_column
+            .as_deref()
+            .map(|name| find_column(&columns, name))
+            .transpose()?,
+        limit: args.limit,
+    };
+    let mut writer = open_output(args.out)?;
+    write_feed(&mut writer, args.format, &rows, &options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+async fn render(
+    credentials: &DatabaseCredentials,
+    renames: &Renames,
+    template: PathBuf,
+    out: String,
+    format: Option<DocumentFormat>,
+    filter: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let format = format.unwrap_or_else(|| DocumentFormat::from_path(&out));
+    let template = Template::parse(&fs::read_to_string(&template)?)?;
+    let out = Template::parse(&out)?;
+    let db = fetch_notion_database(credentials).await?;
+    let columns = columns(&db.body, renames)?;
+    let filter = filter
+        .as_deref()
+        .map(|filter| parse_filter(filter, &columns))
+        .transpose()?;
+    let mut rows = query_notion_database(credentials, filter.as_ref()).await?;
+    alias_cells(&mut rows, &columns);
+
+    let written = render_documents(&template, &out, &rows, format)?;
+    for path in written.iter() {
+        println!("{}", path.display());
+    }
+    println!("Wrote {} documents.", written.len());
+    Ok(())
+}
+
+async fn import(
+    credentials: &DatabaseCredentials,
+    renames: &Renames,
+    journal: &Path,
+    file: PathBuf,
+    mapping: Option<PathBuf>,
+    dry_run: bool,
+    per_second: u32,
+) -> Result<(), Box<dyn Error>> {
+    let db = fetch_notion_database(credentials).await?;
+    let columns = columns(&db.body, renames)?;
+    let mapping = match &mapping {
+        Some(path) => load_mapping(path)?,
+        None => HashMap::new(),
+    };
+
+    let headers = csv::Reader::from_path(&file)?
+        .headers()?
+        .iter()
+        .map(|header| header.trim().to_string())
+        .collect::<Vec<String>>();
+    let headers = map_headers(&headers, &columns, &mapping)?;
+    let mapped = headers.iter().flatten().copied().collect::<Vec<&Column>>();
+    let relations = RelationTitles::fetch(&credentials.token, &mapped).await?;
+    let (rows, errors) = read_csv(File::open(&file)?, &headers, &relations)?;
+
+    for error in errors.iter() {
+        eprintln!("❌ {}", error);
+    }
+    if dry_run {
+        println!(
+            "{} rows would be created, {} have problems.",
+            rows.len(),
+            errors.len()
+        );
+        return Ok(());
+    }
+    if !errors.is_empty() {
+        return Err(format!(
+            "Nothing was imported, since {} rows have problems. Fix them, or use --dry-run to check the file.",
+            errors.len()
+        )
+        .into());
+    }
+
+    let journal = Journal::open(journal)?;
+    let run = journal.start_run("import", &credentials.id)?;
+    let (created, errors) = create_pages(credentials, &rows, per_second, Some(&run)).await;
+    for error in errors.iter() {
+        eprintln!("❌ {}", error);
+    }
+    println!("Created {} pages.", created);
+    if run.has_changes()? {
+        println!("To undo this, run `undo {}`.", run.id);
+    }
+    if !errors.is_empty() {
+        return Err(format!("{} rows couldn't be created.", errors.len()).into());
+    }
+    Ok(())
+}
+
+async fn update(
+    credentials: &DatabaseCredentials,
+    renames: &Renames,
+    journal: &Path,
+    args: UpdateArgs,
+) -> Result<(), Box<dyn Error>> {
+    let db = fetch_notion_database(credentials).await?;
+    let columns = columns(&db.body, renames)?;
+    let filter = parse_filter(&args.filter, &columns)?;
+    let assignments = parse_assignments(&args.set, &columns)?;
+    let assigned = assignments
+        .iter()
+        .map(|(column, _)| *column)
+        .collect::<Vec<&Column>>();
+    let relations = RelationTitles::fetch(&credentials.token, &assigned).await?;
+    let properties = assignment_properties(&assignments, &relations)?;
+
+    let rows = query_notion_database(credentials, Some(&filter)).await?;
+    if !list_matches(&rows) {
+        return Ok(());
+    }
+
+    let changes = assignments
+        .iter()
+        .map(|(column, value)| format!("{} = {}", column.name, value.trim()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    if !args.yes && !confirm(&format!("Set {} on these rows?", changes))? {
+        println!("Nothing was updated.");
+        return Ok(());
+    }
+
+    let options = UpdateOptions {
+        concurrency: args.concurrency,
+        per_second: args.per_second,
+        check_conflicts: args.check_conflicts,
+    };
+    let journal = Journal::open(journal)?;
+    let run = journal.start_run("update", &credentials.id)?;
+    let results = update_rows(&credentials.token, &rows, &properties, &options, Some(&run)).await;
+    report_rows(&results, "Updated", &run)
+}
+
+/// Archives every row matching a filter, or moves them to the trash.
+async fn remove(
+    credentials: &DatabaseCredentials,
+    renames: &Renames,
+    journal: &Path,
+    args: RowsArgs,
+    trash: bool,
+) -> Result<(), Box<dyn Error>> {
+    let db = fetch_notion_database(credentials).await?;
+    let columns = columns(&db.body, renames)?;
+    let filter = parse_filter(&args.filter, &columns)?;
+    let rows = query_notion_database(credentials, Some(&filter)).await?;
+    if !list_matches(&rows) {
+        return Ok(());
+    }
+
+    let (question, verb) = match trash {
+        true => ("Move these rows to the trash?", "Trashed"),
+        false => ("Archive these rows?", "Archived"),
+    };
+    if !args.yes && !confirm(question)? {
+        println!("Nothing was changed.");
+        return Ok(());
+    }
+
+    let options = UpdateOptions {
+        concurrency: args.concurrency,
+        per_second: args.per_second,
+        check_conflicts: false,
+    };
+    let journal = Journal::open(journal)?;
+    let run = journal.start_run(if trash { "trash" } else { "archive" }, &credentials.id)?;
+    let results = match trash {
+        true => trash_rows(&credentials.token, &rows, &options, Some(&run)).await,
+        false => archive_rows(&credentials.token, &rows, &options, Some(&run)).await,
+    };
+    report_rows(&results, verb, &run)
+}
+
+/// Prints how many rows match and the first few of them, returning whether
+/// there are any.
+fn list_matches(rows: &[Row]) -> bool {
+    if rows.is_empty() {
+        println!("No rows match.");
+        return false;
+    }
+    println!("{} rows match:", rows.len());
+    for row in rows.iter().take(10) {
+        println!("  {}", row.title().unwrap_or_else(|| row.id.clone()));
+    }
+    if rows.len() > 10 {
+        println!("  ...and {} more", rows.len() - 10);
+    }
+    true
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Prints how writing each row went, and how to undo the run if it wrote anything.
+fn report_rows(results: &[RowResult], verb: &str, run: &JournalRun) -> Result<(), Box<dyn Error>> {
+    let mut failed = 0;
+    let mut conflicts = 0;
+    for row in results.iter() {
+        let name = row.title.clone().unwrap_or_else(|| row.row_id.clone());
+        match &row.result {
+            Ok(()) => println!("✅ {}", name),
+            Err(err) => match err.downcast_ref::<UpdateError>() {
+                Some(UpdateError::Conflict(conflict)) => {
+                    conflicts += 1;
+                    println!("⚠️ {}: {}", name, conflict);
+                }
+                _ => {
+                    failed += 1;
+                    println!("❌ {}: {}", name, err);
+                }
+            },
+        }
+    }
+    println!("{} {} rows.", verb, results.len() - failed - conflicts);
+    if run.has_changes()? {
+        println!("To undo this, run `undo {}`.", run.id);
+    }
+    if conflicts > 0 {
+        println!(
+            "{} rows were edited after being read, so were left alone.",
+            conflicts
+        );
+    }
+    if failed > 0 {
+        return Err(format!("{} rows couldn't be changed.", failed).into());
+    }
+    Ok(())
+}
+
+async fn undo_run(
+    credentials: &DatabaseCredentials,
+    journal: &Path,
+    run_id: Option<String>,
+    list: bool,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    let journal = Journal::open(journal)?;
+    if list {
+        for run in journal.runs()?.iter().take(20) {
+            println!(
+                "{}  {} on {}, {} writes ({} undone), started {}",
+                run.id, run.command, run.database_id, run.changes, run.undone, run.started_at
+            );
+        }
+        return Ok(());
+    }
+    let run_id = run_id.ok_or("Which run should be undone?")?;
+    if !journal.has_run(&run_id)? {
+        return Err(format!("The journal has no run '{}'.", run_id).into());
+    }
+
+    let results = undo(&credentials.token, &journal, &run_id, force).await?;
+    let mut left = 0;
+    for (page_id, outcome) in results.iter() {
+        match outcome {
+            Undone::Restored => println!("✅ {}", page_id),
+            Undone::Conflict(edited_time) => {
+                left += 1;
+                println!("⚠️ {}: edited since, at {}", page_id, edited_time);
+            }
+            Undone::Unknown => {
+                left += 1;
+                println!(
+                    "⚠️ {}: it isn't known whether the write went through",
+                    page_id
+                );
+            }
+            Undone::Failed(err) => {
+                left += 1;
+                println!("❌ {}: {}", page_id, err);
+            }
+        }
+    }
+    println!("Undid {} writes.", results.len() - left);
+    if left > 0 {
+        return Err(format!(
+            "{} writes weren't undone. Run this again once they're sorted out, or use \
+            --force to undo writes to pages edited since anyway.",
+            left
+        )
+        .into());
+    }
+    Ok(())
+}
+
+async fn schema(
+    credentials: &DatabaseCredentials,
+    command: SchemaCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        SchemaCommand::Export { out } => {
+            let schema = DatabaseSchema::fetch(credentials).await?;
+            let mut writer = open_output(out)?;
+            serde_json::to_writer_pretty(&mut writer, &schema)?;
+            writeln!(writer)?;
+            Ok(())
+        }
+        SchemaCommand::Create {
+            parent,
+            file,
+            title,
+        } => {
+            let mut schema = match file {
+                Some(file) => DatabaseSchema::load(&file)?,
+                None => DatabaseSchema::fetch(credentials).await?,
+            };
+            if let Some(title) = title {
+                schema.title = title;
+            }
+            let uncreatable = schema.remove_uncreatable();
+            if !uncreatable.is_empty() {
+                eprintln!(
+                    "⚠️  Notion's API can't create these columns, so they were left out: {}",
+                    uncreatable.join(", ")
+                );
+            }
+            let id = create_database(&credentials.token, &parent, &schema).await?;
+            println!("Created '{}' ({}).", schema.title, id);
+            Ok(())
+        }
+        SchemaCommand::Alter(args) => alter(credentials, args).await,
+        SchemaCommand::Plan { file } => {
+            let (changes, live) = plan_schema(credentials, &file).await?;
+            if changes.is_empty() {
+                println!("The database matches {}.", file.display());
+            }
+            for change in changes.iter() {
+                println!("{}", change);
+            }
+            warn_of_probable_renames(&changes, &live);
+            warn_of_removed_options(&changes, &live.columns);
+            Ok(())
+        }
+        SchemaCommand::Apply {
+            file,
+            yes,
+            allow_removals,
+        } => {
+            let (changes, live) = plan_schema(credentials, &file).await?;
+            if changes.is_empty() {
+                println!("The database already matches {}.", file.display());
+                return Ok(());
+            }
+            if warn_of_probable_renames(&changes, &live) && !allow_removals {
+                return Err(
+                    "Nothing was changed. Add `renamed_from` to the columns that were \
+                    renamed, or use --allow-removals to remove them along with their values."
+                        .into(),
+                );
+            }
+            apply_changes(credentials, &changes, &live.columns, yes).await
+        }
+    }
+}
+
+/// The changes that would make the database match `file`, along with the
+/// database's current schema.
+async fn plan_schema(
+    credentials: &DatabaseCredentials,
+    file: &Path,
+) -> Result<(Vec<SchemaChange>, DatabaseSchema), Box<dyn Error>> {
+    let file = SchemaFile::load(file)?;
+    let live = DatabaseSchema::fetch(credentials).await?;
+    Ok((plan(&file, &live)?, live))
+}
+
+/// Warns of columns that look renamed rather than removed, returning whether
+/// there were any.
+fn warn_of_probable_renames(changes: &[SchemaChange], live: &DatabaseSchema) -> bool {
+    let renames = probable_renames(changes, live);
+    for (removed, added) in renames.iter() {
+        eprintln!(
+            "⚠️  '{}' is removed and '{}' added with the same type. If it was renamed, \
+            add `renamed_from: {}` to '{}' to keep its values.",
+            removed, added, removed, added
+        );
+    }
+    !renames.is_empty()
+}
+
+fn warn_of_removed_options(changes: &[SchemaChange], columns: &[Column]) {
+    for (name, options) in removed_options(changes, columns) {
+        eprintln!(
+            "⚠️  '{}' loses the options {}, which are cleared from every row that has them.",
+            name,
+            options.join(", ")
+        );
+    }
+}
+
+/// Prints the changes to a database with `columns`, then makes them once confirmed.
+async fn apply_changes(
+    credentials: &DatabaseCredentials,
+    changes: &[SchemaChange],
+    columns: &[Column],
+    yes: bool,
+) -> Result<(), Box<dyn Error>> {
+    for change in changes.iter() {
+        println!("{}", change);
+    }
+    if changes
+        .iter()
+        .any(|change| matches!(change, SchemaChange::RemoveColumn(_)))
+    {
+        println!("Removing a column removes its values from every row.");
+    }
+    warn_of_removed_options(changes, columns);
+    if !yes && !confirm("Make these changes?")? {
+        println!("Nothing was changed.");
+        return Ok(());
+    }
+    alter_database(credentials, changes).await?;
+    println!("Made {} changes.", changes.len());
+    Ok(())
+}
+
+/// Splits `name<separator>value`, trimming both.
+fn split_pair<'a>(
+    text: &'a str,
+    separator: char,
+    expected: &str,
+) -> Result<(&'a str, &'a str), Box<dyn Error>> {
+    text.split_once(separator)
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .ok_or_else(|| format!("'{}' should be {}", text, expected).into())
+}
+
+async fn alter(credentials: &DatabaseCredentials, args: AlterArgs) -> Result<(), Box<dyn Error>> {
+    let mut changes = Vec::new();
+    if let Some(title) = args.title {
+        changes.push(SchemaChange::RenameDatabase(title));
+    }
+    for add in args.add.iter() {
+        let (name, column_type) = split_pair(add, ':', "Name:type")?;
+        let (column_type, relation) = match column_type.split_once(':') {
+            Some(("relation", database_id)) => (
+                "relation",
+                Some(Relation {
+                    database_id: database_id.trim().to_string(),
+                    synced_property_id: None,
+                    synced_property_name: None,
+                }),
+            ),
+            _ => (column_type, None),
+        };
+        changes.push(SchemaChange::AddColumn(Column {
+            id: String::new(),
+            name: name.to_string(),
+            column_type: column_type.to_string(),
+            relation,
+            options: Vec::new(),
+            aliases: Vec::new(),
+        }));
+    }
+    for rename in args.rename.iter() {
+        let (name, new_name) = split_pair(rename, '=', "Old=New")?;
+        changes.push(SchemaChange::RenameColumn {
+            name: name.to_string(),
+            new_name: new_name.to_string(),
+        });
+    }
+    for name in args.remove.iter() {
+        changes.push(SchemaChange::RemoveColumn(name.trim().to_string()));
+    }
+    for options in args.options.iter() {
+        let (name, options) = split_pair(options, '=', "Name=A,B,C")?;
+        changes.push(SchemaChange::SetOptions {
+            name: name.to_string(),
+            options: options
+                .split(',')
+                .map(str::trim)
+                .filter(|option| !option.is_empty())
+                .map(|option| SelectOption {
+                    id: None,
+                    name: option.to_string(),
+                    color: None,
+                })
+                .collect(),
+        });
+    }
+    if changes.is_empty() {
+        return Err("There's nothing to change.".into());
+    }
+    let live = DatabaseSchema::fetch(credentials).await?;
+    apply_changes(credentials, &changes, &live.columns, args.yes).await
+}
+
+async fn load_suppression_list(
+    credentials: &DatabaseCredentials,
+    args: &SuppressionArgs,
+) -> Result<SuppressionList, Box<dyn Error>> {
+    let mut suppression = SuppressionList::default();
+    for path in args.suppress.iter() {
+        suppression.load_file(path)?;
+    }
+    if let Some(database) = &args.suppress_database {
+        let credentials = DatabaseCredentials {
+            id: database.clone(),
+            token: credentials.token.clone(),
+        };
+        suppression
+            .load_database(
+                &credentials,
+                &args.suppress_email_column,
+                args.suppress_checkbox.as_deref(),
+            )
+            .await?;
+    }
+    if !suppression.is_empty() {
+        println!("{} addresses are suppressed.", suppression.len());
+    }
+    Ok(suppression)
+}
+
+fn preview_merge(
+    template: &MergeTemplate,
+    options: &MergeOptions,
+    suppression: &SuppressionList,
+    columns: &[Column],
+    rows: &[Row],
+) -> Result<(), Box<dyn Error>> {
+    let unknown = unknown_columns(template, options, columns);
+
+    let mut sendable = 0;
+    let mut with_problems = 0;
+    for row in rows.iter() {
+        let preview = preview_row(template, row, options);
+        println!("{}", "=".repeat(28));
+        println!("Row {}", preview.row_id);
+        for (name, value) in preview.headers.iter() {
+            println!("{}: {}", name, value);
+        }
+        println!("\n{}", preview.body.trim_end());
+
+        if !preview.attachments.is_empty() {
+            println!("\n📎 {}", preview.attachments.join(", "));
+        }
+
+        let suppressed = recipient(row, options)
+            .ok()
+            .and_then(|recipient| suppression.reason(recipient.email.as_ref()));
+        if let Some(reason) = suppressed {
+            println!("\n🚫 Skipped: {}", reason);
+        }
+        if let Some(err) = &preview.error {
+            println!("\n⚠️  {}", err);
+        }
+        for path in preview.missing_files.iter() {
+            println!("\n⚠️  The attachment '{}' doesn't exist", path);
+        }
+        for name in preview.empty_placeholders.iter() {
+            println!("\n⚠️  '{}' is empty for this row", name);
+        }
+        if preview.error.is_none() && preview.missing_files.is_empty() && suppressed.is_none() {
+            sendable += 1;
+        }
+        if preview.error.is_some()
+            || !preview.empty_placeholders.is_empty()
+            || !preview.missing_files.is_empty()
+        {
+            with_problems += 1;
+        }
+    }
+
+    println!("{}\n", "=".repeat(28));
+    for name in unknown.iter() {
+        println!(
+            "⚠️  The template refers to '{}', which isn't a column in the database.",
+            name
+        );
+    }
+    println!(
+        "{} of {} messages would go out; {} rows have problems.",
+        sendable,
+        rows.len(),
+        with_problems
+    );
+    Ok(())
+}
+
+async fn interactive(credentials: &DatabaseCredentials) -> Result<(), Box<dyn Error>> {
+    let db = fetch_notion_database(credentials).await?;
     let columns = get_db_columns(&db.body)?;
 
     if columns.is_none() {
@@ -72,7 +1663,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             io::stdin().read_line(&mut column_to_print_name).unwrap();
             let column_to_print_name = column_to_print_name.trim().to_string();
 
-            let column_to_print = columns.iter().find(|col| col.name == column_to_print_name);
+            let column_to_print = find_column(&columns, &column_to_print_name).ok();
 
             if column_to_print_name.is_empty() {
                 if i == 0 {
@@ -113,7 +1704,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         io::stdin().read_line(&mut query_column_name).unwrap();
         let query_column_name = query_column_name.trim().to_string();
 
-        let query_column = columns.iter().find(|col| col.name == query_column_name);
+        let query_column = find_column(&columns, &query_column_name).ok();
         if query_column.is_none() {
             println!("The column '{}' does not exist.", query_column_name);
             continue;
@@ -196,7 +1787,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
 
         filter = ColumnFilter {
-            property: query_column.name.clone(),
+            property: query_column.reference().to_string(),
             ..Default::default()
         };
 
@@ -272,11 +1863,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     print!("\nFetching data from Notion...");
     io::stdout().flush().unwrap();
-    let columns_and_values = query_column_values(&credentials, &columns_to_print, &query).await?;
+    let columns_and_values = query_column_values(credentials, &columns_to_print, &query).await?;
     print!("\r{}\n\n", "=".repeat(28));
     for row in columns_and_values.iter() {
         for column in columns_to_print.iter() {
-            println!("{}: {}", column.name, row.get(&column.name).unwrap());
+            let value = row.get(&column.name).map(|block| block.to_string());
+            println!("{}: {}", column.name, value.unwrap_or_default());
         }
         println!();
     }
@@ -0,0 +1,454 @@
+use core::fmt;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use lettre::{
+    message::{
+        header::{ContentType, HeaderName, HeaderValue},
+        Mailbox, MultiPart, SinglePart,
+    },
+    Message,
+};
+
+use crate::{
+    models::database::{Column, Row},
+    template::{Template, TemplateError},
+};
+
+use attachments::{local_paths, Attachment};
+use suppression::unsubscribe_token;
+
+pub mod attachments;
+pub mod ledger;
+pub mod smtp;
+pub mod suppression;
+
+/// An email template: `Header: value` lines, a blank line, then the body.
+/// Headers and body can both use placeholders.
+#[derive(Debug, Clone)]
+pub struct MergeTemplate {
+    pub headers: Vec<(String, Template)>,
+    pub body: Template,
+}
+
+#[derive(Debug)]
+pub struct MergeOptions {
+    /// Column holding each recipient's email address.
+    pub email_column: String,
+    /// Column holding each recipient's name, shown alongside their address.
+    pub name_column: Option<String>,
+    /// Sender used when the template has no `From` header.
+    pub from: Option<Mailbox>,
+    /// `files` column whose files are attached to each row's message.
+    pub files_column: Option<String>,
+    /// Local files to attach, e.g. `invoices/{{Invoice ID}}.pdf`.
+    pub attachment_paths: Vec<Template>,
+    /// Directory `attachment_paths` are relative to and have to stay inside.
+    pub attachment_dir: PathBuf,
+    /// Makes `{{unsubscribe_token}}` and `{{unsubscribe_url}}` available to templates.
+    pub unsubscribe: Option<Unsubscribe>,
+}
+
+#[derive(Debug)]
+pub struct Unsubscribe {
+    /// Key the per-recipient tokens are derived from.
+    pub secret: String,
+    /// Link to the unsubscribe page, e.g. `https://example.com/unsubscribe?t={{unsubscribe_token}}`.
+    /// Also sent as a `List-Unsubscribe` header.
+    pub url: Option<Template>,
+}
+
+#[derive(Debug)]
+pub struct MergedMessage {
+    pub row_id: String,
+    pub recipient: Mailbox,
+    pub message: Message,
+}
+
+/// A row's message rendered for reading in the terminal, along with anything
+/// that looks wrong with it.
+#[derive(Debug)]
+pub struct Preview {
+    pub row_id: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Placeholders that rendered as nothing.
+    pub empty_placeholders: Vec<String>,
+    /// Names of the files that would be attached.
+    pub attachments: Vec<String>,
+    /// Local attachment paths that don't exist.
+    pub missing_files: Vec<String>,
+    /// Why the message couldn't be built, if it couldn't.
+    pub error: Option<MergeError>,
+}
+
+#[derive(Debug)]
+pub enum MergeError {
+    MissingAddress { row_id: String },
+    InvalidAddress { row_id: String, address: String },
+    MissingSender,
+    InvalidHeader(String),
+    Attachment { row_id: String, message: String },
+    Email(lettre::error::Error),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::MissingAddress { row_id } => {
+                write!(f, "Row {} has no email address", row_id)
+            }
+            MergeError::InvalidAddress { row_id, address } => {
+                write!(
+                    f,
+                    "Row {} has an invalid email address '{}'",
+                    row_id, address
+                )
+            }
+            MergeError::MissingSender => write!(
+                f,
+                "Messages need a sender, either from a 'From' header or --from"
+            ),
+            MergeError::InvalidHeader(name) => write!(f, "'{}' isn't a valid header name", name),
+            MergeError::Attachment { row_id, message } => write!(f, "Row {}: {}", row_id, message),
+            MergeError::Email(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+impl From<lettre::error::Error> for MergeError {
+    fn from(err: lettre::error::Error) -> Self {
+        MergeError::Email(err)
+    }
+}
+
+fn is_header_line(line: &str) -> bool {
+    line.split_once(':').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+impl MergeTemplate {
+    pub fn parse(source: &str) -> Result<MergeTemplate, TemplateError> {
+        let source = source.replace("\r\n", "\n");
+        let (header_block, body) = match source.split_once("\n\n") {
+            Some((header_block, body)) if header_block.lines().all(is_header_line) => {
+                (header_block, body)
+            }
+            _ => ("", source.as_str()),
+        };
+
+        let headers = header_block
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                let (name, value) = line.split_once(':').unwrap();
+                Template::parse(value.trim())
+                    .map(|template| (name.trim().to_string(), template))
+                    .map_err(|err| TemplateError { line: i + 1, ..err })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let body_offset = header_block.lines().count() + usize::from(!header_block.is_empty());
+        let body = Template::parse(body).map_err(|err| TemplateError {
+            line: err.line + body_offset,
+            ..err
+        })?;
+
+        Ok(MergeTemplate { headers, body })
+    }
+
+    /// Every column referred to in the headers or body.
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for template in self
+            .headers
+            .iter()
+            .map(|(_, template)| template)
+            .chain(std::iter::once(&self.body))
+        {
+            for name in template.placeholders() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+}
+
+/// The row's recipient, taken from the email and name columns.
+pub fn recipient(row: &Row, options: &MergeOptions) -> Result<Mailbox, MergeError> {
+    let values = row.template_values();
+
+    let address = values
+        .get(&options.email_column)
+        .map(|block| block.plain_text().trim().to_string())
+        .filter(|address| !address.is_empty())
+        .ok_or_else(|| MergeError::MissingAddress {
+            row_id: row.id.clone(),
+        })?;
+    let name = options
+        .name_column
+        .as_ref()
+        .and_then(|column| values.get(column))
+        .map(|block| block.plain_text())
+        .filter(|name| !name.trim().is_empty());
+    Ok(Mailbox::new(
+        name,
+        address.parse().map_err(|_| MergeError::InvalidAddress {
+            row_id: row.id.clone(),
+            address: address.clone(),
+        })?,
+    ))
+}
+
+/// Values templates can use besides the row's columns.
+fn variables(
+    row: &Row,
+    recipient: Option<&Mailbox>,
+    options: &MergeOptions,
+) -> HashMap<String, String> {
+    let mut variables = HashMap::new();
+    if let (Some(unsubscribe), Some(recipient)) = (&options.unsubscribe, recipient) {
+        variables.insert(
+            "unsubscribe_token".to_string(),
+            unsubscribe_token(&unsubscribe.secret, recipient.email.as_ref()),
+        );
+        if let Some(url) = &unsubscribe.url {
+            let (url, _) = url.render_with(&row.template_values(), &variables);
+            variables.insert("unsubscribe_url".to_string(), url);
+        }
+    }
+    variables
+}
+
+/// Renders the message for a single row, attaching `attachments` to it.
+pub fn merge_row(
+    template: &MergeTemplate,
+    row: &Row,
+    options: &MergeOptions,
+    attachments: &[Attachment],
+) -> Result<MergedMessage, MergeError> {
+    let recipient = recipient(row, options)?;
+    let variables = variables(row, Some(&recipient), options);
+    let values = row.template_values_with(&variables);
+
+    let mut builder = Message::builder().to(recipient.clone()).message_id(None);
+    let mut has_sender = false;
+    let mut has_list_unsubscribe = false;
+
+    for (name, template) in template.headers.iter() {
+        let (value, _) = template.render_with(&values, &variables);
+        let mailbox = || {
+            value
+                .parse::<Mailbox>()
+                .map_err(|_| MergeError::InvalidAddress {
+                    row_id: row.id.clone(),
+                    address: value.clone(),
+                })
+        };
+
+        builder = match name.to_lowercase().as_str() {
+            "subject" => builder.subject(value.clone()),
+            "from" => {
+                has_sender = true;
+                builder.from(mailbox()?)
+            }
+            "reply-to" => builder.reply_to(mailbox()?),
+            "cc" => builder.cc(mailbox()?),
+            "bcc" => builder.bcc(mailbox()?),
+            // The recipient always comes from the email column.
+            "to" => builder,
+            header => {
+                has_list_unsubscribe |= header == "list-unsubscribe";
+                builder.raw_header(HeaderValue::new(
+                    HeaderName::new_from_ascii(name.clone())
+                        .map_err(|_| MergeError::InvalidHeader(name.clone()))?,
+                    value.clone(),
+                ))
+            }
+        };
+    }
+
+    if !has_sender {
+        builder = builder.from(options.from.clone().ok_or(MergeError::MissingSender)?);
+    }
+    if let (false, Some(url)) = (has_list_unsubscribe, variables.get("unsubscribe_url")) {
+        builder = builder.raw_header(HeaderValue::new(
+            HeaderName::new_from_ascii_str("List-Unsubscribe"),
+            format!("<{}>", url),
+        ));
+    }
+
+    let (body, _) = template.body.render_with(&values, &variables);
+    let message = if attachments.is_empty() {
+        builder.header(ContentType::TEXT_PLAIN).body(body)?
+    } else {
+        let mut multipart = MultiPart::mixed().singlepart(SinglePart::plain(body));
+        for attachment in attachments.iter() {
+            let content_type = ContentType::parse(&attachment.content_type)
+                .unwrap_or_else(|_| ContentType::parse("application/octet-stream").unwrap());
+            multipart = multipart.singlepart(
+                lettre::message::Attachment::new(attachment.filename.clone())
+                    .body(attachment.content.clone(), content_type),
+            );
+        }
+        builder.multipart(multipart)?
+    };
+
+    Ok(MergedMessage {
+        row_id: row.id.clone(),
+        recipient,
+        message,
+    })
+}
+
+pub fn preview_row(template: &MergeTemplate, row: &Row, options: &MergeOptions) -> Preview {
+    let variables = variables(row, recipient(row, options).ok().as_ref(), options);
+    let values = row.template_values_with(&variables);
+    let mut empty_placeholders = Vec::new();
+    let mut headers = Vec::new();
+
+    let (paths, path_error) =
+        match local_paths(&options.attachment_paths, row, &options.attachment_dir) {
+            Ok(paths) => (
+                paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<String>>(),
+                None,
+            ),
+            Err(err) => (Vec::new(), Some(err)),
+        };
+    let missing_files = paths
+        .iter()
+        .filter(|path| !Path::new(path).exists())
+        .cloned()
+        .collect();
+    let attachments = options
+        .files_column
+        .as_ref()
+        .and_then(|column| values.get(column))
+        .map(|block| block.items())
+        .unwrap_or_default()
+        .into_iter()
+        .chain(paths)
+        .collect();
+
+    let merged = merge_row(template, row, options, &[]);
+    if let Ok(merged) = &merged {
+        headers.push(("To".to_string(), merged.recipient.to_string()));
+    }
+    for (name, header) in template.headers.iter() {
+        let (value, empty) = header.render_with(&values, &variables);
+        headers.push((name.clone(), value));
+        empty_placeholders.extend(empty);
+    }
+    let (body, empty) = template.body.render_with(&values, &variables);
+    empty_placeholders.extend(empty);
+
+    let mut seen = Vec::new();
+    empty_placeholders.retain(|name| {
+        let first = !seen.contains(name);
+        seen.push(name.clone());
+        first
+    });
+
+    Preview {
+        row_id: row.id.clone(),
+        headers,
+        body,
+        empty_placeholders,
+        attachments,
+        missing_files,
+        error: merged.err().or(path_error),
+    }
+}
+
+/// Columns the template or options refer to that the database doesn't have.
+pub fn unknown_columns(
+    template: &MergeTemplate,
+    options: &MergeOptions,
+    columns: &[Column],
+) -> Vec<String> {
+    let unsubscribe_url = options
+        .unsubscribe
+        .as_ref()
+        .and_then(|unsubscribe| unsubscribe.url.as_ref());
+    template
+        .placeholders()
+        .into_iter()
+        .chain(std::iter::once(options.email_column.clone()))
+        .chain(options.name_column.clone())
+        .chain(options.files_column.clone())
+        .chain(
+            options
+                .attachment_paths
+                .iter()
+                .flat_map(|path| path.placeholders()),
+        )
+        .chain(
+            unsubscribe_url
+                .into_iter()
+                .flat_map(|url| url.placeholders()),
+        )
+        .filter(|name| {
+            let is_variable = options.unsubscribe.is_some()
+                && (name == "unsubscribe_token"
+                    || (name == "unsubscribe_url" && unsubscribe_url.is_some()));
+            !is_variable && !columns.iter().any(|column| column.matches(name))
+        })
+        .fold(Vec::new(), |mut unknown, name| {
+            if !unknown.contains(&name) {
+                unknown.push(name);
+            }
+            unknown
+        })
+}
+
+/// Writes each message to `<row id>.eml` in `dir`, returning the paths written.
+pub fn write_eml(dir: &Path, messages: &[MergedMessage]) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    messages
+        .iter()
+        .map(|merged| {
+            let path = dir.join(format!("{}.eml", merged.row_id));
+            fs::write(&path, merged.message.formatted())?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Writes every message into a single mbox (mboxrd) file.
+pub fn write_mbox(writer: &mut impl Write, messages: &[MergedMessage]) -> io::Result<()> {
+    let date = chrono::Utc::now().format("%a %b %e %H:%M:%S %Y");
+
+    for merged in messages.iter() {
+        let sender = merged
+            .message
+            .envelope()
+            .from()
+            .map(|address| address.to_string())
+            .unwrap_or_else(|| "MAILER-DAEMON".to_string());
+        writeln!(writer, "From {} {}", sender, date)?;
+
+        let formatted = String::from_utf8_lossy(&merged.message.formatted()).replace("\r\n", "\n");
+        for line in formatted.lines() {
+            // mboxrd quotes any line that would otherwise look like a separator.
+            if line.trim_start_matches('>').starts_with("From ") {
+                write!(writer, ">")?;
+            }
+            writeln!(writer, "{}", line)?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
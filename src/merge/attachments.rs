@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use reqwest::Client;
+
+use crate::{
+    models::{blocks::Blocks, database::Row},
+    render::escape_path,
+    template::Template,
+};
+
+use super::MergeError;
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
+}
+
+/// A best guess at a file's MIME type from its extension.
+pub fn content_type_for(filename: &str) -> &'static str {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "ics" => "text/calendar",
+        "zip" => "application/zip",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Resolves `.` and `..` in `path` without touching the file system.
+fn normalise(path: &Path) -> PathBuf {
+    let mut normalised = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalised.pop();
+            }
+            component => normalised.push(component),
+        }
+    }
+    normalised
+}
+
+/// The local files `paths` point to once filled in from the row's values,
+/// relative to `dir`. Values can't add directories to a path, and a path that
+/// ends up outside `dir` is an error.
+pub fn local_paths(paths: &[Template], row: &Row, dir: &Path) -> Result<Vec<PathBuf>, MergeError> {
+    let values = row.template_values();
+    let dir = normalise(&std::env::current_dir().unwrap_or_default().join(dir));
+    paths
+        .iter()
+        .map(|path| {
+            let rendered = path.render_escaped(&values, &HashMap::new(), escape_path);
+            let path = normalise(&dir.join(&rendered));
+            // Symlinks can still point elsewhere, so files that exist are
+            // checked again once they're resolved.
+            let resolved = match (path.canonicalize(), dir.canonicalize()) {
+                (Ok(path), Ok(dir)) => path.starts_with(dir),
+                _ => true,
+            };
+            if path.starts_with(&dir) && resolved {
+                Ok(path)
+            } else {
+                Err(MergeError::Attachment {
+                    row_id: row.id.clone(),
+                    message: format!(
+                        "'{}' is outside the attachment directory '{}'",
+                        rendered,
+                        dir.display()
+                    ),
+                })
+            }
+        })
+        .collect()
+}
+
+/// Downloads the files in the row's `files_column` (whether uploaded to Notion
+/// or linked from elsewhere) and reads the local files `paths` point to in `dir`.
+pub async fn collect_attachments(
+    client: &Client,
+    row: &Row,
+    files_column: Option<&str>,
+    paths: &[Template],
+    dir: &Path,
+) -> Result<Vec<Attachment>, MergeError> {
+    let error = |message: String| MergeError::Attachment {
+        row_id: row.id.clone(),
+        message,
+    };
+    let mut attachments = Vec::new();
+
+    let files = files_column
+        .and_then(|column| row.template_values().remove(column))
+        .map(|block| match block {
+            Blocks::Files(files) => files,
+            _ => Vec::new(),
+        })
+        .unwrap_or_default();
+
+    for file in files.iter() {
+        let url = file
+            .url()
+            .ok_or_else(|| error(format!("'{}' has no URL", file.name)))?;
+        let response = client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| error(format!("Couldn't download '{}': {}", file.name, err)))?;
+        let content = response
+            .bytes()
+            .await
+            .map_err(|err| error(format!("Couldn't download '{}': {}", file.name, err)))?;
+
+        attachments.push(Attachment {
+            filename: file.name.clone(),
+            content_type: content_type_for(&file.name).to_string(),
+            content: content.to_vec(),
+        });
+    }
+
+    for path in local_paths(paths, row, dir)? {
+        let content = fs::read(&path)
+            .map_err(|err| error(format!("Couldn't read '{}': {}", path.display(), err)))?;
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.display().to_string());
+
+        attachments.push(Attachment {
+            content_type: content_type_for(&filename).to_string(),
+            filename,
+            content,
+        });
+    }
+
+    Ok(attachments)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::database::fixtures::{self, text};
+
+    fn row(reference: &str) -> Row {
+        fixtures::row(json!({
+            "Reference": { "id": "r", "type": "rich_text", "rich_text": text(&[reference]) },
+        }))
+    }
+
+    fn paths(sources: &[&str]) -> Vec<Template> {
+        sources
+            .iter()
+            .map(|source| Template::parse(source).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn values_cant_climb_out_of_the_attachment_directory() {
+        let dir = Path::new("/srv/invoices");
+        let attached = local_paths(
+            &paths(&["{{Reference}}.pdf"]),
+            &row("../../.ssh/id_rsa"),
+            dir,
+        )
+        .unwrap();
+        assert_eq!(
+            attached,
+            vec![PathBuf::from("/srv/invoices/.._.._.ssh_id_rsa.pdf")]
+        );
+
+        let attached = local_paths(&paths(&["{{Reference}}"]), &row(".."), dir).unwrap();
+        assert_eq!(attached, vec![PathBuf::from("/srv/invoices/_")]);
+    }
+
+    #[test]
+    fn paths_outside_the_attachment_directory_are_refused() {
+        let dir = Path::new("/srv/invoices");
+        for path in ["../{{Reference}}.pdf", "/etc/{{Reference}}"] {
+            assert!(matches!(
+                local_paths(&paths(&[path]), &row("passwd"), dir),
+                Err(MergeError::Attachment { .. })
+            ));
+        }
+        assert_eq!(
+            local_paths(&paths(&["2024/./{{Reference}}.pdf"]), &row("a"), dir).unwrap(),
+            vec![PathBuf::from("/srv/invoices/2024/a.pdf")]
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// A record of which rows have already been sent each campaign's message,
+/// so an interrupted run can be picked up again without sending duplicates.
+pub struct Ledger {
+    conn: Connection,
+}
+
+impl Ledger {
+    pub fn open(path: &Path) -> rusqlite::Result<Ledger> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS sent (
+                campaign TEXT NOT NULL,
+                row_id TEXT NOT NULL,
+                recipient TEXT NOT NULL,
+                sent_at TEXT NOT NULL,
+                PRIMARY KEY (campaign, row_id)
+            );",
+        )?;
+        Ok(Ledger { conn })
+    }
+
+    pub fn contains(&self, campaign: &str, row_id: &str) -> rusqlite::Result<bool> {
+        self.conn
+            .query_row(
+                "SELECT 1 FROM sent WHERE campaign = ?1 AND row_id = ?2",
+                params![campaign, row_id],
+                |_| Ok(()),
+            )
+            .optional()
+            .map(|found| found.is_some())
+    }
+
+    pub fn record(&self, campaign: &str, row_id: &str, recipient: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO sent (campaign, row_id, recipient, sent_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![campaign, row_id, recipient, chrono::Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_campaign_is_remembered_separately() {
+        let ledger = Ledger::open(Path::new(":memory:")).unwrap();
+        ledger.record("launch", "p1", "ada@example.com").unwrap();
+        assert!(ledger.contains("launch", "p1").unwrap());
+        assert!(!ledger.contains("launch", "p2").unwrap());
+        assert!(!ledger.contains("follow-up", "p1").unwrap());
+
+        // Recording a row again, e.g. after a resend, isn't an error.
+        ledger.record("launch", "p1", "ada@example.com").unwrap();
+        assert!(ledger.contains("launch", "p1").unwrap());
+    }
+
+    #[test]
+    fn the_ledger_survives_being_reopened() {
+        let path = std::env::temp_dir().join(format!("margaret-ledger-{}.db", std::process::id()));
+        Ledger::open(&path)
+            .unwrap()
+            .record("launch", "p1", "ada@example.com")
+            .unwrap();
+        let reopened = Ledger::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(reopened.contains("launch", "p1").unwrap());
+    }
+}
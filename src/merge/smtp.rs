@@ -0,0 +1,211 @@
+use std::{collections::HashMap, error::Error, fmt::Display, time::Duration};
+
+use crate::models::{
+    database::DatabaseCredentials,
+    pages::{update_page, PropertyValue},
+};
+use clap::ValueEnum;
+use lettre::{
+    transport::smtp::{authentication::Credentials, Error as SmtpError},
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+
+use super::{ledger::Ledger, MergedMessage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SmtpSecurity {
+    /// Upgrade a plain connection with STARTTLS
+    Starttls,
+    /// Connect over TLS from the start
+    Tls,
+    /// No encryption, e.g. for a local SMTP sink
+    None,
+}
+
+#[derive(Debug)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SendOptions {
+    /// Name of this mailing, so rows can be sent different messages over time.
+    pub campaign: String,
+    /// Most messages to send in any one minute.
+    pub per_minute: Option<u32>,
+    /// Checkbox column to tick on each row once its message is sent.
+    pub mark_sent: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SendReport {
+    pub sent: usize,
+    /// Rows the ledger says were already sent this campaign's message.
+    pub already_sent: usize,
+    pub failed: Vec<(String, String)>,
+    /// Rows that were sent their message, but whose `mark_sent` checkbox
+    /// couldn't be ticked.
+    pub not_marked: Vec<(String, String)>,
+}
+
+pub fn transport(config: &SmtpConfig) -> Result<AsyncSmtpTransport<Tokio1Executor>, SmtpError> {
+    let mut builder = match config.security {
+        SmtpSecurity::Starttls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+        }
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+    };
+    if let Some(port) = config.port {
+        builder = builder.port(port);
+    }
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(builder.build())
+}
+
+/// How long to wait between messages to send no more than `per_minute`.
+fn delay(per_minute: Option<u32>) -> Option<Duration> {
+    per_minute
+        .filter(|per_minute| *per_minute > 0)
+        .map(|per_minute| Duration::from_secs(60) / per_minute)
+}
+
+/// Sends each message that the ledger hasn't seen before, recording it once
+/// sent. Failures are collected in the report rather than stopping the run.
+pub async fn send_messages<T>(
+    transport: &T,
+    credentials: &DatabaseCredentials,
+    messages: &[MergedMessage],
+    ledger: &Ledger,
+    options: &SendOptions,
+) -> Result<SendReport, Box<dyn Error>>
+where
+    T: AsyncTransport + Sync,
+    T::Error: Display,
+{
+    let mut report = SendReport::default();
+    let delay = delay(options.per_minute);
+
+    for merged in messages.iter() {
+        if ledger.contains(&options.campaign, &merged.row_id)? {
+            report.already_sent += 1;
+            continue;
+        }
+        if let (Some(delay), true) = (delay, report.sent > 0) {
+            tokio::time::sleep(delay).await;
+        }
+
+        if let Err(err) = transport.send(merged.message.clone()).await {
+            report.failed.push((merged.row_id.clone(), err.to_string()));
+            continue;
+        }
+        ledger.record(
+            &options.campaign,
+            &merged.row_id,
+            merged.recipient.email.as_ref(),
+        )?;
+        report.sent += 1;
+
+        if let Some(column) = &options.mark_sent {
+            let properties = HashMap::from([(column.clone(), PropertyValue::Checkbox(true))]);
+            if let Err(err) = update_page(&credentials.token, &merged.row_id, &properties).await {
+                report
+                    .not_marked
+                    .push((merged.row_id.clone(), err.to_string()));
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use lettre::{message::Mailbox, transport::stub::AsyncStubTransport, Message};
+
+    use super::*;
+
+    fn merged(row_id: &str) -> MergedMessage {
+        let recipient: Mailbox = format!("{}@example.com", row_id).parse().unwrap();
+        MergedMessage {
+            row_id: row_id.to_string(),
+            message: Message::builder()
+                .from("me@example.com".parse().unwrap())
+                .to(recipient.clone())
+                .subject("Hello")
+                .body("Hi!".to_string())
+                .unwrap(),
+            recipient,
+        }
+    }
+
+    async fn send(
+        transport: &AsyncStubTransport,
+        ledger: &Ledger,
+        row_ids: &[&str],
+        per_minute: Option<u32>,
+    ) -> SendReport {
+        let credentials = DatabaseCredentials {
+            id: "d1".to_string(),
+            token: "secret".to_string(),
+        };
+        let options = SendOptions {
+            campaign: "launch".to_string(),
+            per_minute,
+            mark_sent: None,
+        };
+        let messages = row_ids.iter().map(|id| merged(id)).collect::<Vec<_>>();
+        send_messages(transport, &credentials, &messages, ledger, &options)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rows_in_the_ledger_arent_sent_again() {
+        let ledger = Ledger::open(Path::new(":memory:")).unwrap();
+        ledger.record("launch", "p1", "p1@example.com").unwrap();
+        let transport = AsyncStubTransport::new_ok();
+
+        let report = send(&transport, &ledger, &["p1", "p2"], None).await;
+        assert_eq!((report.sent, report.already_sent), (1, 1));
+        assert_eq!(transport.messages().await.len(), 1);
+        assert!(ledger.contains("launch", "p2").unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_sends_arent_recorded() {
+        let ledger = Ledger::open(Path::new(":memory:")).unwrap();
+        let transport = AsyncStubTransport::new_error();
+
+        let report = send(&transport, &ledger, &["p1"], None).await;
+        assert_eq!(report.sent, 0);
+        assert_eq!(report.failed.len(), 1);
+        assert!(!ledger.contains("launch", "p1").unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_are_spaced_out_to_stay_under_the_rate() {
+        let ledger = Ledger::open(Path::new(":memory:")).unwrap();
+        let transport = AsyncStubTransport::new_ok();
+
+        let start = tokio::time::Instant::now();
+        let report = send(&transport, &ledger, &["p1", "p2", "p3"], Some(30)).await;
+        assert_eq!(report.sent, 3);
+        // Only between messages, so the first goes straight away.
+        assert_eq!(start.elapsed(), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn rates_become_the_gap_between_messages() {
+        assert_eq!(delay(Some(120)), Some(Duration::from_millis(500)));
+        assert_eq!(delay(Some(0)), None);
+        assert_eq!(delay(None), None);
+    }
+}
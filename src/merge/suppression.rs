@@ -0,0 +1,139 @@
+use std::{collections::HashMap, error::Error, fs, io, path::Path};
+
+use hmac::{Hmac, Mac};
+use lettre::message::Mailbox;
+use sha2::Sha256;
+
+use crate::{
+    get_db_columns,
+    models::{
+        database::{
+            fetch_notion_database, find_column, query_notion_database, DatabaseCredentials,
+        },
+        filters::{CheckboxColumnFilter, ColumnFilter, QueryFilter},
+    },
+};
+
+/// Addresses that must never be mailed, each with the reason it's listed.
+/// Addresses are compared case-insensitively.
+#[derive(Debug, Default)]
+pub struct SuppressionList {
+    reasons: HashMap<String, String>,
+}
+
+fn normalise(address: &str) -> String {
+    let address = address.trim();
+    address
+        .parse::<Mailbox>()
+        .map(|mailbox| mailbox.email.to_string())
+        .unwrap_or_else(|_| address.to_string())
+        .to_lowercase()
+}
+
+impl SuppressionList {
+    fn insert(&mut self, address: &str, reason: String) -> bool {
+        let address = normalise(address);
+        if address.is_empty() {
+            return false;
+        }
+        self.reasons.entry(address).or_insert(reason);
+        true
+    }
+
+    /// Adds the addresses in a file, one per line. Blank lines and lines
+    /// starting with `#` are ignored, as is anything after the first comma,
+    /// so the first column of a CSV export works too.
+    pub fn load_file(&mut self, path: &Path) -> io::Result<usize> {
+        let mut added = 0;
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let address = line.split(',').next().unwrap_or_default();
+            if self.insert(address, format!("listed in {}:{}", path.display(), i + 1)) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// Adds the addresses in `email_column` of another database, only taking
+    /// rows with `checkbox_column` ticked if one is given. Columns can be
+    /// given by name or id, and it's an error if either doesn't exist, since
+    /// a list that quietly suppresses no one would mail everyone.
+    pub async fn load_database(
+        &mut self,
+        credentials: &DatabaseCredentials,
+        email_column: &str,
+        checkbox_column: Option<&str>,
+    ) -> Result<usize, Box<dyn Error>> {
+        let db = fetch_notion_database(credentials).await?;
+        let columns = get_db_columns(&db.body)?.unwrap_or_default();
+        let find = |reference: &str| {
+            find_column(&columns, reference).ok_or_else(|| {
+                format!(
+                    "The column '{}' does not exist in database {}.",
+                    reference, credentials.id
+                )
+            })
+        };
+        let email_column = find(email_column)?;
+        let checkbox_column = checkbox_column.map(find).transpose()?;
+
+        let filter = checkbox_column.map(|column| {
+            QueryFilter::ColumnFilter(Box::new(ColumnFilter {
+                property: column.reference().to_string(),
+                checkbox: Some(CheckboxColumnFilter {
+                    equals: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }))
+        });
+        let rows = query_notion_database(credentials, filter.as_ref()).await?;
+
+        let mut added = 0;
+        for row in rows.iter() {
+            let Some(address) = row.block(email_column).map(|block| block.plain_text()) else {
+                continue;
+            };
+            let reason = match checkbox_column {
+                Some(column) => {
+                    format!("'{}' is ticked in database {}", column.name, credentials.id)
+                }
+                None => format!("listed in database {}", credentials.id),
+            };
+            if self.insert(&address, reason) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// Why `address` is suppressed, or `None` if it can be mailed.
+    pub fn reason(&self, address: &str) -> Option<&str> {
+        self.reasons.get(&normalise(address)).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.reasons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reasons.is_empty()
+    }
+}
+
+/// A token identifying `address` that can't be forged without `secret`, for
+/// unsubscribe links. The same address always gets the same token.
+pub fn unsubscribe_token(secret: &str, address: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(normalise(address).as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
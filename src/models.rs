@@ -1,5 +1,7 @@
 pub mod blocks;
+pub mod content;
 pub mod database;
 pub mod filters;
+pub mod pages;
 pub mod responses;
 pub mod users;
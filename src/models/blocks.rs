@@ -1,11 +1,12 @@
 use core::fmt;
 
-use serde::Deserialize;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use crate::models::users::User;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Blocks {
     #[serde(rename = "rich_text")]
     RichText(Vec<RichText>),
@@ -25,6 +26,70 @@ pub enum Blocks {
     Number(f32),
     #[serde(rename = "relation")]
     Relation(Vec<RelationBlock>),
+    #[serde(rename = "url")]
+    Url(String),
+    #[serde(rename = "files")]
+    Files(Vec<FileBlock>),
+    /// `None` when the date hasn't been set.
+    #[serde(rename = "date")]
+    Date(Option<DateBlock>),
+    /// `None` when no number has been entered.
+    #[serde(rename = "phone_number")]
+    PhoneNumber(Option<String>),
+    /// `None` when no option has been chosen.
+    #[serde(rename = "select")]
+    Select(Option<MultiSelectSelection>),
+}
+
+impl Blocks {
+    /// The individual values held by list-like blocks (multi-selects,
+    /// relations and files), or the block's single value otherwise.
+    pub fn items(&self) -> Vec<String> {
+        match self {
+            Blocks::MultiSelect(selections) => selections
+                .iter()
+                .map(|selection| selection.name.clone())
+                .collect(),
+            Blocks::Relation(ids) => ids.iter().map(|ids| ids.id.clone()).collect(),
+            Blocks::Files(files) => files.iter().map(|file| file.name.clone()).collect(),
+            block if block.is_empty() => Vec::new(),
+            block => vec![block.plain_text()],
+        }
+    }
+
+    /// The block's value as a single line of text. Unlike `Display`, title and
+    /// rich text segments (runs of bold text, links, mentions...) are joined
+    /// as they are rather than line by line.
+    pub fn plain_text(&self) -> String {
+        match self {
+            Blocks::RichText(texts) | Blocks::Title(texts) => {
+                texts.iter().map(|text| text.plain_text.as_str()).collect()
+            }
+            block => block.to_string(),
+        }
+    }
+
+    /// Whether the block has nothing in it. Unticked checkboxes count as empty.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Blocks::RichText(texts) | Blocks::Title(texts) => {
+                texts.iter().all(|text| text.plain_text.trim().is_empty())
+            }
+            Blocks::Checkbox(value) => !value,
+            Blocks::Email(value) | Blocks::Url(value) | Blocks::CreatedTime(value) => {
+                value.trim().is_empty()
+            }
+            Blocks::MultiSelect(selections) => selections.is_empty(),
+            Blocks::Relation(ids) => ids.is_empty(),
+            Blocks::Files(files) => files.is_empty(),
+            Blocks::Date(date) => date.as_ref().is_none_or(|date| date.start.is_empty()),
+            Blocks::PhoneNumber(value) => {
+                value.as_ref().is_none_or(|value| value.trim().is_empty())
+            }
+            Blocks::Select(selection) => selection.is_none(),
+            Blocks::CreatedBy(_) | Blocks::Number(_) => false,
+        }
+    }
 }
 
 impl fmt::Display for Blocks {
@@ -55,47 +120,63 @@ impl fmt::Display for Blocks {
                 .map(|ids| ids.id.clone())
                 .collect::<Vec<String>>()
                 .join(", "),
+            Blocks::Url(value) => value.to_string(),
+            Blocks::Date(None) => String::new(),
+            Blocks::Date(Some(date)) => match &date.end {
+                Some(end) => format!("{} → {}", date.start, end),
+                None => date.start.clone(),
+            },
+            Blocks::Files(files) => files
+                .iter()
+                .map(|file| file.name.clone())
+                .collect::<Vec<String>>()
+                .join(", "),
+            Blocks::PhoneNumber(value) => value.clone().unwrap_or_default(),
+            Blocks::Select(selection) => selection
+                .as_ref()
+                .map(|selection| selection.name.clone())
+                .unwrap_or_default(),
         };
         write!(f, "{}", value)
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
-struct RichTextAnnotations {
-    bold: bool,
-    code: bool,
-    color: String,
-    italic: bool,
-    strikethrough: bool,
-    underline: bool,
+pub struct RichTextAnnotations {
+    pub bold: bool,
+    pub code: bool,
+    pub color: String,
+    pub italic: bool,
+    pub strikethrough: bool,
+    pub underline: bool,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct RichText {
     #[serde(rename = "type")]
-    block_type: TextTypes,
+    pub block_type: TextTypes,
     #[serde(skip_serializing_if = "Option::is_none")]
     text: Option<Text>,
     #[serde(skip_serializing_if = "Option::is_none")]
     // TODO: handle mentions
     mention: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    equation: Option<Expression>,
-    annotations: RichTextAnnotations,
-    plain_text: String,
-    href: Option<String>,
+    pub equation: Option<Expression>,
+    pub annotations: RichTextAnnotations,
+    pub plain_text: String,
+    pub href: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct Text {
     content: String,
     link: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum TextTypes {
     #[serde(rename = "text")]
     Text,
@@ -105,22 +186,84 @@ pub enum TextTypes {
     Mention,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct MultiSelectSelection {
-    color: String,
-    id: String,
-    name: String,
+    pub color: String,
+    pub id: String,
+    pub name: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct Expression {
-    expression: String,
+    pub expression: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct RelationBlock {
     pub id: String,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileBlock {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub file_type: String,
+    /// Set for files uploaded to Notion, whose URLs expire after an hour.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileUrl>,
+    /// Set for files linked from elsewhere.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external: Option<FileUrl>,
+}
+
+impl FileBlock {
+    pub fn url(&self) -> Option<&str> {
+        self.file
+            .as_ref()
+            .or(self.external.as_ref())
+            .map(|file| file.url.as_str())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileUrl {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry_time: Option<String>,
+}
+
+/// A date, or a range of dates, each either `YYYY-MM-DD` or an RFC 3339 timestamp.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DateBlock {
+    pub start: String,
+    pub end: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<String>,
+}
+
+/// Parses a date property's value, taking dates without a time as midnight UTC.
+pub fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value).ok().or_else(|| {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc().fixed_offset())
+    })
+}
+
+impl DateBlock {
+    /// Whether the date has no time, i.e. it lasts all day.
+    pub fn is_all_day(&self) -> bool {
+        !self.start.contains('T')
+    }
+
+    pub fn start_time(&self) -> Option<DateTime<FixedOffset>> {
+        parse_date(&self.start)
+    }
+
+    pub fn end_time(&self) -> Option<DateTime<FixedOffset>> {
+        parse_date(self.end.as_ref()?)
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::models::blocks::{FileUrl, RichText};
+
+use super::responses::{response_to_result, ErrorResponse};
+
+/// A block of a page's body content, such as a paragraph or heading.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ContentBlock {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub block_type: String,
+    #[serde(default)]
+    pub has_children: bool,
+    /// `None` for block types we don't understand.
+    #[serde(flatten)]
+    pub content: Option<BlockContent>,
+    /// Filled in by `fetch_page_content`; the API doesn't nest children.
+    #[serde(skip)]
+    pub children: Vec<ContentBlock>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum BlockContent {
+    #[serde(rename = "paragraph")]
+    Paragraph(TextContent),
+    #[serde(rename = "heading_1")]
+    Heading1(TextContent),
+    #[serde(rename = "heading_2")]
+    Heading2(TextContent),
+    #[serde(rename = "heading_3")]
+    Heading3(TextContent),
+    #[serde(rename = "bulleted_list_item")]
+    BulletedListItem(TextContent),
+    #[serde(rename = "numbered_list_item")]
+    NumberedListItem(TextContent),
+    #[serde(rename = "to_do")]
+    ToDo(ToDoContent),
+    #[serde(rename = "code")]
+    Code(CodeContent),
+    #[serde(rename = "quote")]
+    Quote(TextContent),
+    #[serde(rename = "callout")]
+    Callout(CalloutContent),
+    #[serde(rename = "toggle")]
+    Toggle(TextContent),
+    #[serde(rename = "table")]
+    Table(TableContent),
+    #[serde(rename = "table_row")]
+    TableRow(TableRowContent),
+    #[serde(rename = "image")]
+    Image(ImageContent),
+    #[serde(rename = "divider")]
+    Divider(Value),
+    #[serde(rename = "child_page")]
+    ChildPage(ChildPageContent),
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TextContent {
+    pub rich_text: Vec<RichText>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ToDoContent {
+    pub rich_text: Vec<RichText>,
+    #[serde(default)]
+    pub checked: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CodeContent {
+    pub rich_text: Vec<RichText>,
+    #[serde(default)]
+    pub language: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CalloutContent {
+    pub rich_text: Vec<RichText>,
+    pub icon: Option<Icon>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Icon {
+    pub emoji: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TableContent {
+    pub table_width: usize,
+    #[serde(default)]
+    pub has_column_header: bool,
+    #[serde(default)]
+    pub has_row_header: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TableRowContent {
+    pub cells: Vec<Vec<RichText>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ImageContent {
+    #[serde(default)]
+    pub caption: Vec<RichText>,
+    /// Set for images uploaded to Notion, whose URLs expire after an hour.
+    pub file: Option<FileUrl>,
+    /// Set for images linked from elsewhere.
+    pub external: Option<FileUrl>,
+    /// Filled in by `download_images`: the downloaded copy of an image
+    /// uploaded to Notion, relative to the documents linking to it.
+    #[serde(skip)]
+    pub local: Option<String>,
+}
+
+impl ImageContent {
+    pub fn url(&self) -> Option<&str> {
+        self.local.as_deref().or_else(|| {
+            self.file
+                .as_ref()
+                .or(self.external.as_ref())
+                .map(|file| file.url.as_str())
+        })
+    }
+}
+
+/// The name an image block's downloaded copy is saved as: its id, and the
+/// extension of the file it was uploaded as.
+pub fn image_file_name(block_id: &str, url: &str) -> String {
+    let extension = Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            let name = url.path_segments()?.next_back()?.to_string();
+            let (_, extension) = name.rsplit_once('.')?;
+            (!extension.is_empty() && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+                .then(|| extension.to_lowercase())
+        })
+        .unwrap_or_else(|| "png".to_string());
+    format!("{}.{}", block_id, extension)
+}
+
+fn uploaded_images(blocks: &mut [ContentBlock]) -> Vec<(&str, &mut ImageContent)> {
+    let mut images = Vec::new();
+    for block in blocks.iter_mut() {
+        match &mut block.content {
+            Some(BlockContent::Image(image)) if image.file.is_some() => {
+                images.push((block.id.as_str(), image))
+            }
+            _ => images.extend(uploaded_images(&mut block.children)),
+        }
+    }
+    images
+}
+
+/// Downloads the images uploaded to Notion in `blocks` into `dir`, since their
+/// URLs expire after an hour, and links them as `<link_dir>/<file name>`
+/// instead. Images linked from elsewhere are left as they are. Returns the
+/// paths written.
+pub async fn download_images(
+    blocks: &mut [ContentBlock],
+    dir: &Path,
+    link_dir: &str,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let client = Client::new();
+    let mut written = Vec::new();
+
+    for (block_id, image) in uploaded_images(blocks) {
+        let Some(url) = image.file.as_ref().map(|file| file.url.clone()) else {
+            continue;
+        };
+        let content = client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())?
+            .bytes()
+            .await?;
+        let name = image_file_name(block_id, &url);
+        fs::create_dir_all(dir)?;
+        let path = dir.join(&name);
+        fs::write(&path, content)?;
+        image.local = Some(format!("{}/{}", link_dir, name));
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChildPageContent {
+    pub title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockChildrenResponse {
+    results: Vec<ContentBlock>,
+    #[serde(default)]
+    has_more: bool,
+    next_cursor: Option<String>,
+}
+
+/// Fetches a block's (or page's) direct children, following pagination cursors.
+pub async fn fetch_block_children(
+    token: &str,
+    block_id: &str,
+) -> Result<Vec<ContentBlock>, ErrorResponse> {
+    let client = Client::new();
+    let url = format!("https://api.notion.com/v1/blocks/{}/children", block_id);
+    let mut blocks = Vec::new();
+    let mut start_cursor: Option<String> = None;
+
+    loop {
+        let mut query = vec![("page_size", "100".to_string())];
+        if let Some(cursor) = &start_cursor {
+            query.push(("start_cursor", cursor.clone()));
+        }
+
+        let response = client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Notion-Version", "2022-06-28")
+            .query(&query)
+            .send()
+            .await;
+
+        let result = response_to_result(response.unwrap()).await?;
+        let body: BlockChildrenResponse = serde_json::from_str(&result.body).unwrap();
+        blocks.extend(body.results);
+
+        if !body.has_more || body.next_cursor.is_none() {
+            break;
+        }
+        start_cursor = body.next_cursor;
+    }
+
+    Ok(blocks)
+}
+
+/// Fetches a page's body content, recursing into nested blocks. Child pages
+/// and databases are left as they are, since they're pages of their own.
+pub async fn fetch_page_content(
+    token: &str,
+    page_id: &str,
+) -> Result<Vec<ContentBlock>, ErrorResponse> {
+    let mut blocks = fetch_block_children(token, page_id).await?;
+    for block in blocks.iter_mut() {
+        if block.has_children
+            && !matches!(block.block_type.as_str(), "child_page" | "child_database")
+        {
+            block.children = Box::pin(fetch_page_content(token, &block.id)).await?;
+        }
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn image(id: &str, image: Value) -> ContentBlock {
+        serde_json::from_value(json!({
+            "id": id,
+            "type": "image",
+            "has_children": false,
+            "image": image,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn only_uploaded_images_are_downloaded() {
+        let mut blocks = vec![
+            image(
+                "b1",
+                json!({ "type": "file", "caption": [], "file": {
+                    "url": "https://s3.us-west-2.amazonaws.com/secure/a1/Photo.JPG?X-Amz-Expires=3600",
+                    "expiry_time": "2024-01-02T04:04:00.000Z",
+                } }),
+            ),
+            image(
+                "b2",
+                json!({ "type": "external", "caption": [],
+                    "external": { "url": "https://example.com/cat.gif" } }),
+            ),
+        ];
+        let images = uploaded_images(&mut blocks);
+        assert_eq!(images.len(), 1);
+        let (id, image) = images.into_iter().next().unwrap();
+        assert_eq!(id, "b1");
+        assert_eq!(image_file_name(id, image.url().unwrap()), "b1.jpg");
+
+        // Once downloaded, the copy is linked instead.
+        image.local = Some("images/b1.jpg".to_string());
+        assert_eq!(image.url(), Some("images/b1.jpg"));
+        assert_eq!(image_file_name("b3", "https://example.com/"), "b3.png");
+    }
+}
@@ -1,24 +1,54 @@
 use std::{cmp, collections::HashMap, error::Error};
 
 use reqwest::Client;
-use serde::Deserialize;
-use serde_json::Value;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
 
 use crate::models::blocks::Blocks;
+use crate::models::filters::QueryFilter;
 use crate::models::users::User;
 
 use super::responses::{response_to_result, ErrorResponse, SimpleResponse};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct Cell {
     pub id: String,
-    #[serde(rename = "type")]
     pub cell_type: String,
-    #[serde(flatten)]
+    /// `None` for types `Blocks` can't read, or values it can't (e.g. an empty number).
     pub block: Option<Blocks>,
+    /// The property as Notion gave it, whatever its type.
+    pub raw: Value,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize)]
+struct ParsedCell {
+    id: String,
+    #[serde(rename = "type")]
+    cell_type: String,
+    #[serde(flatten)]
+    block: Option<Blocks>,
+}
+
+impl<'de> Deserialize<'de> for Cell {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Cell, D::Error> {
+        let raw = Value::deserialize(deserializer)?;
+        let parsed: ParsedCell = serde_json::from_value(raw.clone()).map_err(de::Error::custom)?;
+        Ok(Cell {
+            id: parsed.id,
+            cell_type: parsed.cell_type,
+            block: parsed.block,
+            raw,
+        })
+    }
+}
+
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.raw.serialize(serializer)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Row {
     pub archived: bool,
     pub cover: Option<Value>,
@@ -35,10 +65,84 @@ pub struct Row {
     pub url: String,
 }
 
+impl Row {
+    /// The cell for `column` in this row, found by the column's id so it's
+    /// found after the column is renamed, or by name if it has no id.
+    pub fn cell(&self, column: &Column) -> Option<&Cell> {
+        let properties = self.properties.as_ref()?;
+        if column.id.is_empty() {
+            return properties.get(&column.name);
+        }
+        properties.values().find(|cell| cell.id == column.id)
+    }
+
+    /// The value of `column` in this row, if the row has one of a type we understand.
+    pub fn block(&self, column: &Column) -> Option<&Blocks> {
+        self.cell(column)?.block.as_ref()
+    }
+
+    /// The number in `column` exactly as Notion gave it, which `Blocks::Number`
+    /// only keeps to an f32's precision.
+    pub fn number(&self, column: &Column) -> Option<f64> {
+        self.cell(column)?.raw["number"].as_f64()
+    }
+
+    /// The row's title property, if it has a non-empty one.
+    pub fn title(&self) -> Option<String> {
+        self.properties
+            .iter()
+            .flatten()
+            .find_map(|(_, cell)| match &cell.block {
+                Some(block @ Blocks::Title(_)) => Some(block.plain_text()),
+                _ => None,
+            })
+            .filter(|title| !title.trim().is_empty())
+    }
+
+    /// Every value in this row we know how to read, keyed by column name.
+    pub fn values(&self) -> HashMap<String, Blocks> {
+        self.properties
+            .iter()
+            .flatten()
+            .filter_map(|(name, cell)| Some((name.clone(), cell.block.clone()?)))
+            .collect()
+    }
+
+    /// Every value in this row we know how to read, keyed by column name and
+    /// again by column id, so templates can refer to columns in a way that
+    /// survives them being renamed.
+    pub fn template_values(&self) -> HashMap<String, Blocks> {
+        self.template_values_with(&HashMap::new())
+    }
+
+    /// Like `template_values`, for templates that also have `variables`.
+    /// Ids that are the same as a variable's name are left out, so they don't
+    /// hide it; the title column's id is always `title`.
+    pub fn template_values_with(
+        &self,
+        variables: &HashMap<String, String>,
+    ) -> HashMap<String, Blocks> {
+        let mut values = self.values();
+        for cell in self.properties.iter().flatten().map(|(_, cell)| cell) {
+            if let Some(block) = &cell.block {
+                for id in [cell.id.clone(), decode_id(&cell.id)] {
+                    if !variables.contains_key(&id) {
+                        values.entry(id).or_insert_with(|| block.clone());
+                    }
+                }
+            }
+        }
+        values
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct DatabaseQueryResponse {
     pub object: String,
     pub results: Vec<Row>,
+    #[serde(default)]
+    pub has_more: bool,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug)]
@@ -63,6 +167,89 @@ pub async fn fetch_notion_database(
     response_to_result(response.unwrap()).await
 }
 
+/// Creates a database, where `body` has its parent, title and properties in
+/// the shape Notion expects.
+pub async fn create_notion_database(
+    token: &str,
+    body: &Value,
+) -> Result<SimpleResponse, ErrorResponse> {
+    let client = Client::new();
+
+    let response = client
+        .post("https://api.notion.com/v1/databases")
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Notion-Version", "2022-06-28")
+        .json(body)
+        .send()
+        .await;
+
+    response_to_result(response.unwrap()).await
+}
+
+/// Changes a database's title or properties, where `body` is in the shape
+/// Notion expects, e.g. `{ "properties": { "Old name": { "name": "New name" } } }`.
+pub async fn update_notion_database(
+    credentials: &DatabaseCredentials,
+    body: &Value,
+) -> Result<SimpleResponse, ErrorResponse> {
+    let client = Client::new();
+    let url = format!("https://api.notion.com/v1/databases/{}", credentials.id);
+
+    let response = client
+        .patch(url)
+        .header("Authorization", format!("Bearer {}", credentials.token))
+        .header("Notion-Version", "2022-06-28")
+        .json(body)
+        .send()
+        .await;
+
+    response_to_result(response.unwrap()).await
+}
+
+/// Queries every row in the database matching `filter`, following Notion's
+/// pagination cursors until there are no more results.
+pub async fn query_notion_database(
+    credentials: &DatabaseCredentials,
+    filter: Option<&QueryFilter>,
+) -> Result<Vec<Row>, ErrorResponse> {
+    let client = Client::new();
+    let url = format!(
+        "https://api.notion.com/v1/databases/{}/query",
+        credentials.id
+    );
+    let mut rows = Vec::new();
+    let mut start_cursor: Option<String> = None;
+
+    loop {
+        let mut query_body = json!({ "page_size": 100 });
+        if let Some(filter) = filter {
+            query_body["filter"] = json!(filter);
+        }
+        if let Some(cursor) = &start_cursor {
+            query_body["start_cursor"] = json!(cursor);
+        }
+
+        let response = client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", credentials.token))
+            .header("Notion-Version", "2022-06-28")
+            .json(&query_body)
+            .send()
+            .await;
+
+        let result = response_to_result(response.unwrap()).await?;
+        let body: DatabaseQueryResponse = serde_json::from_str(&result.body).unwrap();
+        rows.extend(body.results);
+
+        if !body.has_more || body.next_cursor.is_none() {
+            break;
+        }
+        start_cursor = body.next_cursor;
+    }
+
+    Ok(rows)
+}
+
 pub async fn follow_relation(
     token: &str,
     relation: &Relation,
@@ -75,19 +262,190 @@ pub async fn follow_relation(
     Ok(res)
 }
 
-#[derive(Debug, Hash, PartialEq, cmp::Eq, Deserialize)]
+#[derive(Debug, Hash, PartialEq, cmp::Eq, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct Relation {
     pub database_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub synced_property_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub synced_property_name: Option<String>,
 }
 
-#[derive(Debug, Hash, PartialEq, cmp::Eq)]
+/// One of a `select`, `multi_select` or `status` column's options.
+#[derive(Debug, Hash, PartialEq, cmp::Eq, Serialize, Deserialize, Clone)]
+pub struct SelectOption {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Hash, PartialEq, cmp::Eq, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct Column {
+    /// Empty for columns that don't exist yet, such as those in a schema file.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub id: String,
     pub name: String,
+    #[serde(rename = "type")]
     pub column_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub relation: Option<Relation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<SelectOption>,
+    /// Names the column had before being renamed, which still refer to it
+    /// when renames are being followed.
+    #[serde(skip)]
+    pub aliases: Vec<String>,
+}
+
+/// Decodes the percent-encoding Notion uses in property ids, e.g. `%3AuBc`
+/// becomes `:uBc`.
+pub fn decode_id(id: &str) -> String {
+    let bytes = id.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = id
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap_or_else(|_| id.to_string())
+}
+
+impl Column {
+    /// Whether `reference` refers to this column, by its name, its id (as
+    /// Notion gives it or decoded), or a name it had before being renamed.
+    pub fn matches(&self, reference: &str) -> bool {
+        self.name == reference
+            || (!self.id.is_empty() && (self.id == reference || decode_id(&self.id) == reference))
+            || self.aliases.iter().any(|alias| alias == reference)
+    }
+
+    /// How to refer to the column in requests: by id, which doesn't change
+    /// when the column is renamed, or by name if it doesn't have one yet.
+    pub fn reference(&self) -> &str {
+        if self.id.is_empty() {
+            &self.name
+        } else {
+            &self.id
+        }
+    }
+}
+
+/// The column `reference` refers to, by name, id or earlier name. Names are
+/// preferred, in case one column's name is another's id.
+pub fn find_column<'a>(columns: &'a [Column], reference: &str) -> Option<&'a Column> {
+    columns
+        .iter()
+        .find(|column| column.name == reference)
+        .or_else(|| columns.iter().find(|column| column.matches(reference)))
+}
+
+/// Columns and rows for tests to be written against.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use serde_json::{json, Value};
+
+    use super::{Column, Row};
+
+    pub fn column(id: &str, name: &str, column_type: &str) -> Column {
+        Column {
+            id: id.to_string(),
+            name: name.to_string(),
+            column_type: column_type.to_string(),
+            relation: None,
+            options: Vec::new(),
+            aliases: Vec::new(),
+        }
+    }
+
+    /// A page `p1` with `properties`, as Notion returns it from a query.
+    pub fn row(properties: Value) -> Row {
+        serde_json::from_value(json!({
+            "object": "page",
+            "id": "p1",
+            "archived": false,
+            "in_trash": false,
+            "created_time": "2024-01-02T03:04:00.000Z",
+            "last_edited_time": "2024-01-02T03:04:00.000Z",
+            "url": "https://www.notion.so/p1",
+            "cover": null,
+            "icon": null,
+            "parent": null,
+            "created_by": { "object": "user", "id": "u1" },
+            "last_edited_by": { "object": "user", "id": "u1" },
+            "properties": properties,
+        }))
+        .unwrap()
+    }
+
+    /// Rich text segments, each as plain, unformatted text.
+    pub fn text(segments: &[&str]) -> Value {
+        json!(segments
+            .iter()
+            .map(|segment| json!({
+                "type": "text",
+                "text": { "content": segment, "link": null },
+                "annotations": { "bold": false, "italic": false, "strikethrough": false,
+                    "underline": false, "code": false, "color": "default" },
+                "plain_text": segment,
+                "href": null,
+            }))
+            .collect::<Vec<Value>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::fixtures::row;
+    use super::*;
+    use crate::template::Template;
+
+    #[test]
+    fn decode_id_decodes_percent_encoding() {
+        assert_eq!(decode_id("%3AuBc"), ":uBc");
+        assert_eq!(decode_id("a%3D%5Cb"), "a=\\b");
+        assert_eq!(decode_id("title"), "title");
+        // Anything that isn't an escape is left as it is.
+        assert_eq!(decode_id("50%"), "50%");
+        assert_eq!(decode_id("%zz"), "%zz");
+    }
+
+    #[test]
+    fn template_values_are_keyed_by_name_and_id() {
+        let row = row(json!({
+            "Score": { "id": "s%3D1", "type": "number", "number": 4.0 },
+        }));
+        let values = row.template_values();
+        for key in ["Score", "s%3D1", "s=1"] {
+            assert_eq!(values[key].to_string(), "4");
+        }
+    }
+
+    #[test]
+    fn ids_dont_hide_variables() {
+        let row = row(json!({
+            "Name": { "id": "title", "type": "title", "title": [] },
+        }));
+        let variables = HashMap::from([("title".to_string(), "p1".to_string())]);
+        let template = Template::parse("<h1>{{title}}</h1>{{Name}}").unwrap();
+        let (rendered, _) = template.render_with(&row.template_values_with(&variables), &variables);
+        assert_eq!(rendered, "<h1>p1</h1>");
+    }
 }
@@ -3,6 +3,8 @@ use std::collections::HashMap;
 use serde::Serialize;
 use struct_iterable::Iterable;
 
+pub mod expression;
+
 pub fn get_filter_conditions() -> HashMap<String, String> {
     vec![
         (
@@ -71,23 +73,113 @@ pub struct RelationColumnFilter {
     pub is_not_empty: Option<bool>,
 }
 
+#[derive(Debug, Serialize, Default, Iterable)]
+pub struct NumberColumnFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equals: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub does_not_equal: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub greater_than: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub less_than: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub greater_than_or_equal_to: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub less_than_or_equal_to: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_empty: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_not_empty: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Default, Iterable)]
+pub struct MultiSelectColumnFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contains: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub does_not_contain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_empty: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_not_empty: Option<bool>,
+}
+
+/// Used for both `select` and `status` columns.
+#[derive(Debug, Serialize, Default, Iterable)]
+pub struct SelectColumnFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub does_not_equal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_empty: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_not_empty: Option<bool>,
+}
+
 #[derive(Debug, Serialize, Default)]
 pub struct ColumnFilter {
     pub property: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rich_text: Option<RichTextColumnFilter>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<RichTextColumnFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<RichTextColumnFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<RichTextColumnFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub checkbox: Option<CheckboxColumnFilter>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub relation: Option<RelationColumnFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<NumberColumnFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multi_select: Option<MultiSelectColumnFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub select: Option<SelectColumnFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<SelectColumnFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<DateFilter>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct DateFilter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_or_before: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_or_after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_empty: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_not_empty: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct TimestampFilter {
+    pub timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_time: Option<DateFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_edited_time: Option<DateFilter>,
 }
 
 #[derive(Debug, Serialize)]
 pub enum QueryFilter {
     #[serde(rename = "and")]
-    And(Box<QueryFilter>, Box<QueryFilter>),
+    And(Vec<QueryFilter>),
     #[serde(rename = "or")]
-    Or(Box<QueryFilter>, Box<QueryFilter>),
+    Or(Vec<QueryFilter>),
     #[serde(untagged)]
     ColumnFilter(Box<ColumnFilter>),
+    #[serde(untagged)]
+    Timestamp(Box<TimestampFilter>),
 }
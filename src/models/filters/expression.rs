@@ -0,0 +1,388 @@
+use core::fmt;
+use std::error::Error;
+
+use crate::models::database::{find_column, Column};
+
+use super::{
+    CheckboxColumnFilter, ColumnFilter, DateFilter, MultiSelectColumnFilter, NumberColumnFilter,
+    QueryFilter, RelationColumnFilter, RichTextColumnFilter, SelectColumnFilter, TimestampFilter,
+};
+
+#[derive(Debug)]
+pub struct FilterError(pub String);
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for FilterError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Equals,
+    NotEquals,
+    GreaterThan,
+    LessThan,
+    AtLeast,
+    AtMost,
+    Contains,
+    DoesNotContain,
+    StartsWith,
+    EndsWith,
+    IsEmpty,
+    IsNotEmpty,
+}
+
+/// Operators as they're written, longest first so `>=` is found before `>`.
+/// Word operators have to be surrounded by whitespace.
+const OPERATORS: [(&str, Operator, bool); 12] = [
+    ("is not empty", Operator::IsNotEmpty, true),
+    ("is empty", Operator::IsEmpty, true),
+    ("does not contain", Operator::DoesNotContain, true),
+    ("contains", Operator::Contains, true),
+    ("starts with", Operator::StartsWith, true),
+    ("ends with", Operator::EndsWith, true),
+    ("!=", Operator::NotEquals, false),
+    (">=", Operator::AtLeast, false),
+    ("<=", Operator::AtMost, false),
+    ("=", Operator::Equals, false),
+    (">", Operator::GreaterThan, false),
+    ("<", Operator::LessThan, false),
+];
+
+/// Byte offsets of `needle` in `haystack`, ignoring ASCII case and anything
+/// inside double quotes. With `word`, matches must be whitespace-delimited.
+fn find_unquoted(haystack: &str, needle: &str, word: bool) -> Vec<usize> {
+    let mut found = Vec::new();
+    let mut quoted = false;
+    for (i, c) in haystack.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+            continue;
+        }
+        if quoted {
+            continue;
+        }
+        let Some(candidate) = haystack.get(i..i + needle.len()) else {
+            continue;
+        };
+        if !candidate.eq_ignore_ascii_case(needle) {
+            continue;
+        }
+        let before = haystack[..i].chars().next_back();
+        let after = haystack[i + needle.len()..].chars().next();
+        if word
+            && !(before.is_some_and(char::is_whitespace) && after.is_none_or(char::is_whitespace))
+        {
+            continue;
+        }
+        found.push(i);
+    }
+    found
+}
+
+fn split_unquoted<'a>(expr: &'a str, keyword: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for i in find_unquoted(expr, keyword, true) {
+        if i >= start {
+            parts.push(&expr[start..i]);
+            start = i + keyword.len();
+        }
+    }
+    parts.push(&expr[start..]);
+    parts
+}
+
+fn unquote(text: &str) -> String {
+    let text = text.trim();
+    text.strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .unwrap_or(text)
+        .to_string()
+}
+
+fn text_filter(operator: Operator, value: String) -> Option<RichTextColumnFilter> {
+    let mut filter = RichTextColumnFilter::default();
+    match operator {
+        Operator::Equals => filter.equals = Some(value),
+        Operator::NotEquals => filter.does_not_equal = Some(value),
+        Operator::Contains => filter.contains = Some(value),
+        Operator::DoesNotContain => filter.does_not_contain = Some(value),
+        Operator::StartsWith => filter.starts_with = Some(value),
+        Operator::EndsWith => filter.ends_with = Some(value),
+        Operator::IsEmpty => filter.is_empty = Some(true),
+        Operator::IsNotEmpty => filter.is_not_empty = Some(true),
+        _ => return None,
+    }
+    Some(filter)
+}
+
+fn number_filter(
+    operator: Operator,
+    value: &str,
+) -> Result<Option<NumberColumnFilter>, FilterError> {
+    let number = || {
+        value
+            .parse::<f64>()
+            .map_err(|_| FilterError(format!("'{}' isn't a number", value)))
+    };
+    let mut filter = NumberColumnFilter::default();
+    match operator {
+        Operator::Equals => filter.equals = Some(number()?),
+        Operator::NotEquals => filter.does_not_equal = Some(number()?),
+        Operator::GreaterThan => filter.greater_than = Some(number()?),
+        Operator::LessThan => filter.less_than = Some(number()?),
+        Operator::AtLeast => filter.greater_than_or_equal_to = Some(number()?),
+        Operator::AtMost => filter.less_than_or_equal_to = Some(number()?),
+        Operator::IsEmpty => filter.is_empty = Some(true),
+        Operator::IsNotEmpty => filter.is_not_empty = Some(true),
+        _ => return Ok(None),
+    }
+    Ok(Some(filter))
+}
+
+fn date_filter(operator: Operator, value: String) -> Option<DateFilter> {
+    let mut filter = DateFilter::default();
+    match operator {
+        Operator::Equals => filter.equals = Some(value),
+        Operator::GreaterThan => filter.after = Some(value),
+        Operator::LessThan => filter.before = Some(value),
+        Operator::AtLeast => filter.on_or_after = Some(value),
+        Operator::AtMost => filter.on_or_before = Some(value),
+        Operator::IsEmpty => filter.is_empty = Some(true),
+        Operator::IsNotEmpty => filter.is_not_empty = Some(true),
+        _ => return None,
+    }
+    Some(filter)
+}
+
+fn parse_condition(condition: &str, columns: &[Column]) -> Result<QueryFilter, FilterError> {
+    let (start, text, operator) = OPERATORS
+        .iter()
+        .filter_map(|(text, operator, word)| {
+            let start = *find_unquoted(condition, text, *word).first()?;
+            Some((start, *text, *operator))
+        })
+        .min_by_key(|(start, text, _)| (*start, std::cmp::Reverse(text.len())))
+        .ok_or_else(|| FilterError(format!("'{}' has no operator", condition.trim())))?;
+
+    let name = unquote(&condition[..start]);
+    let value = unquote(&condition[start + text.len()..]);
+    let column = find_column(columns, &name)
+        .ok_or_else(|| FilterError(format!("There's no column named '{}'", name)))?;
+
+    if matches!(operator, Operator::IsEmpty | Operator::IsNotEmpty) && !value.is_empty() {
+        return Err(FilterError(format!(
+            "'{}' doesn't take a value",
+            text.to_lowercase()
+        )));
+    }
+
+    let unsupported = || {
+        FilterError(format!(
+            "'{}' can't be used with '{}', a {} column",
+            text.to_lowercase(),
+            column.name,
+            column.column_type
+        ))
+    };
+    let mut filter = ColumnFilter {
+        property: column.reference().to_string(),
+        ..Default::default()
+    };
+
+    match column.column_type.as_str() {
+        "rich_text" => {
+            filter.rich_text = Some(text_filter(operator, value).ok_or_else(unsupported)?)
+        }
+        "title" => filter.title = Some(text_filter(operator, value).ok_or_else(unsupported)?),
+        "email" => filter.email = Some(text_filter(operator, value).ok_or_else(unsupported)?),
+        "url" => filter.url = Some(text_filter(operator, value).ok_or_else(unsupported)?),
+        "number" => filter.number = Some(number_filter(operator, &value)?.ok_or_else(unsupported)?),
+        "checkbox" => {
+            let checked = match value.to_lowercase().as_str() {
+                "true" | "yes" => true,
+                "false" | "no" => false,
+                _ => {
+                    return Err(FilterError(format!(
+                        "'{}' is a checkbox, so it can only be true or false",
+                        column.name
+                    )))
+                }
+            };
+            filter.checkbox = Some(match operator {
+                Operator::Equals => CheckboxColumnFilter {
+                    equals: Some(checked),
+                    ..Default::default()
+                },
+                Operator::NotEquals => CheckboxColumnFilter {
+                    does_not_equal: Some(checked),
+                    ..Default::default()
+                },
+                _ => return Err(unsupported()),
+            });
+        }
+        "multi_select" => {
+            let mut multi_select = MultiSelectColumnFilter::default();
+            match operator {
+                Operator::Contains | Operator::Equals => multi_select.contains = Some(value),
+                Operator::DoesNotContain | Operator::NotEquals => {
+                    multi_select.does_not_contain = Some(value)
+                }
+                Operator::IsEmpty => multi_select.is_empty = Some(true),
+                Operator::IsNotEmpty => multi_select.is_not_empty = Some(true),
+                _ => return Err(unsupported()),
+            }
+            filter.multi_select = Some(multi_select);
+        }
+        "select" | "status" => {
+            let mut select = SelectColumnFilter::default();
+            match operator {
+                Operator::Equals => select.equals = Some(value),
+                Operator::NotEquals => select.does_not_equal = Some(value),
+                Operator::IsEmpty => select.is_empty = Some(true),
+                Operator::IsNotEmpty => select.is_not_empty = Some(true),
+                _ => return Err(unsupported()),
+            }
+            if column.column_type == "select" {
+                filter.select = Some(select);
+            } else {
+                filter.status = Some(select);
+            }
+        }
+        "relation" => {
+            let mut relation = RelationColumnFilter::default();
+            match operator {
+                Operator::Contains => relation.contains = Some(value),
+                Operator::DoesNotContain => relation.does_not_contain = Some(value),
+                Operator::IsEmpty => relation.is_empty = Some(true),
+                Operator::IsNotEmpty => relation.is_not_empty = Some(true),
+                _ => return Err(unsupported()),
+            }
+            filter.relation = Some(relation);
+        }
+        "date" => filter.date = Some(date_filter(operator, value).ok_or_else(unsupported)?),
+        "created_time" | "last_edited_time" => {
+            let date = date_filter(operator, value).ok_or_else(unsupported)?;
+            let mut timestamp = TimestampFilter {
+                timestamp: column.column_type.clone(),
+                ..Default::default()
+            };
+            if column.column_type == "created_time" {
+                timestamp.created_time = Some(date);
+            } else {
+                timestamp.last_edited_time = Some(date);
+            }
+            return Ok(QueryFilter::Timestamp(Box::new(timestamp)));
+        }
+        _ => {
+            return Err(FilterError(format!(
+                "Filtering on '{}' columns isn't supported",
+                column.column_type
+            )))
+        }
+    }
+    Ok(QueryFilter::ColumnFilter(Box::new(filter)))
+}
+
+/// Parses a filter such as `Published = true and Tags contains "Release notes"`.
+///
+/// Conditions are `Column operator value`, where the operator is one of `=`,
+/// `!=`, `>`, `<`, `>=`, `<=`, `contains`, `does not contain`, `starts with`,
+/// `ends with`, `is empty` or `is not empty`, and can be joined with `and`
+/// and `or` (`and` binding tighter). Column names and values can be quoted.
+///
+/// Each level is a single compound filter, since Notion only allows them to
+/// be nested two deep.
+pub fn parse_filter(expr: &str, columns: &[Column]) -> Result<QueryFilter, FilterError> {
+    let combine = |mut filters: Vec<QueryFilter>, and: bool| match filters.len() {
+        1 => filters.remove(0),
+        _ if and => QueryFilter::And(filters),
+        _ => QueryFilter::Or(filters),
+    };
+
+    let alternatives = split_unquoted(expr, "or")
+        .into_iter()
+        .map(|alternative| {
+            let conditions = split_unquoted(alternative, "and")
+                .into_iter()
+                .map(|condition| parse_condition(condition, columns))
+                .collect::<Result<Vec<QueryFilter>, _>>()?;
+            Ok(combine(conditions, true))
+        })
+        .collect::<Result<Vec<QueryFilter>, FilterError>>()?;
+    Ok(combine(alternatives, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::models::database::fixtures::column;
+
+    fn columns() -> Vec<Column> {
+        vec![
+            column("title", "Name", "title"),
+            column("a1", "Published", "checkbox"),
+            column("b2", "Score", "number"),
+            column("c3", "Tags", "multi_select"),
+        ]
+    }
+
+    fn parse(expr: &str) -> Value {
+        serde_json::to_value(parse_filter(expr, &columns()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn a_single_condition_isnt_wrapped() {
+        assert_eq!(
+            parse("Score >= 3"),
+            json!({ "property": "b2", "number": { "greater_than_or_equal_to": 3.0 } })
+        );
+    }
+
+    #[test]
+    fn conditions_joined_the_same_way_share_one_compound_filter() {
+        let filter = parse("Published = true and Score > 1 and Tags contains Rust");
+        assert_eq!(filter["and"].as_array().unwrap().len(), 3);
+        assert_eq!(filter["and"][2]["multi_select"]["contains"], "Rust");
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("Published = true and Score > 1 or Name starts with Draft"),
+            json!({ "or": [
+                { "and": [
+                    { "property": "a1", "checkbox": { "equals": true } },
+                    { "property": "b2", "number": { "greater_than": 1.0 } },
+                ] },
+                { "property": "title", "title": { "starts_with": "Draft" } },
+            ] })
+        );
+    }
+
+    #[test]
+    fn keywords_inside_quotes_are_left_alone() {
+        assert_eq!(
+            parse(r#"Name = "Salt and pepper or not""#),
+            json!({ "property": "title", "title": { "equals": "Salt and pepper or not" } })
+        );
+    }
+
+    #[test]
+    fn problems_are_reported() {
+        let error = |expr: &str| parse_filter(expr, &columns()).unwrap_err().to_string();
+        assert_eq!(error("Colour = red"), "There's no column named 'Colour'");
+        assert_eq!(error("Score = lots"), "'lots' isn't a number");
+        assert_eq!(error("Published"), "'Published' has no operator");
+        assert_eq!(
+            error("Published > true"),
+            "'>' can't be used with 'Published', a checkbox column"
+        );
+    }
+}
@@ -0,0 +1,573 @@
+use core::fmt;
+use std::{collections::HashMap, error::Error};
+
+use reqwest::Client;
+use serde::{Serialize, Serializer};
+use serde_json::{json, Value};
+
+use super::{
+    blocks::{Blocks, DateBlock},
+    database::{Cell, Row},
+    responses::{response_to_result, ErrorResponse, SimpleResponse},
+};
+
+/// The most characters Notion allows in a single rich text object.
+const TEXT_LIMIT: usize = 2000;
+
+/// A value to set a property to: the writable counterpart of `Blocks`.
+/// `None` clears a property.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub enum PropertyValue {
+    #[serde(rename = "title", serialize_with = "serialize_text")]
+    Title(String),
+    #[serde(rename = "rich_text", serialize_with = "serialize_text")]
+    RichText(String),
+    #[serde(rename = "number")]
+    Number(Option<f64>),
+    #[serde(rename = "checkbox")]
+    Checkbox(bool),
+    #[serde(rename = "select", serialize_with = "serialize_option")]
+    Select(Option<String>),
+    #[serde(rename = "status", serialize_with = "serialize_option")]
+    Status(Option<String>),
+    #[serde(rename = "multi_select", serialize_with = "serialize_options")]
+    MultiSelect(Vec<String>),
+    #[serde(rename = "date")]
+    Date(Option<DateBlock>),
+    /// Ids of related pages.
+    #[serde(rename = "relation", serialize_with = "serialize_ids")]
+    Relation(Vec<String>),
+    /// Ids of users.
+    #[serde(rename = "people", serialize_with = "serialize_users")]
+    People(Vec<String>),
+    #[serde(rename = "url")]
+    Url(Option<String>),
+    #[serde(rename = "email")]
+    Email(Option<String>),
+    #[serde(rename = "phone_number")]
+    PhoneNumber(Option<String>),
+}
+
+/// Text as rich text objects, split up to fit Notion's length limit.
+fn serialize_text<S: Serializer>(text: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    text.chars()
+        .collect::<Vec<char>>()
+        .chunks(TEXT_LIMIT)
+        .map(|chunk| json!({ "text": { "content": chunk.iter().collect::<String>() } }))
+        .collect::<Vec<Value>>()
+        .serialize(serializer)
+}
+
+fn serialize_option<S: Serializer>(
+    name: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    name.as_ref()
+        .map(|name| json!({ "name": name }))
+        .serialize(serializer)
+}
+
+fn serialize_options<S: Serializer>(names: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    names
+        .iter()
+        .map(|name| json!({ "name": name }))
+        .collect::<Vec<Value>>()
+        .serialize(serializer)
+}
+
+fn serialize_ids<S: Serializer>(ids: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    ids.iter()
+        .map(|id| json!({ "id": id }))
+        .collect::<Vec<Value>>()
+        .serialize(serializer)
+}
+
+fn serialize_users<S: Serializer>(ids: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    ids.iter()
+        .map(|id| json!({ "object": "user", "id": id }))
+        .collect::<Vec<Value>>()
+        .serialize(serializer)
+}
+
+impl PropertyValue {
+    /// The value that would set a property back to `cell`'s, if it can be set.
+    /// Rich text loses its formatting and links; use `writable_property` to
+    /// keep them.
+    pub fn from_cell(cell: &Cell) -> Option<PropertyValue> {
+        let text = |value: &String| Some(value.clone()).filter(|value| !value.is_empty());
+        let block = cell.block.as_ref()?;
+        Some(match block {
+            Blocks::Title(_) => PropertyValue::Title(block.plain_text()),
+            Blocks::RichText(_) => PropertyValue::RichText(block.plain_text()),
+            // Notion's own value, which the f32 in `Blocks::Number` can't
+            // always hold exactly.
+            Blocks::Number(_) => PropertyValue::Number(cell.raw["number"].as_f64()),
+            Blocks::Checkbox(value) => PropertyValue::Checkbox(*value),
+            Blocks::Select(selection) => {
+                PropertyValue::Select(selection.as_ref().map(|selection| selection.name.clone()))
+            }
+            Blocks::MultiSelect(_) => PropertyValue::MultiSelect(block.items()),
+            Blocks::Date(date) => PropertyValue::Date(date.clone()),
+            Blocks::Relation(_) => PropertyValue::Relation(block.items()),
+            Blocks::Url(value) => PropertyValue::Url(text(value)),
+            Blocks::Email(value) => PropertyValue::Email(text(value)),
+            Blocks::PhoneNumber(value) => PropertyValue::PhoneNumber(value.clone()),
+            Blocks::CreatedBy(_) | Blocks::CreatedTime(_) | Blocks::Files(_) => return None,
+        })
+    }
+
+    /// The value that clears a property of the given type, if it can be set.
+    pub fn cleared(column_type: &str) -> Option<PropertyValue> {
+        Some(match column_type {
+            "title" => PropertyValue::Title(String::new()),
+            "rich_text" => PropertyValue::RichText(String::new()),
+            "number" => PropertyValue::Number(None),
+            "checkbox" => PropertyValue::Checkbox(false),
+            "select" => PropertyValue::Select(None),
+            "status" => PropertyValue::Status(None),
+            "multi_select" => PropertyValue::MultiSelect(Vec::new()),
+            "date" => PropertyValue::Date(None),
+            "relation" => PropertyValue::Relation(Vec::new()),
+            "people" => PropertyValue::People(Vec::new()),
+            "url" => PropertyValue::Url(None),
+            "email" => PropertyValue::Email(None),
+            "phone_number" => PropertyValue::PhoneNumber(None),
+            _ => return None,
+        })
+    }
+}
+
+/// A rich text object as read from a page, in the shape Notion expects when
+/// setting it, keeping its formatting and links.
+fn writable_text(text: &Value) -> Result<Value, String> {
+    let text_type = text["type"].as_str().unwrap_or_default();
+    match text_type {
+        "text" | "equation" => {}
+        "mention" => {
+            let mention_type = text["mention"]["type"].as_str().unwrap_or_default();
+            if !matches!(mention_type, "user" | "page" | "database" | "date") {
+                return Err(format!("{} mentions can't be set", mention_type));
+            }
+        }
+        _ => return Err(format!("{} rich text can't be set", text_type)),
+    }
+    Ok(json!({
+        "type": text_type,
+        text_type: text[text_type],
+        "annotations": text["annotations"],
+    }))
+}
+
+/// A property as read from a page (a `Cell`'s `raw` value), in the shape
+/// Notion expects when setting it, so it can be set back exactly as it was.
+pub fn writable_property(raw: &Value) -> Result<Value, String> {
+    let property_type = raw["type"].as_str().unwrap_or_default();
+    let value = &raw[property_type];
+    let items = || value.as_array().cloned().unwrap_or_default();
+    let writable = match property_type {
+        "title" | "rich_text" => Value::Array(
+            items()
+                .iter()
+                .map(writable_text)
+                .collect::<Result<Vec<Value>, String>>()?,
+        ),
+        "number" | "checkbox" | "url" | "email" | "phone_number" | "date" => value.clone(),
+        "select" | "status" => match value.get("name") {
+            Some(name) => json!({ "name": name }),
+            None => Value::Null,
+        },
+        "multi_select" => Value::Array(
+            items()
+                .iter()
+                .map(|option| json!({ "name": option["name"] }))
+                .collect(),
+        ),
+        "relation" => {
+            if raw["has_more"].as_bool() == Some(true) {
+                return Err("it relates to more pages than Notion returns at once".to_string());
+            }
+            Value::Array(
+                items()
+                    .iter()
+                    .map(|page| json!({ "id": page["id"] }))
+                    .collect(),
+            )
+        }
+        "people" => Value::Array(
+            items()
+                .iter()
+                .map(|user| json!({ "object": "user", "id": user["id"] }))
+                .collect(),
+        ),
+        "files" => Value::Array(
+            items()
+                .iter()
+                .map(|file| match file["type"].as_str() {
+                    Some("external") => Ok(json!({
+                        "name": file["name"],
+                        "type": "external",
+                        "external": { "url": file["external"]["url"] },
+                    })),
+                    _ => Err("files uploaded to Notion can't be set".to_string()),
+                })
+                .collect::<Result<Vec<Value>, String>>()?,
+        ),
+        _ => return Err(format!("{} properties can't be set", property_type)),
+    };
+    Ok(json!({ property_type: writable }))
+}
+
+/// Fetches a page, such as one of a database's rows.
+pub async fn fetch_page(token: &str, page_id: &str) -> Result<Row, ErrorResponse> {
+    let client = Client::new();
+    let url = format!("https://api.notion.com/v1/pages/{}", page_id);
+
+    let response = client
+        .get(url)
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Notion-Version", "2022-06-28")
+        .send()
+        .await;
+
+    let result = response_to_result(response.unwrap()).await?;
+    Ok(serde_json::from_str(&result.body).unwrap())
+}
+
+async fn patch_page(
+    token: &str,
+    page_id: &str,
+    body: &Value,
+) -> Result<SimpleResponse, ErrorResponse> {
+    let client = Client::new();
+    let url = format!("https://api.notion.com/v1/pages/{}", page_id);
+
+    let response = client
+        .patch(url)
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Notion-Version", "2022-06-28")
+        .json(body)
+        .send()
+        .await;
+
+    response_to_result(response.unwrap()).await
+}
+
+/// Sets properties on a page, where `properties` maps property names (or ids)
+/// to values in the shape Notion expects, e.g. `{ "Sent": { "checkbox": true } }`.
+pub async fn update_page_properties(
+    token: &str,
+    page_id: &str,
+    properties: &Value,
+) -> Result<SimpleResponse, ErrorResponse> {
+    patch_page(token, page_id, &json!({ "properties": properties })).await
+}
+
+/// Sets properties on a page, keyed by property name (or id).
+pub async fn update_page(
+    token: &str,
+    page_id: &str,
+    properties: &HashMap<String, PropertyValue>,
+) -> Result<SimpleResponse, ErrorResponse> {
+    update_page_properties(token, page_id, &json!(properties)).await
+}
+
+/// A property whose value differs between two reads of a page.
+#[derive(Debug)]
+pub struct PropertyChange {
+    pub name: String,
+    /// `None` when the property was empty, or had no value we could read.
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// The properties that differ between two reads of the same page, by name.
+pub fn diff_rows(before: &Row, after: &Row) -> Vec<PropertyChange> {
+    let before = before.values();
+    let after = after.values();
+    let mut names = before.keys().chain(after.keys()).collect::<Vec<&String>>();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let value = |values: &HashMap<String, Blocks>| {
+                Some(values.get(name)?.plain_text()).filter(|value| !value.is_empty())
+            };
+            let (before, after) = (value(&before), value(&after));
+            (before != after).then(|| PropertyChange {
+                name: name.clone(),
+                before,
+                after,
+            })
+        })
+        .collect()
+}
+
+/// A page was edited between being read and being written to.
+#[derive(Debug)]
+pub struct ConflictError {
+    pub page_id: String,
+    /// The page's `last_edited_time` when it was read.
+    pub read_at: String,
+    /// Its `last_edited_time` now.
+    pub edited_at: String,
+    pub changes: Vec<PropertyChange>,
+}
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} was edited at {}, after it was read ({})",
+            self.page_id, self.edited_at, self.read_at
+        )?;
+        if self.changes.is_empty() {
+            return write!(f, ", though none of its properties we can read changed");
+        }
+        for change in self.changes.iter() {
+            write!(
+                f,
+                "\n  {}: {} → {}",
+                change.name,
+                change.before.as_deref().unwrap_or("(empty)"),
+                change.after.as_deref().unwrap_or("(empty)")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConflictError {}
+
+#[derive(Debug)]
+pub enum UpdateError {
+    Conflict(ConflictError),
+    Response(ErrorResponse),
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateError::Conflict(err) => write!(f, "{}", err),
+            UpdateError::Response(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for UpdateError {}
+
+impl From<ErrorResponse> for UpdateError {
+    fn from(err: ErrorResponse) -> Self {
+        UpdateError::Response(err)
+    }
+}
+
+/// Sets properties on a row, unless the page has been edited since `row` was
+/// read. Notion has no conditional writes, so this narrows the window for
+/// clobbering someone's edit rather than closing it.
+pub async fn update_row_checked(
+    token: &str,
+    row: &Row,
+    properties: &HashMap<String, PropertyValue>,
+) -> Result<SimpleResponse, UpdateError> {
+    let current = fetch_page(token, &row.id).await?;
+    if current.last_edited_time != row.last_edited_time {
+        return Err(UpdateError::Conflict(ConflictError {
+            page_id: row.id.clone(),
+            read_at: row.last_edited_time.clone(),
+            edited_at: current.last_edited_time.clone(),
+            changes: diff_rows(row, &current),
+        }));
+    }
+    Ok(update_page(token, &row.id, properties).await?)
+}
+
+/// Archives a page, which removes it from its database until it's unarchived.
+pub async fn archive_page(token: &str, page_id: &str) -> Result<SimpleResponse, ErrorResponse> {
+    patch_page(token, page_id, &json!({ "archived": true })).await
+}
+
+pub async fn unarchive_page(token: &str, page_id: &str) -> Result<SimpleResponse, ErrorResponse> {
+    patch_page(token, page_id, &json!({ "archived": false })).await
+}
+
+/// Moves a page to the trash, from where it can be restored for 30 days.
+pub async fn trash_page(token: &str, page_id: &str) -> Result<SimpleResponse, ErrorResponse> {
+    patch_page(token, page_id, &json!({ "in_trash": true })).await
+}
+
+/// Restores a page from the trash.
+pub async fn restore_page(token: &str, page_id: &str) -> Result<SimpleResponse, ErrorResponse> {
+    patch_page(token, page_id, &json!({ "in_trash": false })).await
+}
+
+/// Creates a page in a database, with `properties` in the same shape as for
+/// `update_page_properties`.
+pub async fn create_page(
+    token: &str,
+    database_id: &str,
+    properties: &Value,
+) -> Result<SimpleResponse, ErrorResponse> {
+    let client = Client::new();
+
+    let response = client
+        .post("https://api.notion.com/v1/pages")
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Notion-Version", "2022-06-28")
+        .json(&json!({
+            "parent": { "database_id": database_id },
+            "properties": properties,
+        }))
+        .send()
+        .await;
+
+    response_to_result(response.unwrap()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_cell_joins_rich_text_segments_as_they_are() {
+        let title: Cell = serde_json::from_value(json!({
+            "id": "title",
+            "type": "title",
+            "title": [
+                { "type": "text", "text": { "content": "Hello ", "link": null },
+                  "annotations": { "bold": false, "italic": false, "strikethrough": false,
+                      "underline": false, "code": false, "color": "default" },
+                  "plain_text": "Hello ", "href": null },
+                { "type": "text", "text": { "content": "world", "link": null },
+                  "annotations": { "bold": true, "italic": false, "strikethrough": false,
+                      "underline": false, "code": false, "color": "default" },
+                  "plain_text": "world", "href": null }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            PropertyValue::from_cell(&title),
+            Some(PropertyValue::Title("Hello world".to_string()))
+        );
+    }
+
+    #[test]
+    fn from_cell_keeps_numbers_exact() {
+        let number: Cell = serde_json::from_value(json!({
+            "id": "n", "type": "number", "number": 16777217,
+        }))
+        .unwrap();
+        assert_eq!(
+            PropertyValue::from_cell(&number),
+            Some(PropertyValue::Number(Some(16777217.0)))
+        );
+    }
+
+    #[test]
+    fn writable_property_keeps_rich_text_formatting() {
+        let raw = json!({
+            "id": "title",
+            "type": "title",
+            "title": [
+                {
+                    "type": "text",
+                    "text": { "content": "Hello ", "link": null },
+                    "annotations": { "bold": false, "italic": false, "strikethrough": false,
+                        "underline": false, "code": false, "color": "default" },
+                    "plain_text": "Hello ",
+                    "href": null
+                },
+                {
+                    "type": "text",
+                    "text": { "content": "world", "link": { "url": "https://example.com" } },
+                    "annotations": { "bold": true, "italic": false, "strikethrough": false,
+                        "underline": false, "code": false, "color": "default" },
+                    "plain_text": "world",
+                    "href": "https://example.com"
+                }
+            ]
+        });
+        let writable = writable_property(&raw).unwrap();
+        assert_eq!(writable["title"][0]["text"]["content"], "Hello ");
+        assert_eq!(
+            writable["title"][1]["text"]["link"]["url"],
+            "https://example.com"
+        );
+        assert_eq!(writable["title"][1]["annotations"]["bold"], true);
+        assert!(writable["title"][1].get("plain_text").is_none());
+    }
+
+    #[test]
+    fn writable_property_reads_types_blocks_cant() {
+        let status = json!({ "id": "s", "type": "status",
+            "status": { "id": "1", "name": "In progress", "color": "blue" } });
+        assert_eq!(
+            writable_property(&status).unwrap(),
+            json!({ "status": { "name": "In progress" } })
+        );
+        let number = json!({ "id": "n", "type": "number", "number": null });
+        assert_eq!(
+            writable_property(&number).unwrap(),
+            json!({ "number": null })
+        );
+        let people = json!({ "id": "p", "type": "people",
+            "people": [{ "object": "user", "id": "u1", "name": "Ann" }] });
+        assert_eq!(
+            writable_property(&people).unwrap(),
+            json!({ "people": [{ "object": "user", "id": "u1" }] })
+        );
+    }
+
+    #[test]
+    fn writable_property_refuses_what_it_cant_restore() {
+        let formula =
+            json!({ "id": "f", "type": "formula", "formula": { "type": "number", "number": 1 } });
+        assert!(writable_property(&formula).is_err());
+        let relation = json!({ "id": "r", "type": "relation", "relation": [], "has_more": true });
+        assert!(writable_property(&relation).is_err());
+        let files = json!({ "id": "f", "type": "files",
+            "files": [{ "name": "a.pdf", "type": "file", "file": { "url": "https://s3/a.pdf" } }] });
+        assert!(writable_property(&files).is_err());
+    }
+
+    #[test]
+    fn property_values_serialize_as_notion_expects() {
+        let properties = HashMap::from([
+            ("a", PropertyValue::Select(Some("Done".to_string()))),
+            ("b", PropertyValue::Select(None)),
+            (
+                "c",
+                PropertyValue::MultiSelect(vec!["x".to_string(), "y".to_string()]),
+            ),
+            ("d", PropertyValue::Relation(vec!["p1".to_string()])),
+            ("e", PropertyValue::People(vec!["u1".to_string()])),
+            ("f", PropertyValue::Number(None)),
+            ("g", PropertyValue::Checkbox(true)),
+        ]);
+        assert_eq!(
+            serde_json::to_value(&properties).unwrap(),
+            json!({
+                "a": { "select": { "name": "Done" } },
+                "b": { "select": null },
+                "c": { "multi_select": [{ "name": "x" }, { "name": "y" }] },
+                "d": { "relation": [{ "id": "p1" }] },
+                "e": { "people": [{ "object": "user", "id": "u1" }] },
+                "f": { "number": null },
+                "g": { "checkbox": true },
+            })
+        );
+    }
+
+    #[test]
+    fn long_text_is_split_to_fit_notions_limit() {
+        let text = "é".repeat(TEXT_LIMIT + 1);
+        let value = serde_json::to_value(PropertyValue::RichText(text)).unwrap();
+        let chunks = value["rich_text"].as_array().unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1], json!({ "text": { "content": "é" } }));
+        assert_eq!(
+            serde_json::to_value(PropertyValue::Title(String::new())).unwrap(),
+            json!({ "title": [] })
+        );
+    }
+}
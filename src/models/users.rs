@@ -1,14 +1,14 @@
 use core::fmt;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct UserEmail {
     email: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct User {
     avatar_url: Option<String>,
@@ -20,6 +20,12 @@ pub struct User {
     user_type: Option<String>,
 }
 
+impl User {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
 impl fmt::Display for User {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
@@ -0,0 +1,107 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+
+use crate::{models::database::Row, template::Template};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum DocumentFormat {
+    Markdown,
+    /// Values are HTML-escaped
+    Html,
+    Text,
+}
+
+impl DocumentFormat {
+    /// Guesses the format from a file name's extension, ignoring a trailing `.tmpl`.
+    pub fn from_path(path: &str) -> DocumentFormat {
+        let path = path.strip_suffix(".tmpl").unwrap_or(path);
+        match Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .as_deref()
+        {
+            Some("md" | "markdown") => DocumentFormat::Markdown,
+            Some("html" | "htm") => DocumentFormat::Html,
+            _ => DocumentFormat::Text,
+        }
+    }
+}
+
+pub(crate) fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Keeps a value from adding directories to, or climbing out of, the output path.
+pub(crate) fn escape_path(value: &str) -> String {
+    let value = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0' => '_',
+            c => c,
+        })
+        .collect::<String>();
+    match value.trim() {
+        "." | ".." => "_".to_string(),
+        _ => value,
+    }
+}
+
+/// Appends ` (2)`, ` (3)`... before the extension until `path` isn't in `taken`.
+pub(crate) fn unique_path(path: PathBuf, taken: &[PathBuf]) -> PathBuf {
+    let mut candidate = path.clone();
+    let mut i = 2;
+    while taken.contains(&candidate) {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = match path.extension() {
+            Some(extension) => format!("{} ({}).{}", stem, i, extension.to_string_lossy()),
+            None => format!("{} ({})", stem, i),
+        };
+        candidate = path.with_file_name(name);
+        i += 1;
+    }
+    candidate
+}
+
+/// Writes one document per row, at the path `out` renders to for that row.
+/// Rows whose paths collide get a number added to their file name.
+pub fn render_documents(
+    template: &Template,
+    out: &Template,
+    rows: &[Row],
+    format: DocumentFormat,
+) -> io::Result<Vec<PathBuf>> {
+    let escape = match format {
+        DocumentFormat::Html => escape_html,
+        DocumentFormat::Markdown | DocumentFormat::Text => str::to_string,
+    };
+    let mut written = Vec::new();
+
+    for row in rows.iter() {
+        let values = row.template_values();
+        let path = PathBuf::from(out.render_escaped(&values, &HashMap::new(), escape_path));
+        let path = unique_path(path, &written);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &path,
+            template.render_escaped(&values, &HashMap::new(), escape),
+        )?;
+        written.push(path);
+    }
+    Ok(written)
+}
@@ -0,0 +1,446 @@
+pub mod drift;
+pub mod plan;
+
+use core::fmt;
+use std::{error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    get_db_columns, get_db_title,
+    models::database::{
+        create_notion_database, fetch_notion_database, find_column, update_notion_database, Column,
+        DatabaseCredentials, SelectOption,
+    },
+    schema::plan::SchemaFile,
+};
+
+#[derive(Debug)]
+pub struct SchemaError(pub String);
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for SchemaError {}
+
+/// A database's title and columns, as read from or written to a schema file.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DatabaseSchema {
+    pub title: String,
+    pub columns: Vec<Column>,
+}
+
+impl DatabaseSchema {
+    /// The schema of a database, from the body of a `fetch_notion_database` response.
+    pub fn from_database(body: &str) -> Result<DatabaseSchema, Box<dyn Error>> {
+        let mut columns = get_db_columns(body)?.unwrap_or_default();
+        // The title column first, then the rest alphabetically.
+        columns.sort_by_key(|column| (column.column_type != "title", column.name.to_lowercase()));
+        Ok(DatabaseSchema {
+            title: get_db_title(body)?.unwrap_or_default(),
+            columns,
+        })
+    }
+
+    pub async fn fetch(
+        credentials: &DatabaseCredentials,
+    ) -> Result<DatabaseSchema, Box<dyn Error>> {
+        let db = fetch_notion_database(credentials).await?;
+        DatabaseSchema::from_database(&db.body)
+    }
+
+    /// Reads a schema file written by `schema export`, or a `.yaml`, `.yml`
+    /// or `.toml` file as described by `plan::SchemaFile`.
+    pub fn load(path: &Path) -> Result<DatabaseSchema, Box<dyn Error>> {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if matches!(extension, Some("yaml" | "yml" | "toml")) {
+            return Ok(SchemaFile::load(path)?.schema()?);
+        }
+        serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+
+    /// Leaves out the columns Notion's API can't create, such as status and
+    /// formula columns, returning their names.
+    pub fn remove_uncreatable(&mut self) -> Vec<String> {
+        let (columns, uncreatable) = self
+            .columns
+            .drain(..)
+            .partition(|column| property_schema(column).is_ok());
+        self.columns = columns;
+        uncreatable
+            .into_iter()
+            .map(|column: Column| column.name)
+            .collect()
+    }
+}
+
+fn options_schema(options: &[SelectOption]) -> Value {
+    json!({ "options": options })
+}
+
+/// A column's configuration in the shape Notion expects when creating it.
+pub fn property_schema(column: &Column) -> Result<Value, SchemaError> {
+    let config = match column.column_type.as_str() {
+        "title" | "rich_text" | "number" | "checkbox" | "url" | "email" | "phone_number"
+        | "date" | "people" | "files" | "created_time" | "created_by" | "last_edited_time"
+        | "last_edited_by" => json!({}),
+        "select" | "multi_select" => {
+            // Option ids belong to the database they were read from.
+            let options = column
+                .options
+                .iter()
+                .map(|option| SelectOption {
+                    id: None,
+                    ..option.clone()
+                })
+                .collect::<Vec<SelectOption>>();
+            options_schema(&options)
+        }
+        "relation" => {
+            let relation = column.relation.as_ref().ok_or_else(|| {
+                SchemaError(format!(
+                    "'{}' is a relation column, so needs a database to relate to",
+                    column.name
+                ))
+            })?;
+            // One way only: a synced column read from another database belongs
+            // to that database's relation, and can't be shared with a new one.
+            json!({
+                "database_id": relation.database_id,
+                "type": "single_property",
+                "single_property": {},
+            })
+        }
+        column_type => {
+            return Err(SchemaError(format!(
+                "'{}' is a {} column, which can't be created through Notion's API",
+                column.name, column_type
+            )))
+        }
+    };
+    Ok(json!({ column.column_type.clone(): config }))
+}
+
+fn title_text(title: &str) -> Value {
+    json!([{ "type": "text", "text": { "content": title } }])
+}
+
+/// Creates a database with `schema`'s title and columns as a child of a page,
+/// returning the new database's id.
+pub async fn create_database(
+    token: &str,
+    parent_page_id: &str,
+    schema: &DatabaseSchema,
+) -> Result<String, Box<dyn Error>> {
+    let titles = schema
+        .columns
+        .iter()
+        .filter(|column| column.column_type == "title")
+        .count();
+    if titles != 1 {
+        return Err(SchemaError(format!(
+            "A database needs exactly one title column, but the schema has {}",
+            titles
+        ))
+        .into());
+    }
+
+    let properties = schema
+        .columns
+        .iter()
+        .map(|column| Ok((column.name.clone(), property_schema(column)?)))
+        .collect::<Result<Map<String, Value>, SchemaError>>()?;
+    let body = json!({
+        "parent": { "type": "page_id", "page_id": parent_page_id },
+        "title": title_text(&schema.title),
+        "properties": properties,
+    });
+    let response = create_notion_database(token, &body).await?;
+    let created: Value = serde_json::from_str(&response.body)?;
+    Ok(created["id"].as_str().unwrap_or_default().to_string())
+}
+
+/// A change to a database's title or columns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaChange {
+    RenameDatabase(String),
+    AddColumn(Column),
+    RenameColumn {
+        name: String,
+        new_name: String,
+    },
+    RemoveColumn(String),
+    /// Replaces a `select` or `multi_select` column's options. Options that
+    /// already exist keep their colours unless they're given one.
+    SetOptions {
+        name: String,
+        options: Vec<SelectOption>,
+    },
+    /// Changes an existing column's type, and whatever goes with it.
+    ChangeType {
+        from: String,
+        column: Column,
+    },
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaChange::RenameDatabase(title) => {
+                write!(f, "~ rename the database to '{}'", title)
+            }
+            SchemaChange::AddColumn(column) => {
+                write!(f, "+ add '{}' ({})", column.name, column.column_type)
+            }
+            SchemaChange::RenameColumn { name, new_name } => {
+                write!(f, "~ rename '{}' to '{}'", name, new_name)
+            }
+            SchemaChange::RemoveColumn(name) => write!(f, "- remove '{}'", name),
+            SchemaChange::SetOptions { name, options } => {
+                let names = options
+                    .iter()
+                    .map(|option| option.name.as_str())
+                    .collect::<Vec<&str>>();
+                write!(f, "~ set '{}' options to {}", name, names.join(", "))
+            }
+            SchemaChange::ChangeType { from, column } => match &column.relation {
+                Some(relation) if from == &column.column_type => write!(
+                    f,
+                    "~ relate '{}' to {} instead",
+                    column.name, relation.database_id
+                ),
+                _ => write!(
+                    f,
+                    "~ change '{}' from {} to {}",
+                    column.name, from, column.column_type
+                ),
+            },
+        }
+    }
+}
+
+/// Sets `key` in the changes to an existing column, which is addressed by id
+/// so renames don't get in the way. A null value removes the column.
+fn set_property(properties: &mut Map<String, Value>, column: &Column, key: &str, value: Value) {
+    let entry = properties
+        .entry(column.id.clone())
+        .or_insert_with(|| json!({}));
+    if value.is_null() {
+        *entry = Value::Null;
+    } else if let Some(entry) = entry.as_object_mut() {
+        entry.insert(key.to_string(), value);
+    }
+}
+
+/// The body of a request making `changes` to a database with `columns`.
+/// Several changes to the same column are combined, and columns renamed by
+/// `changes` can be changed further by their new names.
+pub fn change_body(changes: &[SchemaChange], columns: &[Column]) -> Result<Value, SchemaError> {
+    let find = |name: &str| {
+        find_column(columns, name)
+            .ok_or_else(|| SchemaError(format!("The column '{}' does not exist.", name)))
+    };
+    let find_renamed = |name: &str| {
+        let renamed = changes.iter().find_map(|change| match change {
+            SchemaChange::RenameColumn {
+                name: old_name,
+                new_name,
+            } if new_name == name => Some(old_name.as_str()),
+            _ => None,
+        });
+        find(renamed.unwrap_or(name))
+    };
+    let mut body = Map::new();
+    let mut properties = Map::new();
+
+    for change in changes.iter() {
+        match change {
+            SchemaChange::RenameDatabase(title) => {
+                body.insert("title".to_string(), title_text(title));
+            }
+            SchemaChange::AddColumn(column) => {
+                if columns.iter().any(|existing| existing.name == column.name) {
+                    return Err(SchemaError(format!(
+                        "There's already a column named '{}'",
+                        column.name
+                    )));
+                }
+                properties.insert(column.name.clone(), property_schema(column)?);
+            }
+            SchemaChange::RenameColumn { name, new_name } => {
+                set_property(&mut properties, find(name)?, "name", json!(new_name));
+            }
+            SchemaChange::RemoveColumn(name) => {
+                set_property(&mut properties, find(name)?, "", Value::Null)
+            }
+            SchemaChange::SetOptions { name, options } => {
+                let column = find_renamed(name)?;
+                if !matches!(column.column_type.as_str(), "select" | "multi_select") {
+                    return Err(SchemaError(format!(
+                        "'{}' is a {} column, whose options can't be set through Notion's API",
+                        column.name, column.column_type
+                    )));
+                }
+                let options = options
+                    .iter()
+                    .map(|option| {
+                        match column
+                            .options
+                            .iter()
+                            .find(|existing| existing.name == option.name)
+                        {
+                            Some(existing) => SelectOption {
+                                id: existing.id.clone(),
+                                name: existing.name.clone(),
+                                color: option.color.clone().or_else(|| existing.color.clone()),
+                            },
+                            None => option.clone(),
+                        }
+                    })
+                    .collect::<Vec<SelectOption>>();
+                set_property(
+                    &mut properties,
+                    column,
+                    &column.column_type,
+                    options_schema(&options),
+                );
+            }
+            SchemaChange::ChangeType {
+                column: changed, ..
+            } => {
+                let column = find_renamed(&changed.name)?;
+                if column.column_type == "title" || changed.column_type == "title" {
+                    return Err(SchemaError(format!(
+                        "'{}' can't become or stop being the title column",
+                        column.name
+                    )));
+                }
+                let schema = property_schema(changed)?;
+                set_property(
+                    &mut properties,
+                    column,
+                    &changed.column_type,
+                    schema[&changed.column_type].clone(),
+                );
+            }
+        }
+    }
+    if !properties.is_empty() {
+        body.insert("properties".to_string(), Value::Object(properties));
+    }
+    Ok(Value::Object(body))
+}
+
+/// The options `changes` would take away from each select or multi-select
+/// column in `columns`, clearing them from every row that has them.
+pub fn removed_options(changes: &[SchemaChange], columns: &[Column]) -> Vec<(String, Vec<String>)> {
+    changes
+        .iter()
+        .filter_map(|change| match change {
+            SchemaChange::SetOptions { name, options } => {
+                let column = find_column(columns, name)?;
+                let removed = column
+                    .options
+                    .iter()
+                    .filter(|existing| !options.iter().any(|option| option.name == existing.name))
+                    .map(|existing| existing.name.clone())
+                    .collect::<Vec<String>>();
+                (!removed.is_empty()).then(|| (column.name.clone(), removed))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Makes `changes` to a database in a single request.
+pub async fn alter_database(
+    credentials: &DatabaseCredentials,
+    changes: &[SchemaChange],
+) -> Result<(), Box<dyn Error>> {
+    let db = fetch_notion_database(credentials).await?;
+    let columns = get_db_columns(&db.body)?.unwrap_or_default();
+    let body = change_body(changes, &columns)?;
+    update_notion_database(credentials, &body).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::database::{fixtures::column, Relation};
+
+    #[test]
+    fn property_schema_drops_option_ids_and_makes_relations_one_way() {
+        let mut tags = column("", "Tags", "multi_select");
+        tags.options = vec![SelectOption {
+            id: Some("o1".to_string()),
+            name: "Red".to_string(),
+            color: Some("red".to_string()),
+        }];
+        assert_eq!(
+            property_schema(&tags).unwrap(),
+            json!({ "multi_select": { "options": [{ "name": "Red", "color": "red" }] } })
+        );
+
+        let mut client = column("", "Client", "relation");
+        client.relation = Some(Relation {
+            database_id: "db2".to_string(),
+            synced_property_id: Some("s1".to_string()),
+            synced_property_name: Some("Projects".to_string()),
+        });
+        assert_eq!(
+            property_schema(&client).unwrap(),
+            json!({ "relation": {
+                "database_id": "db2",
+                "type": "single_property",
+                "single_property": {},
+            } })
+        );
+    }
+
+    #[test]
+    fn removed_options_are_the_ones_left_out() {
+        let option = |name: &str| SelectOption {
+            id: None,
+            name: name.to_string(),
+            color: None,
+        };
+        let mut status = column("", "Status", "select");
+        status.options = vec![option("Planned"), option("Active"), option("Done")];
+        let changes = [SchemaChange::SetOptions {
+            name: "Status".to_string(),
+            options: vec![option("Active"), option("Done"), option("Dropped")],
+        }];
+        assert_eq!(
+            removed_options(&changes, &[status]),
+            [("Status".to_string(), vec!["Planned".to_string()])]
+        );
+    }
+
+    #[test]
+    fn remove_uncreatable_leaves_out_what_the_api_cant_create() {
+        let mut schema = DatabaseSchema {
+            title: "Projects".to_string(),
+            columns: vec![
+                column("", "Name", "title"),
+                column("", "Stage", "status"),
+                column("", "Due", "date"),
+                column("", "Days left", "formula"),
+            ],
+        };
+        assert_eq!(schema.remove_uncreatable(), ["Stage", "Days left"]);
+        assert_eq!(
+            schema
+                .columns
+                .iter()
+                .map(|column| column.name.as_str())
+                .collect::<Vec<&str>>(),
+            ["Name", "Due"]
+        );
+    }
+}
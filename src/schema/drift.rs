@@ -0,0 +1,287 @@
+use core::fmt;
+use std::{collections::HashMap, path::Path};
+
+use chrono::Utc;
+use rusqlite::{params, Connection};
+
+use crate::models::database::{Column, Row};
+
+/// Every name columns have been seen with, by column id.
+pub type Renames = HashMap<String, Vec<String>>;
+
+/// How a database's columns have changed since they were last seen.
+#[derive(Debug, Default)]
+pub struct Drift {
+    /// Each renamed column, with its previous name.
+    pub renamed: Vec<(String, Column)>,
+    /// Each column whose type changed, with its previous type.
+    pub retyped: Vec<(String, Column)>,
+    pub removed: Vec<Column>,
+    pub added: Vec<Column>,
+}
+
+impl Drift {
+    /// Compares columns by id, so a renamed column isn't taken for a removed one.
+    pub fn detect(previous: &[Column], current: &[Column]) -> Drift {
+        let mut drift = Drift::default();
+        for column in current.iter() {
+            match previous.iter().find(|previous| previous.id == column.id) {
+                Some(previous) => {
+                    if previous.name != column.name {
+                        drift.renamed.push((previous.name.clone(), column.clone()));
+                    }
+                    if previous.column_type != column.column_type {
+                        drift
+                            .retyped
+                            .push((previous.column_type.clone(), column.clone()));
+                    }
+                }
+                None => drift.added.push(column.clone()),
+            }
+        }
+        drift.removed = previous
+            .iter()
+            .filter(|previous| !current.iter().any(|column| column.id == previous.id))
+            .cloned()
+            .collect();
+        drift
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.renamed.is_empty()
+            && self.retyped.is_empty()
+            && self.removed.is_empty()
+            && self.added.is_empty()
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = Vec::new();
+        for (name, column) in self.renamed.iter() {
+            lines.push(format!("~ '{}' was renamed to '{}'", name, column.name));
+        }
+        for (column_type, column) in self.retyped.iter() {
+            lines.push(format!(
+                "~ '{}' changed from {} to {}",
+                column.name, column_type, column.column_type
+            ));
+        }
+        for column in self.removed.iter() {
+            lines.push(format!("- '{}' was removed", column.name));
+        }
+        for column in self.added.iter() {
+            lines.push(format!(
+                "+ '{}' was added ({})",
+                column.name, column.column_type
+            ));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// The columns each database had when margaret last ran against it, along
+/// with every name each column has had.
+pub struct SchemaCache {
+    conn: Connection,
+}
+
+impl SchemaCache {
+    pub fn open(path: &Path) -> rusqlite::Result<SchemaCache> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS columns (
+                database_id TEXT NOT NULL,
+                column_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                column_type TEXT NOT NULL,
+                seen_at TEXT NOT NULL,
+                PRIMARY KEY (database_id, column_id)
+            );
+            CREATE TABLE IF NOT EXISTS column_names (
+                database_id TEXT NOT NULL,
+                column_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                PRIMARY KEY (database_id, column_id, name)
+            );",
+        )?;
+        Ok(SchemaCache { conn })
+    }
+
+    /// The columns last seen, or `None` if the database hasn't been seen before.
+    pub fn columns(&self, database_id: &str) -> rusqlite::Result<Option<Vec<Column>>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT column_id, name, column_type FROM columns WHERE database_id = ?1")?;
+        let columns = statement
+            .query_map(params![database_id], |row| {
+                Ok(Column {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    column_type: row.get(2)?,
+                    relation: None,
+                    options: Vec::new(),
+                    aliases: Vec::new(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<Column>>>()?;
+        Ok(Some(columns).filter(|columns| !columns.is_empty()))
+    }
+
+    /// Compares `columns` with those last seen. Returns `None` the first
+    /// time a database is seen.
+    pub fn drift(&self, database_id: &str, columns: &[Column]) -> rusqlite::Result<Option<Drift>> {
+        Ok(self
+            .columns(database_id)?
+            .map(|previous| Drift::detect(&previous, columns)))
+    }
+
+    /// Remembers `columns` in place of those last seen, along with their names.
+    pub fn remember(&mut self, database_id: &str, columns: &[Column]) -> rusqlite::Result<()> {
+        let now = Utc::now().to_rfc3339();
+        let transaction = self.conn.transaction()?;
+        transaction.execute(
+            "DELETE FROM columns WHERE database_id = ?1",
+            params![database_id],
+        )?;
+        for column in columns.iter() {
+            transaction.execute(
+                "INSERT INTO columns (database_id, column_id, name, column_type, seen_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![database_id, column.id, column.name, column.column_type, now],
+            )?;
+            transaction.execute(
+                "INSERT OR IGNORE INTO column_names (database_id, column_id, name)
+                 VALUES (?1, ?2, ?3)",
+                params![database_id, column.id, column.name],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// Every name the database's columns have been seen with, by column id.
+    pub fn renames(&self, database_id: &str) -> rusqlite::Result<Renames> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT column_id, name FROM column_names WHERE database_id = ?1")?;
+        let mut renames = Renames::new();
+        let names = statement.query_map(params![database_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for name in names {
+            let (column_id, name) = name?;
+            renames.entry(column_id).or_default().push(name);
+        }
+        Ok(renames)
+    }
+}
+
+/// Lets columns be referred to by their earlier names too, unless a column
+/// has the name now.
+pub fn follow_renames(columns: &mut [Column], renames: &Renames) {
+    let names = columns
+        .iter()
+        .map(|column| column.name.clone())
+        .collect::<Vec<String>>();
+    for column in columns.iter_mut() {
+        if let Some(earlier) = renames.get(&column.id) {
+            column.aliases = earlier
+                .iter()
+                .filter(|name| !names.contains(name))
+                .cloned()
+                .collect();
+        }
+    }
+}
+
+/// Gives each row's cells their columns' earlier names too, so templates
+/// written before a column was renamed keep working.
+pub fn alias_cells(rows: &mut [Row], columns: &[Column]) {
+    for row in rows.iter_mut() {
+        let aliased = columns
+            .iter()
+            .filter(|column| !column.aliases.is_empty())
+            .filter_map(|column| Some((column, row.cell(column)?.clone())))
+            .collect::<Vec<_>>();
+        let Some(properties) = row.properties.as_mut() else {
+            continue;
+        };
+        for (column, cell) in aliased.iter() {
+            for alias in column.aliases.iter() {
+                properties
+                    .entry(alias.clone())
+                    .or_insert_with(|| cell.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::database::fixtures::column;
+
+    #[test]
+    fn detect_matches_columns_by_id() {
+        let previous = [
+            column("title", "Name", "title"),
+            column("a1", "Status", "select"),
+            column("b2", "Notes", "rich_text"),
+            column("c3", "Score", "number"),
+        ];
+        let current = [
+            column("title", "Name", "title"),
+            column("a1", "State", "status"),
+            column("c3", "Score", "number"),
+            column("d4", "Due", "date"),
+        ];
+        let drift = Drift::detect(&previous, &current);
+        assert_eq!(
+            drift.to_string(),
+            "~ 'Status' was renamed to 'State'\n\
+             ~ 'State' changed from select to status\n\
+             - 'Notes' was removed\n\
+             + 'Due' was added (date)"
+        );
+        assert!(Drift::detect(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn the_cache_remembers_every_name_a_column_has_had() {
+        let mut cache = SchemaCache::open(Path::new(":memory:")).unwrap();
+        let before = [column("a1", "Status", "select")];
+        assert!(cache.drift("db", &before).unwrap().is_none());
+        cache.remember("db", &before).unwrap();
+
+        let after = [column("a1", "State", "select")];
+        let drift = cache.drift("db", &after).unwrap().unwrap();
+        assert_eq!(drift.renamed.len(), 1);
+        // Nothing changes until the new columns are remembered.
+        assert_eq!(cache.columns("db").unwrap().unwrap()[0].name, "Status");
+        cache.remember("db", &after).unwrap();
+        assert!(cache.drift("db", &after).unwrap().unwrap().is_empty());
+
+        let mut names = cache.renames("db").unwrap().remove("a1").unwrap();
+        names.sort();
+        assert_eq!(names, ["State", "Status"]);
+    }
+
+    #[test]
+    fn follow_renames_skips_names_in_use() {
+        let mut columns = [
+            column("a1", "State", "select"),
+            column("b2", "Stage", "select"),
+        ];
+        let renames = Renames::from([(
+            "a1".to_string(),
+            vec![
+                "Status".to_string(),
+                "Stage".to_string(),
+                "State".to_string(),
+            ],
+        )]);
+        follow_renames(&mut columns, &renames);
+        assert_eq!(columns[0].aliases, ["Status"]);
+        assert!(columns[1].aliases.is_empty());
+    }
+}
@@ -0,0 +1,519 @@
+use core::fmt;
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use serde::{
+    de::{self, value::MapAccessDeserializer, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use super::{property_schema, DatabaseSchema, SchemaChange, SchemaError};
+use crate::models::database::{Column, Relation, SelectOption};
+
+/// A database's expected title and columns, written by hand in YAML or TOML:
+///
+/// ```yaml
+/// title: Projects
+/// columns:
+///   Name: title
+///   Due: date
+///   Status:
+///     type: select
+///     options: [Planned, Active, { name: Done, color: green }]
+///   Client:
+///     type: relation
+///     database: 6f1c0d1e8e0a4b2c9a1d3f5e7b9c1a2d
+///   Summary:
+///     type: rich_text
+///     renamed_from: Notes
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaFile {
+    /// Left alone when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub columns: BTreeMap<String, ColumnSpec>,
+}
+
+/// A column's type, or its type along with its options or related database.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ColumnSpec {
+    Type(String),
+    Details(ColumnDetails),
+}
+
+struct ColumnSpecVisitor;
+
+impl<'de> Visitor<'de> for ColumnSpecVisitor {
+    type Value = ColumnSpec;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "a column type, or a map with its type and options or database"
+        )
+    }
+
+    fn visit_str<E: de::Error>(self, column_type: &str) -> Result<ColumnSpec, E> {
+        Ok(ColumnSpec::Type(column_type.to_string()))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<ColumnSpec, A::Error> {
+        // Deserialized directly rather than untagged, so typos are reported as such.
+        ColumnDetails::deserialize(MapAccessDeserializer::new(map)).map(ColumnSpec::Details)
+    }
+}
+
+impl<'de> Deserialize<'de> for ColumnSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ColumnSpec, D::Error> {
+        deserializer.deserialize_any(ColumnSpecVisitor)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnDetails {
+    #[serde(rename = "type")]
+    pub column_type: String,
+    /// For `select` and `multi_select` columns.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<OptionSpec>,
+    /// For `relation` columns, the id of the database related to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    /// The column's name before it was renamed, so the column is renamed
+    /// rather than removed, along with its values, and added again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_from: Option<String>,
+}
+
+/// An option's name, or its name and colour.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum OptionSpec {
+    Name(String),
+    Colored { name: String, color: String },
+}
+
+impl OptionSpec {
+    fn option(&self) -> SelectOption {
+        match self {
+            OptionSpec::Name(name) => SelectOption {
+                id: None,
+                name: name.clone(),
+                color: None,
+            },
+            OptionSpec::Colored { name, color } => SelectOption {
+                id: None,
+                name: name.clone(),
+                color: Some(color.clone()),
+            },
+        }
+    }
+}
+
+impl SchemaFile {
+    /// Reads a `.yaml`, `.yml` or `.toml` file.
+    pub fn load(path: &Path) -> Result<SchemaFile, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        let file = match extension {
+            "yaml" | "yml" => serde_yaml::from_str(&text).map_err(|err| err.to_string()),
+            "toml" => toml::from_str(&text).map_err(|err| err.to_string()),
+            _ => {
+                return Err(
+                    format!("{} should be a .yaml, .yml or .toml file", path.display()).into(),
+                )
+            }
+        };
+        file.map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+
+    /// The columns the file describes.
+    pub fn columns(&self) -> Result<Vec<Column>, SchemaError> {
+        self.columns
+            .iter()
+            .map(|(name, spec)| {
+                let column = match spec {
+                    ColumnSpec::Type(column_type) => Column {
+                        id: String::new(),
+                        name: name.clone(),
+                        column_type: column_type.clone(),
+                        relation: None,
+                        options: Vec::new(),
+                        aliases: Vec::new(),
+                    },
+                    ColumnSpec::Details(details) => {
+                        if !details.options.is_empty()
+                            && !matches!(details.column_type.as_str(), "select" | "multi_select")
+                        {
+                            return Err(SchemaError(format!(
+                                "'{}' is a {} column, so can't have options",
+                                name, details.column_type
+                            )));
+                        }
+                        Column {
+                            id: String::new(),
+                            name: name.clone(),
+                            column_type: details.column_type.clone(),
+                            relation: details.database.as_ref().map(|database_id| Relation {
+                                database_id: database_id.clone(),
+                                synced_property_id: None,
+                                synced_property_name: None,
+                            }),
+                            options: details.options.iter().map(OptionSpec::option).collect(),
+                            aliases: Vec::new(),
+                        }
+                    }
+                };
+                Ok(column)
+            })
+            .collect()
+    }
+
+    /// The name the column called `name` used to have, if it's been renamed.
+    fn renamed_from(&self, name: &str) -> Option<&str> {
+        match self.columns.get(name)? {
+            ColumnSpec::Details(details) => details.renamed_from.as_deref(),
+            ColumnSpec::Type(_) => None,
+        }
+    }
+
+    /// The file as a schema to create a database from.
+    pub fn schema(&self) -> Result<DatabaseSchema, SchemaError> {
+        Ok(DatabaseSchema {
+            title: self.title.clone().unwrap_or_default(),
+            columns: self.columns()?,
+        })
+    }
+}
+
+/// Whether two ids are the same, with or without dashes.
+fn same_id(a: &str, b: &str) -> bool {
+    a.replace('-', "") == b.replace('-', "")
+}
+
+/// Whether `live`'s options differ from those wanted. Colours only count
+/// where they're given, and no options at all leaves them alone.
+fn options_differ(wanted: &[SelectOption], live: &[SelectOption]) -> bool {
+    if wanted.is_empty() {
+        return false;
+    }
+    wanted.len() != live.len()
+        || wanted.iter().zip(live.iter()).any(|(wanted, live)| {
+            wanted.name != live.name
+                || wanted
+                    .color
+                    .as_ref()
+                    .is_some_and(|color| Some(color) != live.color.as_ref())
+        })
+}
+
+/// The changes that would make `live` match `file`: the title column is
+/// renamed if it's named differently, as are columns with a `renamed_from`,
+/// columns missing from the file are removed, and columns whose type or
+/// related database differ are changed.
+pub fn plan(file: &SchemaFile, live: &DatabaseSchema) -> Result<Vec<SchemaChange>, SchemaError> {
+    let wanted = file.columns()?;
+    let titles = wanted
+        .iter()
+        .filter(|column| column.column_type == "title")
+        .collect::<Vec<&Column>>();
+    let [title] = titles.as_slice() else {
+        return Err(SchemaError(format!(
+            "A database needs exactly one title column, but the file has {}",
+            titles.len()
+        )));
+    };
+    let find = |name: &str| live.columns.iter().find(|column| column.name == name);
+
+    let mut changes = Vec::new();
+    if let Some(database_title) = &file.title {
+        if database_title != &live.title {
+            changes.push(SchemaChange::RenameDatabase(database_title.clone()));
+        }
+    }
+    // A database always has a title column, so it's renamed rather than replaced.
+    let live_title = live
+        .columns
+        .iter()
+        .find(|column| column.column_type == "title");
+    let mut renamed_title = None;
+    if let Some(live_title) = live_title {
+        if live_title.name != title.name {
+            if let Some(taken) = find(&title.name) {
+                return Err(SchemaError(format!(
+                    "The title column can't be renamed to '{}', since that's a {} column",
+                    taken.name, taken.column_type
+                )));
+            }
+            changes.push(SchemaChange::RenameColumn {
+                name: live_title.name.clone(),
+                new_name: title.name.clone(),
+            });
+            renamed_title = Some(&live_title.name);
+        }
+    }
+
+    // Renames that are still to be made, from the old name to the new.
+    let renames = wanted
+        .iter()
+        .filter(|column| column.column_type != "title" && find(&column.name).is_none())
+        .filter_map(|column| {
+            let existing = find(file.renamed_from(&column.name)?)?;
+            let still_wanted = wanted.iter().any(|wanted| wanted.name == existing.name);
+            (existing.column_type != "title" && !still_wanted)
+                .then_some((&existing.name, &column.name))
+        })
+        .collect::<Vec<(&String, &String)>>();
+
+    for column in live.columns.iter() {
+        let kept = wanted.iter().any(|wanted| wanted.name == column.name)
+            || renames.iter().any(|(name, _)| *name == &column.name);
+        if !kept && Some(&column.name) != renamed_title {
+            changes.push(SchemaChange::RemoveColumn(column.name.clone()));
+        }
+    }
+
+    for column in wanted.iter() {
+        if column.column_type == "title" {
+            continue;
+        }
+        let renamed = renames
+            .iter()
+            .find(|(_, new_name)| *new_name == &column.name)
+            .and_then(|(name, _)| find(name));
+        let Some(existing) = find(&column.name).or(renamed) else {
+            property_schema(column)?;
+            changes.push(SchemaChange::AddColumn(column.clone()));
+            continue;
+        };
+        if existing.name != column.name {
+            changes.push(SchemaChange::RenameColumn {
+                name: existing.name.clone(),
+                new_name: column.name.clone(),
+            });
+        }
+        let relation_differs = match (&column.relation, &existing.relation) {
+            (Some(wanted), Some(live)) => !same_id(&wanted.database_id, &live.database_id),
+            _ => false,
+        };
+        if column.column_type != existing.column_type || relation_differs {
+            property_schema(column)?;
+            changes.push(SchemaChange::ChangeType {
+                from: existing.column_type.clone(),
+                column: column.clone(),
+            });
+        } else if matches!(column.column_type.as_str(), "select" | "multi_select")
+            && options_differ(&column.options, &existing.options)
+        {
+            changes.push(SchemaChange::SetOptions {
+                name: column.name.clone(),
+                options: column.options.clone(),
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// Columns `changes` remove while adding another of the same type, which is
+/// usually a rename that's missing a `renamed_from`, as `(removed, added)`.
+pub fn probable_renames(changes: &[SchemaChange], live: &DatabaseSchema) -> Vec<(String, String)> {
+    changes
+        .iter()
+        .filter_map(|change| match change {
+            SchemaChange::RemoveColumn(name) => {
+                live.columns.iter().find(|column| &column.name == name)
+            }
+            _ => None,
+        })
+        .flat_map(|removed| {
+            changes.iter().filter_map(move |change| match change {
+                SchemaChange::AddColumn(added) if added.column_type == removed.column_type => {
+                    Some((removed.name.clone(), added.name.clone()))
+                }
+                _ => None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::database::fixtures::column;
+    use crate::schema::change_body;
+    use serde_json::json;
+
+    fn live() -> DatabaseSchema {
+        DatabaseSchema {
+            title: "Projects".to_string(),
+            columns: vec![
+                column("title", "Name", "title"),
+                column("a1", "Due", "date"),
+                column("b2", "Notes", "rich_text"),
+                Column {
+                    options: vec![SelectOption {
+                        id: Some("o1".to_string()),
+                        name: "Active".to_string(),
+                        color: Some("blue".to_string()),
+                    }],
+                    ..column("c3", "Status", "select")
+                },
+            ],
+        }
+    }
+
+    fn file(yaml: &str) -> SchemaFile {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn plan_lines(yaml: &str) -> Vec<String> {
+        plan(&file(yaml), &live())
+            .unwrap()
+            .iter()
+            .map(SchemaChange::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn a_matching_file_plans_nothing() {
+        let yaml = "title: Projects\ncolumns:\n  Name: title\n  Due: date\n  Notes: rich_text\n  Status: select\n";
+        assert!(plan_lines(yaml).is_empty());
+    }
+
+    #[test]
+    fn the_title_column_is_renamed_rather_than_removed() {
+        let yaml =
+            "columns:\n  Project: title\n  Due: date\n  Notes: rich_text\n  Status: select\n";
+        assert_eq!(plan_lines(yaml), ["~ rename 'Name' to 'Project'"]);
+    }
+
+    #[test]
+    fn columns_missing_from_the_file_are_removed() {
+        let yaml = "title: Work\ncolumns:\n  Name: title\n  Due: rich_text\n  Owner: people\n  Status:\n    type: select\n    options: [Active, { name: Done, color: green }]\n";
+        assert_eq!(
+            plan_lines(yaml),
+            [
+                "~ rename the database to 'Work'",
+                "- remove 'Notes'",
+                "~ change 'Due' from date to rich_text",
+                "+ add 'Owner' (people)",
+                "~ set 'Status' options to Active, Done",
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_columns_keep_their_values() {
+        let yaml = "columns:\n  Name: title\n  Due: date\n  Summary:\n    type: rich_text\n    renamed_from: Notes\n  Status: select\n";
+        let changes = plan(&file(yaml), &live()).unwrap();
+        assert_eq!(
+            changes
+                .iter()
+                .map(SchemaChange::to_string)
+                .collect::<Vec<String>>(),
+            ["~ rename 'Notes' to 'Summary'"]
+        );
+        assert!(probable_renames(&changes, &live()).is_empty());
+
+        // Once it's been renamed, there's nothing left to do.
+        let mut renamed = live();
+        renamed.columns[2].name = "Summary".to_string();
+        assert!(plan(&file(yaml), &renamed).unwrap().is_empty());
+    }
+
+    #[test]
+    fn removing_and_adding_the_same_type_looks_like_a_rename() {
+        let yaml = "columns:\n  Name: title\n  Due: date\n  Summary: rich_text\n  Owner: people\n  Status: select\n";
+        let changes = plan(&file(yaml), &live()).unwrap();
+        assert_eq!(
+            probable_renames(&changes, &live()),
+            [("Notes".to_string(), "Summary".to_string())]
+        );
+    }
+
+    #[test]
+    fn a_file_needs_one_title_column() {
+        let err = plan(&file("columns:\n  Due: date\n"), &live()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "A database needs exactly one title column, but the file has 0"
+        );
+    }
+
+    #[test]
+    fn toml_files_read_the_same_as_yaml() {
+        let toml = r#"
+            title = "Projects"
+            [columns]
+            Name = "title"
+            Status = { type = "select", options = ["Active", { name = "Done", color = "green" }] }
+        "#;
+        let yaml = "title: Projects\ncolumns:\n  Name: title\n  Status:\n    type: select\n    options: [Active, { name: Done, color: green }]\n";
+        assert_eq!(toml::from_str::<SchemaFile>(toml).unwrap(), file(yaml));
+    }
+
+    #[test]
+    fn typos_in_a_column_are_reported() {
+        let err = serde_yaml::from_str::<SchemaFile>("columns:\n  Due:\n    typ: date\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown field `typ`"), "{}", err);
+    }
+
+    #[test]
+    fn change_body_addresses_existing_columns_by_id() {
+        let live = live();
+        let yaml = "columns:\n  Project: title\n  Due: date\n  Owner: people\n  Status:\n    type: select\n    options: [Active, { name: Done, color: green }]\n";
+        let changes = plan(&file(yaml), &live).unwrap();
+        assert_eq!(
+            change_body(&changes, &live.columns).unwrap(),
+            json!({ "properties": {
+                "title": { "name": "Project" },
+                "b2": null,
+                "Owner": { "people": {} },
+                "c3": { "select": { "options": [
+                    { "id": "o1", "name": "Active", "color": "blue" },
+                    { "name": "Done", "color": "green" },
+                ] } },
+            } })
+        );
+    }
+
+    #[test]
+    fn change_body_follows_renames_to_the_column_changed() {
+        let live = live();
+        let yaml = "columns:\n  Name: title\n  Due: date\n  Summary:\n    type: url\n    renamed_from: Notes\n  Status: select\n";
+        let changes = plan(&file(yaml), &live).unwrap();
+        assert_eq!(
+            change_body(&changes, &live.columns).unwrap(),
+            json!({ "properties": { "b2": { "name": "Summary", "url": {} } } })
+        );
+    }
+
+    #[test]
+    fn change_body_refuses_unknown_and_duplicate_columns() {
+        let columns = live().columns;
+        let err = |change: SchemaChange| change_body(&[change], &columns).unwrap_err().to_string();
+        assert_eq!(
+            err(SchemaChange::RemoveColumn("Budget".to_string())),
+            "The column 'Budget' does not exist."
+        );
+        assert_eq!(
+            err(SchemaChange::AddColumn(column("", "Due", "date"))),
+            "There's already a column named 'Due'"
+        );
+        assert_eq!(
+            err(SchemaChange::SetOptions {
+                name: "Notes".to_string(),
+                options: Vec::new(),
+            }),
+            "'Notes' is a rich_text column, whose options can't be set through Notion's API"
+        );
+    }
+}
@@ -0,0 +1,484 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+use crate::{
+    export::markdown,
+    models::{
+        blocks::Blocks,
+        content::{download_images, fetch_page_content},
+        database::{Column, Row},
+    },
+    render::escape_html,
+    template::Template,
+};
+
+const LAYOUT: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+<style>
+body { max-width: 42rem; margin: 2rem auto; padding: 0 1rem; font-family: system-ui, sans-serif; line-height: 1.6; }
+.tags a { margin-right: 0.5rem; }
+img { max-width: 100%; }
+</style>
+</head>
+<body>
+<header><a href="index.html">{{site_title}}</a></header>
+<main>
+{{body}}
+</main>
+</body>
+</html>
+"#;
+
+const INDEX: &str = r#"<h1>{{site_title}}</h1>
+<ul>
+{{items}}</ul>
+{{#if tags}}<p class="tags">{{tags}}</p>{{/if}}
+"#;
+
+const ITEM: &str = r#"<li><a href="{{url}}">{{title}}</a>{{#if tags}} <span class="tags">{{tags}}</span>{{/if}}</li>
+"#;
+
+const PAGE: &str = r#"<article>
+<h1>{{title}}</h1>
+{{#if tags}}<p class="tags">{{tags}}</p>{{/if}}
+{{content}}
+</article>
+"#;
+
+const TAG: &str = r#"<h1>{{tag}}</h1>
+<ul>
+{{items}}</ul>
+"#;
+
+/// The templates a site is built from. Row templates (`item` and `page`) can
+/// use the row's columns, as well as the variables described on each field.
+pub struct SiteTemplates {
+    /// Wraps every page: `{{title}}`, `{{site_title}}` and `{{body}}`.
+    pub layout: Template,
+    /// `{{site_title}}`, `{{items}}` and `{{tags}}`.
+    pub index: Template,
+    /// A row in a list: `{{title}}`, `{{url}}` and `{{tags}}`.
+    pub item: Template,
+    /// A row's own page: `{{title}}`, `{{tags}}` and `{{content}}`.
+    pub page: Template,
+    /// `{{tag}}` and `{{items}}`.
+    pub tag: Template,
+}
+
+impl SiteTemplates {
+    /// Loads `layout.html`, `index.html`, `item.html`, `page.html` and
+    /// `tag.html` from `dir`, using the built-in template for any that are missing.
+    pub fn load(dir: Option<&Path>) -> Result<SiteTemplates, Box<dyn Error>> {
+        let load = |name: &str, default: &str| -> Result<Template, Box<dyn Error>> {
+            let path = dir.map(|dir| dir.join(name)).filter(|path| path.exists());
+            let source = match &path {
+                Some(path) => fs::read_to_string(path)?,
+                None => default.to_string(),
+            };
+            Template::parse(&source).map_err(|err| format!("{}: {}", name, err).into())
+        };
+        Ok(SiteTemplates {
+            layout: load("layout.html", LAYOUT)?,
+            index: load("index.html", INDEX)?,
+            item: load("item.html", ITEM)?,
+            page: load("page.html", PAGE)?,
+            tag: load("tag.html", TAG)?,
+        })
+    }
+}
+
+pub struct SiteOptions<'a> {
+    pub title: String,
+    /// `multi_select` column to build tag pages from.
+    pub tag_column: Option<&'a Column>,
+}
+
+fn compare_blocks(a: Option<&Blocks>, b: Option<&Blocks>) -> Ordering {
+    match (a, b) {
+        (Some(Blocks::Number(a)), Some(Blocks::Number(b))) => a.total_cmp(b),
+        (Some(a), Some(b)) => a
+            .to_string()
+            .to_lowercase()
+            .cmp(&b.to_string().to_lowercase()),
+        // Rows without a value go last.
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Sorts rows by a column's values. Numbers sort numerically and everything
+/// else alphabetically, which puts dates and timestamps in order too. Rows
+/// without a value go last either way.
+pub fn sort_rows(rows: &mut [Row], column: &Column, descending: bool) {
+    rows.sort_by(|a, b| {
+        let (a, b) = (a.block(column), b.block(column));
+        if descending && a.is_some() && b.is_some() {
+            compare_blocks(b, a)
+        } else {
+            compare_blocks(a, b)
+        }
+    });
+}
+
+/// A lowercase, hyphenated version of `text` for use in file names.
+fn slug(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+fn unique_slug(slug: String, taken: &mut Vec<String>) -> String {
+    let mut candidate = slug.clone();
+    let mut i = 2;
+    while taken.contains(&candidate) {
+        candidate = format!("{}-{}", slug, i);
+        i += 1;
+    }
+    taken.push(candidate.clone());
+    candidate
+}
+
+/// Whether following a link can't run anything: it's relative, or to the
+/// web, an email address or a phone number.
+fn is_safe_url(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => matches!(
+            scheme.to_lowercase().as_str(),
+            "http" | "https" | "mailto" | "tel"
+        ),
+        _ => true,
+    }
+}
+
+/// Toggles and line breaks in table cells are the only HTML
+/// `markdown::blocks` writes, so anything else is shown as text. A toggle's
+/// summary is Markdown itself.
+fn allowed_html(html: &str) -> String {
+    html.split_inclusive('\n')
+        .map(|line| {
+            let tag = line.trim_end();
+            let kept = match tag {
+                "<details>" | "</details>" | "<br>" => tag.to_string(),
+                _ => match tag
+                    .strip_prefix("<summary>")
+                    .and_then(|tag| tag.strip_suffix("</summary>"))
+                {
+                    Some(summary) => {
+                        let summary = markdown_to_html(summary);
+                        let summary = summary.trim();
+                        let summary = summary
+                            .strip_prefix("<p>")
+                            .and_then(|summary| summary.strip_suffix("</p>"))
+                            .unwrap_or(summary);
+                        format!("<summary>{}</summary>", summary)
+                    }
+                    None => escape_html(tag),
+                },
+            };
+            kept + &line[tag.len()..]
+        })
+        .collect()
+}
+
+/// A link's destination, or an empty one if following it could run something.
+fn safe_url(url: CowStr) -> CowStr {
+    if is_safe_url(&url) {
+        url
+    } else {
+        CowStr::Borrowed("")
+    }
+}
+
+fn markdown_to_html(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(html) => Event::Html(allowed_html(&html).into()),
+        Event::InlineHtml(html) => Event::InlineHtml(allowed_html(&html).into()),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut out = String::new();
+    html::push_html(&mut out, events);
+    out
+}
+
+fn tag_links(tags: &[String], tag_urls: &HashMap<String, String>) -> String {
+    tags.iter()
+        .map(|tag| format!("<a href=\"{}\">{}</a>", tag_urls[tag], escape_html(tag)))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// The variables a row's list item and page are rendered with, besides its columns.
+fn row_variables(title: &str, slug: &str, tags: String) -> HashMap<String, String> {
+    HashMap::from([
+        ("title".to_string(), escape_html(title)),
+        ("url".to_string(), format!("{}.html", slug)),
+        ("tags".to_string(), tags),
+    ])
+}
+
+/// Builds a static site in `out`: an index listing `rows` in the order given,
+/// a page per row, and a page per tag. Images uploaded to Notion are saved in
+/// `out/images`. Returns the paths written.
+pub async fn build_site(
+    token: &str,
+    out: &Path,
+    rows: &[Row],
+    options: &SiteOptions<'_>,
+    templates: &SiteTemplates,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    fs::create_dir_all(out)?;
+    let mut taken = vec!["index".to_string()];
+
+    let mut tags: Vec<String> = Vec::new();
+    for row in rows.iter() {
+        let row_tags = options
+            .tag_column
+            .and_then(|column| row.block(column))
+            .map(|block| block.items())
+            .unwrap_or_default();
+        for tag in row_tags {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    }
+    tags.sort_by_key(|tag| tag.to_lowercase());
+    let tag_urls = tags
+        .iter()
+        .map(|tag| {
+            let slug = unique_slug(format!("tag-{}", slug(tag)), &mut taken);
+            (tag.clone(), format!("{}.html", slug))
+        })
+        .collect::<HashMap<String, String>>();
+
+    let site_title = escape_html(&options.title);
+    let mut written = Vec::new();
+    let mut write_page = |name: &str, title: &str, body: String| -> std::io::Result<()> {
+        let variables = HashMap::from([
+            ("title".to_string(), title.to_string()),
+            ("site_title".to_string(), site_title.clone()),
+            ("body".to_string(), body),
+        ]);
+        let path = out.join(name);
+        fs::write(
+            &path,
+            templates
+                .layout
+                .render_escaped(&HashMap::new(), &variables, escape_html),
+        )?;
+        written.push(path);
+        Ok(())
+    };
+
+    // Each row's list item, along with its tags for the tag pages.
+    let mut items = Vec::new();
+    for row in rows.iter() {
+        let title = row.title().unwrap_or_else(|| row.id.clone());
+        let slug = unique_slug(
+            Some(slug(&title))
+                .filter(|slug| !slug.is_empty())
+                .unwrap_or_else(|| row.id.clone()),
+            &mut taken,
+        );
+        let row_tags = options
+            .tag_column
+            .and_then(|column| row.block(column))
+            .map(|block| block.items())
+            .unwrap_or_default();
+        let variables = row_variables(&title, &slug, tag_links(&row_tags, &tag_urls));
+        let item = templates.item.render_escaped(
+            &row.template_values_with(&variables),
+            &variables,
+            escape_html,
+        );
+
+        let mut content = fetch_page_content(token, &row.id).await?;
+        download_images(&mut content, &out.join("images"), "images").await?;
+        let mut page_variables = variables.clone();
+        page_variables.insert(
+            "content".to_string(),
+            markdown_to_html(&markdown::blocks(&content)),
+        );
+        let page = templates.page.render_escaped(
+            &row.template_values_with(&page_variables),
+            &page_variables,
+            escape_html,
+        );
+        write_page(
+            &variables["url"],
+            &format!("{} · {}", variables["title"], site_title),
+            page,
+        )?;
+        items.push((row_tags, item));
+    }
+
+    for tag in tags.iter() {
+        let variables = HashMap::from([
+            ("tag".to_string(), escape_html(tag)),
+            (
+                "items".to_string(),
+                items
+                    .iter()
+                    .filter(|(row_tags, _)| row_tags.contains(tag))
+                    .map(|(_, item)| item.as_str())
+                    .collect(),
+            ),
+        ]);
+        let page = templates
+            .tag
+            .render_escaped(&HashMap::new(), &variables, escape_html);
+        write_page(
+            &tag_urls[tag],
+            &format!("{} · {}", escape_html(tag), site_title),
+            page,
+        )?;
+    }
+
+    let variables = HashMap::from([
+        ("site_title".to_string(), site_title.clone()),
+        (
+            "items".to_string(),
+            items.iter().map(|(_, item)| item.as_str()).collect(),
+        ),
+        ("tags".to_string(), tag_links(&tags, &tag_urls)),
+    ]);
+    let index = templates
+        .index
+        .render_escaped(&HashMap::new(), &variables, escape_html);
+    write_page("index.html", &site_title, index)?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::database::fixtures::{column, row};
+
+    #[test]
+    fn html_in_content_is_shown_as_text() {
+        let html = markdown_to_html("<script>alert(1)</script>\n\nHi <img src=x onerror=alert(1)>");
+        assert!(!html.contains("<script>"), "{}", html);
+        assert!(!html.contains("<img"), "{}", html);
+        assert!(
+            html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"),
+            "{}",
+            html
+        );
+
+        // Except for toggles, whose summaries are Markdown too.
+        let html = markdown_to_html(
+            "<details>\n<summary>**More** \\<b\\></summary>\n\nInside\n\n</details>",
+        );
+        assert_eq!(
+            html,
+            "<details>\n<summary><strong>More</strong> &lt;b&gt;</summary>\n<p>Inside</p>\n</details>"
+        );
+    }
+
+    #[test]
+    fn links_that_could_run_scripts_go_nowhere() {
+        let html = markdown_to_html(
+            "[a](javascript:alert(1)) [b](JavaScript:alert(1)) [c](https://example.com) [d](page.html?at=1:2) ![e](data:image/svg+xml,x)",
+        );
+        assert_eq!(html.matches(r#"href="""#).count(), 2, "{}", html);
+        assert!(html.contains(r#"href="https://example.com""#), "{}", html);
+        assert!(html.contains(r#"href="page.html?at=1:2""#), "{}", html);
+        assert!(html.contains(r#"src="""#), "{}", html);
+    }
+
+    #[test]
+    fn slugs_are_unique() {
+        assert_eq!(slug("Hello, World! 2024"), "hello-world-2024");
+        assert_eq!(slug("Café au lait"), "café-au-lait");
+        let mut taken = vec!["index".to_string()];
+        assert_eq!(unique_slug("index".to_string(), &mut taken), "index-2");
+        assert_eq!(unique_slug("index".to_string(), &mut taken), "index-3");
+        assert_eq!(unique_slug("about".to_string(), &mut taken), "about");
+    }
+
+    #[test]
+    fn rows_sort_numerically_with_missing_values_last() {
+        let score = column("s1", "Score", "number");
+        let mut rows = [Some(10), None, Some(9), Some(100)]
+            .iter()
+            .enumerate()
+            .map(|(i, number)| {
+                let mut row =
+                    row(json!({ "Score": { "id": "s1", "type": "number", "number": number } }));
+                row.id = format!("p{}", i);
+                row
+            })
+            .collect::<Vec<Row>>();
+        let ids = |rows: &[Row]| {
+            rows.iter()
+                .map(|row| row.id.clone())
+                .collect::<Vec<String>>()
+        };
+
+        sort_rows(&mut rows, &score, false);
+        assert_eq!(ids(&rows), ["p2", "p0", "p3", "p1"]);
+        sort_rows(&mut rows, &score, true);
+        assert_eq!(ids(&rows), ["p3", "p0", "p2", "p1"]);
+    }
+
+    #[test]
+    fn titles_and_tags_are_escaped() {
+        let tags = vec!["<b>&".to_string()];
+        let tag_urls = HashMap::from([(tags[0].clone(), "tag-b.html".to_string())]);
+        let variables = row_variables(
+            "<script>x</script>",
+            "script-x",
+            tag_links(&tags, &tag_urls),
+        );
+        let item = SiteTemplates::load(None).unwrap().item.render_escaped(
+            &HashMap::new(),
+            &variables,
+            escape_html,
+        );
+        assert_eq!(
+            item,
+            "<li><a href=\"script-x.html\">&lt;script&gt;x&lt;/script&gt;</a> <span class=\"tags\"><a href=\"tag-b.html\">&lt;b&gt;&amp;</a></span></li>\n"
+        );
+    }
+}
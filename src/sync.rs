@@ -0,0 +1,296 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::models::{
+    database::{query_notion_database, Column, DatabaseCredentials, Row},
+    filters::{DateFilter, QueryFilter, TimestampFilter},
+};
+
+#[derive(Debug)]
+pub struct SyncOptions {
+    /// How long to go between full scans of the database's row ids, which is
+    /// the only way to notice rows that were deleted or moved to the trash.
+    pub full_scan_interval: Duration,
+    pub force_full_scan: bool,
+    /// Columns with earlier names (`Column.aliases`), whose cells the mirror
+    /// has under those names are moved to the columns' current names.
+    pub renamed: Vec<Column>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            full_scan_interval: Duration::from_secs(60 * 60 * 24),
+            force_full_scan: false,
+            renamed: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub upserted: usize,
+    pub removed: usize,
+    pub full_scan: bool,
+    pub cursor: Option<String>,
+}
+
+struct SyncCursor {
+    last_edited_time: Option<String>,
+    last_full_scan: Option<u64>,
+}
+
+/// Opens (creating if need be) a SQLite mirror at `path`.
+pub fn open_mirror(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sync_cursors (
+            database_id TEXT PRIMARY KEY,
+            last_edited_time TEXT,
+            last_full_scan INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS rows (
+            id TEXT PRIMARY KEY,
+            database_id TEXT NOT NULL,
+            created_time TEXT NOT NULL,
+            last_edited_time TEXT NOT NULL,
+            url TEXT NOT NULL,
+            properties TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS cells (
+            row_id TEXT NOT NULL REFERENCES rows(id) ON DELETE CASCADE,
+            property TEXT NOT NULL,
+            property_type TEXT NOT NULL,
+            value TEXT,
+            PRIMARY KEY (row_id, property)
+        );
+        CREATE INDEX IF NOT EXISTS rows_database_id ON rows(database_id);
+        PRAGMA foreign_keys = ON;",
+    )?;
+    Ok(conn)
+}
+
+fn read_cursor(conn: &Connection, database_id: &str) -> rusqlite::Result<SyncCursor> {
+    let cursor = conn
+        .query_row(
+            "SELECT last_edited_time, last_full_scan FROM sync_cursors WHERE database_id = ?1",
+            params![database_id],
+            |row| {
+                Ok(SyncCursor {
+                    last_edited_time: row.get(0)?,
+                    last_full_scan: row.get(1)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(cursor.unwrap_or(SyncCursor {
+        last_edited_time: None,
+        last_full_scan: None,
+    }))
+}
+
+fn write_cursor(
+    conn: &Connection,
+    database_id: &str,
+    cursor: &SyncCursor,
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO sync_cursors (database_id, last_edited_time, last_full_scan)
+         VALUES (?1, ?2, ?3)
+         ON CONFLICT(database_id) DO UPDATE SET
+            last_edited_time = excluded.last_edited_time,
+            last_full_scan = excluded.last_full_scan",
+        params![database_id, cursor.last_edited_time, cursor.last_full_scan],
+    )
+}
+
+fn upsert_row(conn: &Connection, database_id: &str, row: &Row) -> Result<(), Box<dyn Error>> {
+    conn.execute(
+        "INSERT INTO rows (id, database_id, created_time, last_edited_time, url, properties)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(id) DO UPDATE SET
+            last_edited_time = excluded.last_edited_time,
+            url = excluded.url,
+            properties = excluded.properties",
+        params![
+            row.id,
+            database_id,
+            row.created_time,
+            row.last_edited_time,
+            row.url,
+            serde_json::to_string(&row.properties)?,
+        ],
+    )?;
+    conn.execute("DELETE FROM cells WHERE row_id = ?1", params![row.id])?;
+
+    if let Some(properties) = &row.properties {
+        for (name, cell) in properties.iter() {
+            conn.execute(
+                "INSERT INTO cells (row_id, property, property_type, value) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    row.id,
+                    name,
+                    cell.cell_type,
+                    cell.block.as_ref().map(|block| block.to_string())
+                ],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the cells of renamed columns to their current names, so the mirror
+/// has each column under one name. Cells already under the current name win.
+fn rename_cells(conn: &Connection, database_id: &str, renamed: &[Column]) -> rusqlite::Result<()> {
+    for column in renamed.iter() {
+        for alias in column.aliases.iter() {
+            conn.execute(
+                "UPDATE OR IGNORE cells SET property = ?1
+                 WHERE property = ?2
+                    AND row_id IN (SELECT id FROM rows WHERE database_id = ?3)",
+                params![column.name, alias, database_id],
+            )?;
+            conn.execute(
+                "DELETE FROM cells
+                 WHERE property = ?1
+                    AND row_id IN (SELECT id FROM rows WHERE database_id = ?2)",
+                params![alias, database_id],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_row(conn: &Connection, id: &str) -> rusqlite::Result<usize> {
+    conn.execute("DELETE FROM rows WHERE id = ?1", params![id])
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Brings the mirror up to date with the database, only fetching rows that
+/// were edited since the last sync. Every `full_scan_interval` the whole
+/// database is listed too, so rows that have disappeared can be removed.
+pub async fn sync_database(
+    credentials: &DatabaseCredentials,
+    conn: &Connection,
+    options: &SyncOptions,
+) -> Result<SyncReport, Box<dyn Error>> {
+    let mut cursor = read_cursor(conn, &credentials.id)?;
+    let mut report = SyncReport::default();
+
+    // Notion rounds last_edited_time down to the minute, so rows edited in
+    // the same minute as the cursor are fetched again rather than missed.
+    let filter = cursor.last_edited_time.as_ref().map(|last_edited_time| {
+        QueryFilter::Timestamp(Box::new(TimestampFilter {
+            timestamp: "last_edited_time".to_string(),
+            last_edited_time: Some(DateFilter {
+                on_or_after: Some(last_edited_time.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }))
+    });
+    let rows = query_notion_database(credentials, filter.as_ref()).await?;
+
+    let full_scan_due = cursor.last_full_scan.is_none_or(|last_full_scan| {
+        now().saturating_sub(last_full_scan) >= options.full_scan_interval.as_secs()
+    });
+    let live = |rows: &[Row]| -> HashSet<String> {
+        rows.iter()
+            .filter(|row| !row.archived && !row.in_trash)
+            .map(|row| row.id.clone())
+            .collect()
+    };
+    let live_ids = match (options.force_full_scan || full_scan_due, &filter) {
+        (false, _) => None,
+        // Without a cursor every row was just fetched, so they needn't be again.
+        (true, None) => Some(live(&rows)),
+        (true, Some(_)) => Some(live(&query_notion_database(credentials, None).await?)),
+    };
+
+    let transaction = conn.unchecked_transaction()?;
+    rename_cells(&transaction, &credentials.id, &options.renamed)?;
+    for row in rows.iter() {
+        if row.archived || row.in_trash {
+            report.removed += remove_row(&transaction, &row.id)?;
+            continue;
+        }
+        upsert_row(&transaction, &credentials.id, row)?;
+        report.upserted += 1;
+    }
+
+    cursor.last_edited_time = rows
+        .iter()
+        .map(|row| row.last_edited_time.clone())
+        .chain(cursor.last_edited_time.clone())
+        .max();
+
+    if let Some(live_ids) = live_ids {
+        let mirrored_ids = transaction
+            .prepare("SELECT id FROM rows WHERE database_id = ?1")?
+            .query_map(params![credentials.id], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        for id in mirrored_ids.iter().filter(|id| !live_ids.contains(*id)) {
+            report.removed += remove_row(&transaction, id)?;
+        }
+        cursor.last_full_scan = Some(now());
+        report.full_scan = true;
+    }
+
+    write_cursor(&transaction, &credentials.id, &cursor)?;
+    transaction.commit()?;
+    report.cursor = cursor.last_edited_time;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rename_cells_moves_cells_to_current_names() {
+        let conn = open_mirror(Path::new(":memory:")).unwrap();
+        conn.execute_batch(
+            "INSERT INTO rows VALUES ('r1', 'db', '', '', '', '{}'), ('r2', 'db', '', '', '', '{}');
+             INSERT INTO cells VALUES ('r1', 'Status', 'select', 'Old'),
+                ('r2', 'Status', 'select', 'Stale'), ('r2', 'State', 'select', 'New');",
+        )
+        .unwrap();
+        let renamed = Column {
+            id: "a1".to_string(),
+            name: "State".to_string(),
+            column_type: "select".to_string(),
+            relation: None,
+            options: Vec::new(),
+            aliases: vec!["Status".to_string()],
+        };
+        rename_cells(&conn, "db", &[renamed]).unwrap();
+
+        let cells = conn
+            .prepare("SELECT row_id, property, value FROM cells ORDER BY row_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(String, String, String)>>>()
+            .unwrap();
+        assert_eq!(
+            cells,
+            [
+                ("r1".to_string(), "State".to_string(), "Old".to_string()),
+                ("r2".to_string(), "State".to_string(), "New".to_string()),
+            ]
+        );
+    }
+}
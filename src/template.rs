@@ -0,0 +1,599 @@
+use core::fmt;
+use std::{collections::HashMap, error::Error, fmt::Write};
+
+use chrono::format::{Item, StrftimeItems};
+
+use crate::models::blocks::{parse_date, Blocks};
+
+/// A piece of a parsed template.
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    /// `{{Column Name}}`, or `{{Column Name | helper ...}}`
+    Placeholder {
+        name: String,
+        helpers: Vec<Helper>,
+    },
+    /// `{{#if Column}}...{{else}}...{{/if}}`, or `{{#unless Column}}` when negated.
+    If {
+        name: String,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    /// `{{#each Column}}...{{this}}...{{/each}}`
+    Each {
+        name: String,
+        body: Vec<Node>,
+    },
+}
+
+/// Formatting applied to a placeholder's value, e.g. `{{Due | date "%d %B %Y"}}`.
+#[derive(Debug, Clone)]
+enum Helper {
+    Upper,
+    Lower,
+    /// A strftime format, applied to RFC 3339 timestamps and `YYYY-MM-DD` dates.
+    /// Date ranges are formatted by their start.
+    Date(String),
+    /// Decimal places, with thousands separated by commas.
+    Number(usize),
+    /// Separator for the items of a list such as a multi-select.
+    Join(String),
+}
+
+enum Token {
+    Text(String),
+    Tag { tag: String, line: usize },
+}
+
+#[derive(Debug)]
+pub struct TemplateError {
+    pub message: String,
+    pub line: usize,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (line {})", self.message, self.line)
+    }
+}
+
+impl Error for TemplateError {}
+
+/// A template with `{{Column Name}}` placeholders, filled in from a row's values.
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            tokens.push(Token::Text(rest[..start].to_string()));
+        }
+        line += rest[..start].matches('\n').count();
+
+        let end = rest[start..].find("}}").ok_or_else(|| TemplateError {
+            message: "Unclosed '{{'".to_string(),
+            line,
+        })?;
+        let tag = &rest[start + 2..start + end];
+        tokens.push(Token::Tag {
+            tag: tag.trim().to_string(),
+            line,
+        });
+        line += tag.matches('\n').count();
+        rest = &rest[start + end + 2..];
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest.to_string()));
+    }
+    Ok(tokens)
+}
+
+/// Splits `text` on `separator`, except where it's inside double quotes.
+fn split_unquoted(text: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                parts.last_mut().unwrap().push(c);
+            }
+            c if c == separator && !quoted => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+fn parse_helper(source: &str, line: usize) -> Result<Helper, TemplateError> {
+    let mut words = split_unquoted(source.trim(), ' ')
+        .into_iter()
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            word.strip_prefix('"')
+                .and_then(|word| word.strip_suffix('"'))
+                .map(str::to_string)
+                .unwrap_or(word)
+        });
+    let error = |message: String| TemplateError { message, line };
+
+    let name = words.next().unwrap_or_default();
+    let argument = words.next();
+    if words.next().is_some() {
+        return Err(error(format!(
+            "Too many arguments to '{}'; quote arguments containing spaces",
+            name
+        )));
+    }
+
+    match (name.as_str(), argument) {
+        ("upper", None) => Ok(Helper::Upper),
+        ("lower", None) => Ok(Helper::Lower),
+        ("date", Some(format)) => {
+            if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+                return Err(error(format!("'{}' isn't a valid date format", format)));
+            }
+            Ok(Helper::Date(format))
+        }
+        ("date", None) => Ok(Helper::Date("%Y-%m-%d".to_string())),
+        ("number", None) => Ok(Helper::Number(0)),
+        ("number", Some(places)) => places
+            .parse()
+            .map(Helper::Number)
+            .map_err(|_| error(format!("'{}' isn't a number of decimal places", places))),
+        ("join", Some(separator)) => Ok(Helper::Join(separator)),
+        ("join", None) => Ok(Helper::Join(", ".to_string())),
+        ("upper" | "lower", Some(_)) => Err(error(format!("'{}' takes no arguments", name))),
+        _ => Err(error(format!("Unknown helper '{}'", name))),
+    }
+}
+
+fn parse_placeholder(tag: &str, line: usize) -> Result<Node, TemplateError> {
+    let mut parts = split_unquoted(tag, '|').into_iter();
+    let name = parts.next().unwrap_or_default().trim().to_string();
+    let helpers = parts
+        .map(|helper| parse_helper(&helper, line))
+        .collect::<Result<Vec<Helper>, _>>()?;
+    Ok(Node::Placeholder { name, helpers })
+}
+
+/// Parses nodes until `{{/closing}}` (or `{{else}}`, if `closing` is `if`/`unless`),
+/// returning them along with whether parsing stopped at an `{{else}}`.
+fn parse_nodes(
+    tokens: &mut std::vec::IntoIter<Token>,
+    closing: Option<(&str, usize)>,
+) -> Result<(Vec<Node>, bool), TemplateError> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let (tag, line) = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text));
+                continue;
+            }
+            Token::Tag { tag, line } => (tag, line),
+        };
+
+        if let Some(name) = tag.strip_prefix('/') {
+            return match closing {
+                Some((block, _)) if block == name.trim() => Ok((nodes, false)),
+                _ => Err(TemplateError {
+                    message: format!("Unexpected '{{{{/{}}}}}'", name.trim()),
+                    line,
+                }),
+            };
+        }
+
+        if tag == "else" {
+            return match closing {
+                Some(("if" | "unless", _)) => Ok((nodes, true)),
+                _ => Err(TemplateError {
+                    message: "Unexpected '{{else}}'".to_string(),
+                    line,
+                }),
+            };
+        }
+
+        if let Some(block) = tag.strip_prefix('#') {
+            let (keyword, name) =
+                block
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| TemplateError {
+                        message: format!("'{{{{#{}}}}}' needs a column name", block),
+                        line,
+                    })?;
+            let name = name.trim().to_string();
+
+            match keyword {
+                "if" | "unless" => {
+                    let (then, has_else) = parse_nodes(tokens, Some((keyword, line)))?;
+                    let otherwise = if has_else {
+                        parse_nodes(tokens, Some((keyword, line)))?.0
+                    } else {
+                        Vec::new()
+                    };
+                    nodes.push(Node::If {
+                        name,
+                        negate: keyword == "unless",
+                        then,
+                        otherwise,
+                    });
+                }
+                "each" => {
+                    let (body, _) = parse_nodes(tokens, Some(("each", line)))?;
+                    nodes.push(Node::Each { name, body });
+                }
+                _ => {
+                    return Err(TemplateError {
+                        message: format!("Unknown block '{{{{#{}}}}}'", keyword),
+                        line,
+                    })
+                }
+            }
+            continue;
+        }
+
+        nodes.push(parse_placeholder(&tag, line)?);
+    }
+
+    match closing {
+        Some((block, line)) => Err(TemplateError {
+            message: format!("'{{{{#{}}}}}' is never closed", block),
+            line,
+        }),
+        None => Ok((nodes, false)),
+    }
+}
+
+struct Scope<'a> {
+    values: &'a HashMap<String, Blocks>,
+    variables: &'a HashMap<String, String>,
+    this: Option<&'a str>,
+    escape: fn(&str) -> String,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, name: &str) -> String {
+        match (name, self.this) {
+            ("this", Some(this)) => this.to_string(),
+            _ => self
+                .values
+                .get(name)
+                .map(|block| block.plain_text())
+                .or_else(|| self.variables.get(name).cloned())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn items(&self, name: &str) -> Vec<String> {
+        match (name, self.this) {
+            ("this", Some(this)) => vec![this.to_string()],
+            _ => match self.values.get(name) {
+                Some(block) => block.items(),
+                None => self.variables.get(name).cloned().into_iter().collect(),
+            },
+        }
+    }
+
+    fn is_truthy(&self, name: &str) -> bool {
+        match (name, self.this) {
+            ("this", Some(this)) => !this.is_empty(),
+            _ => match self.values.get(name) {
+                Some(block) => !block.is_empty(),
+                None => self
+                    .variables
+                    .get(name)
+                    .is_some_and(|value| !value.is_empty()),
+            },
+        }
+    }
+}
+
+fn format_date(value: &str, format: &str) -> Option<String> {
+    // Date ranges are formatted by their start.
+    let start = value.split(" → ").next()?.trim();
+    // Formatted in the date's own offset, so `%z` has one to show. Writing
+    // rather than `to_string()` turns anything chrono can't format into `None`.
+    let mut formatted = String::new();
+    write!(formatted, "{}", parse_date(start)?.format(format)).ok()?;
+    Some(formatted)
+}
+
+fn format_number(value: &str, places: usize) -> Option<String> {
+    let number = value.trim().parse::<f64>().ok()?;
+    let formatted = format!("{:.*}", places, number.abs());
+    let (whole, fraction) = match formatted.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (formatted.as_str(), None),
+    };
+
+    let mut out = String::new();
+    if number < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        out.push('-');
+    }
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    if let Some(fraction) = fraction {
+        out.push('.');
+        out.push_str(fraction);
+    }
+    Some(out)
+}
+
+fn apply_helpers(helpers: &[Helper], mut items: Vec<String>, mut value: String) -> String {
+    for helper in helpers.iter() {
+        match helper {
+            Helper::Upper => {
+                value = value.to_uppercase();
+                items = items.iter().map(|item| item.to_uppercase()).collect();
+            }
+            Helper::Lower => {
+                value = value.to_lowercase();
+                items = items.iter().map(|item| item.to_lowercase()).collect();
+            }
+            Helper::Date(format) => value = format_date(&value, format).unwrap_or(value),
+            Helper::Number(places) => value = format_number(&value, *places).unwrap_or(value),
+            Helper::Join(separator) => value = items.join(separator),
+        }
+    }
+    value
+}
+
+fn render_nodes(nodes: &[Node], scope: &Scope, out: &mut String, empty: &mut Vec<String>) {
+    for node in nodes.iter() {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Placeholder { name, helpers } => {
+                let mut value = scope.lookup(name);
+                if !helpers.is_empty() {
+                    value = apply_helpers(helpers, scope.items(name), value);
+                }
+                if value.trim().is_empty() && !empty.contains(name) {
+                    empty.push(name.clone());
+                }
+                // Variables are supplied by margaret rather than read from Notion,
+                // so they're trusted to already be in the right form.
+                let is_column =
+                    scope.values.contains_key(name) || (name == "this" && scope.this.is_some());
+                let is_variable = !is_column && scope.variables.contains_key(name);
+                if is_variable {
+                    out.push_str(&value);
+                } else {
+                    out.push_str(&(scope.escape)(&value));
+                }
+            }
+            Node::If {
+                name,
+                negate,
+                then,
+                otherwise,
+            } => {
+                if scope.is_truthy(name) != *negate {
+                    render_nodes(then, scope, out, empty);
+                } else {
+                    render_nodes(otherwise, scope, out, empty);
+                }
+            }
+            Node::Each { name, body } => {
+                for item in scope.items(name).iter() {
+                    let scope = Scope {
+                        this: Some(item),
+                        ..*scope
+                    };
+                    render_nodes(body, &scope, out, empty);
+                }
+            }
+        }
+    }
+}
+
+fn collect_placeholders(nodes: &[Node], names: &mut Vec<String>) {
+    fn push(names: &mut Vec<String>, name: &String) {
+        if name != "this" && !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    for node in nodes.iter() {
+        match node {
+            Node::Text(_) => {}
+            Node::Placeholder { name, .. } => push(names, name),
+            Node::If {
+                name,
+                then,
+                otherwise,
+                ..
+            } => {
+                push(names, name);
+                collect_placeholders(then, names);
+                collect_placeholders(otherwise, names);
+            }
+            Node::Each { name, body } => {
+                push(names, name);
+                collect_placeholders(body, names);
+            }
+        }
+    }
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        let mut tokens = tokenize(source)?.into_iter();
+        let (nodes, _) = parse_nodes(&mut tokens, None)?;
+        Ok(Template { nodes })
+    }
+
+    pub fn render(&self, values: &HashMap<String, Blocks>) -> String {
+        self.render_checked(values).0
+    }
+
+    /// Renders the template, also returning the placeholders that came out
+    /// empty. Placeholders skipped over by `{{#if}}` blocks aren't included.
+    pub fn render_checked(&self, values: &HashMap<String, Blocks>) -> (String, Vec<String>) {
+        self.render_with(values, &HashMap::new())
+    }
+
+    /// Like `render_checked`, with extra `variables` that aren't columns,
+    /// e.g. `{{unsubscribe_url}}`. A column with the same name wins.
+    pub fn render_with(
+        &self,
+        values: &HashMap<String, Blocks>,
+        variables: &HashMap<String, String>,
+    ) -> (String, Vec<String>) {
+        self.render_scoped(Scope {
+            values,
+            variables,
+            this: None,
+            escape: str::to_string,
+        })
+    }
+
+    /// Renders the template, passing each column's value through `escape`,
+    /// e.g. to escape HTML or keep values from adding directories to a path.
+    /// `variables` are left as they are.
+    pub fn render_escaped(
+        &self,
+        values: &HashMap<String, Blocks>,
+        variables: &HashMap<String, String>,
+        escape: fn(&str) -> String,
+    ) -> String {
+        self.render_scoped(Scope {
+            values,
+            variables,
+            this: None,
+            escape,
+        })
+        .0
+    }
+
+    fn render_scoped(&self, scope: Scope) -> (String, Vec<String>) {
+        let mut out = String::new();
+        let mut empty = Vec::new();
+        render_nodes(&self.nodes, &scope, &mut out, &mut empty);
+        (out, empty)
+    }
+
+    /// The names of every column the template refers to, in order of first use.
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        collect_placeholders(&self.nodes, &mut names);
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::models::database::fixtures::text;
+
+    fn values(properties: Value) -> HashMap<String, Blocks> {
+        serde_json::from_value(properties).unwrap()
+    }
+
+    fn title(title: &str) -> Value {
+        json!({ "title": text(&[title]) })
+    }
+
+    fn row() -> HashMap<String, Blocks> {
+        values(json!({
+            "Name": title("Ada Lovelace"),
+            "Amount": { "number": 1234.5 },
+            "Due": { "date": { "start": "2024-03-05" } },
+            "Tags": { "multi_select": [
+                { "id": "a", "name": "red", "color": "red" },
+                { "id": "b", "name": "blue", "color": "blue" },
+            ] },
+            "Paid": { "checkbox": false },
+        }))
+    }
+
+    fn render(source: &str) -> String {
+        Template::parse(source).unwrap().render(&row())
+    }
+
+    #[test]
+    fn placeholders_are_filled_in_with_helpers() {
+        assert_eq!(render("Dear {{Name | upper}},"), "Dear ADA LOVELACE,");
+        assert_eq!(render("{{Amount | number 2}}"), "1,234.50");
+        assert_eq!(render(r#"{{Due | date "%d %B %Y"}}"#), "05 March 2024");
+        assert_eq!(render(r#"{{Tags | join " & "}}"#), "red & blue");
+    }
+
+    #[test]
+    fn dates_keep_their_offset() {
+        let values = values(json!({
+            "Due": { "date": { "start": "2024-03-05T09:30:00.000+10:00" } },
+        }));
+        let render = |source: &str| Template::parse(source).unwrap().render(&values);
+        assert_eq!(
+            render(r#"{{Due | date "%Y-%m-%dT%H:%M%:z"}}"#),
+            "2024-03-05T09:30+10:00"
+        );
+        assert_eq!(render(r#"{{Due | date "%H%M %z"}}"#), "0930 +1000");
+    }
+
+    #[test]
+    fn conditionals_and_loops() {
+        assert_eq!(
+            render("{{#if Paid}}Thanks{{else}}Please pay{{/if}}"),
+            "Please pay"
+        );
+        assert_eq!(render("{{#unless Paid}}Unpaid{{/unless}}"), "Unpaid");
+        assert_eq!(render("{{#each Tags}}[{{this}}]{{/each}}"), "[red][blue]");
+    }
+
+    #[test]
+    fn empty_and_missing_placeholders_are_reported() {
+        let template = Template::parse("{{Name}} {{Nickname}}").unwrap();
+        let (rendered, empty) = template.render_checked(&row());
+        assert_eq!(rendered, "Ada Lovelace ");
+        assert_eq!(empty, ["Nickname"]);
+    }
+
+    #[test]
+    fn formatted_titles_stay_on_one_line() {
+        let mut segments = text(&["Ada ", "Lovelace"]);
+        segments[0]["annotations"]["bold"] = json!(true);
+        let values = values(json!({ "Name": { "title": segments } }));
+        let render = |source: &str| Template::parse(source).unwrap().render(&values);
+        assert_eq!(render("Subject: Hi {{Name}}"), "Subject: Hi Ada Lovelace");
+        assert_eq!(
+            render("{{#each Name}}[{{this}}]{{/each}}"),
+            "[Ada Lovelace]"
+        );
+    }
+
+    #[test]
+    fn columns_are_escaped_but_variables_arent() {
+        let values = values(json!({ "Name": title("<b>") }));
+        let variables = HashMap::from([("content".to_string(), "<p>Hi</p>".to_string())]);
+        let rendered = Template::parse("{{Name}}{{content}}")
+            .unwrap()
+            .render_escaped(&values, &variables, |text| text.replace('<', "&lt;"));
+        assert_eq!(rendered, "&lt;b><p>Hi</p>");
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_line() {
+        let error = |source: &str| Template::parse(source).unwrap_err().to_string();
+        assert_eq!(error("Hi\n{{Name"), "Unclosed '{{' (line 2)");
+        assert_eq!(
+            error("{{#if Paid}}\nThanks"),
+            "'{{#if}}' is never closed (line 1)"
+        );
+        assert_eq!(error("{{Name | shout}}"), "Unknown helper 'shout' (line 1)");
+    }
+}
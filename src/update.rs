@@ -0,0 +1,377 @@
+use std::{collections::HashMap, error::Error, future::Future, time::Duration};
+
+use futures::{stream, StreamExt};
+use reqwest::StatusCode;
+use serde_json::{Map, Value};
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::{
+    import::{property_value, RelationTitles},
+    journal::{Action, JournalRun},
+    models::{
+        database::{find_column, Column, Row},
+        pages::{
+            archive_page, trash_page, update_page, update_row_checked, writable_property,
+            PropertyValue, UpdateError,
+        },
+        responses::SimpleResponse,
+    },
+};
+
+/// How many times a request Notion rate limits is retried.
+const RETRIES: u32 = 3;
+
+/// Spaces requests out evenly, however many are made at once.
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn per_second(requests: u32) -> RateLimiter {
+        RateLimiter {
+            interval: Duration::from_secs(1) / requests.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until the next request can be made.
+    pub async fn wait(&self) {
+        let at = {
+            let mut next = self.next.lock().await;
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        tokio::time::sleep_until(at).await;
+    }
+}
+
+/// Splits `Column=value` assignments, looking up their columns.
+pub fn parse_assignments<'a>(
+    assignments: &[String],
+    columns: &'a [Column],
+) -> Result<Vec<(&'a Column, String)>, String> {
+    assignments
+        .iter()
+        .map(|assignment| {
+            let (name, value) = assignment
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' should be Column=value", assignment))?;
+            let name = name.trim();
+            let column = find_column(columns, name)
+                .ok_or_else(|| format!("The column '{}' does not exist.", name))?;
+            Ok((column, value.to_string()))
+        })
+        .collect()
+}
+
+/// Converts assignments to the properties to set, keyed by column id and
+/// read the same way `import` reads cells. An empty value clears the property.
+pub fn assignment_properties(
+    assignments: &[(&Column, String)],
+    relations: &RelationTitles,
+) -> Result<HashMap<String, PropertyValue>, String> {
+    assignments
+        .iter()
+        .map(|(column, value)| {
+            let value = match property_value(column, value, relations) {
+                Ok(Some(value)) => value,
+                Ok(None) => PropertyValue::cleared(&column.column_type).ok_or_else(|| {
+                    format!(
+                        "'{}' is a {} column, which can't be set",
+                        column.name, column.column_type
+                    )
+                })?,
+                Err(problem) => return Err(format!("{}: {}", column.name, problem)),
+            };
+            Ok((column.reference().to_string(), value))
+        })
+        .collect()
+}
+
+pub struct UpdateOptions {
+    /// Most requests in flight at once.
+    pub concurrency: usize,
+    /// Most requests started in any one second.
+    pub per_second: u32,
+    /// Leave rows edited since they were read alone, with a `ConflictError`.
+    pub check_conflicts: bool,
+}
+
+/// How updating one row went.
+pub struct RowResult {
+    pub row_id: String,
+    pub title: Option<String>,
+    pub result: Result<(), Box<dyn Error>>,
+}
+
+/// The values `properties` had on `row` before being set, for the journal,
+/// exactly as Notion gave them. `properties` is keyed by column id, or by
+/// name for columns without one.
+fn previous_values(
+    row: &Row,
+    properties: &HashMap<String, PropertyValue>,
+) -> Result<Value, String> {
+    let cells = row.properties.as_ref();
+    properties
+        .keys()
+        .map(|reference| {
+            let (name, cell) = cells
+                .into_iter()
+                .flatten()
+                .find(|(name, cell)| &cell.id == reference || *name == reference)
+                .ok_or_else(|| format!("the row has no '{}' property", reference))?;
+            let previous =
+                writable_property(&cell.raw).map_err(|err| format!("{}: {}", name, err))?;
+            Ok((reference.clone(), previous[&cell.cell_type].clone()))
+        })
+        .collect::<Result<Map<String, Value>, String>>()
+        .map(Value::Object)
+}
+
+/// The page's `last_edited_time` from a response to a request that changed it.
+fn edited_time(response: &SimpleResponse) -> Option<String> {
+    let page: Row = serde_json::from_str(&response.body).ok()?;
+    Some(page.last_edited_time)
+}
+
+/// Makes a request once `limiter` allows, retrying it with exponential
+/// backoff while Notion rate limits it.
+pub(crate) async fn with_retries<T, F, Fut>(
+    limiter: &RateLimiter,
+    request: F,
+) -> Result<T, UpdateError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, UpdateError>>,
+{
+    let mut attempt = 0;
+    loop {
+        limiter.wait().await;
+        match request().await {
+            Err(UpdateError::Response(err))
+                if err.response.status == StatusCode::TOO_MANY_REQUESTS && attempt < RETRIES =>
+            {
+                attempt += 1;
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A change to make to each row.
+#[derive(Clone, Copy)]
+enum RowWrite<'a> {
+    Update(&'a HashMap<String, PropertyValue>),
+    Archive,
+    Trash,
+}
+
+async fn write_row(
+    token: &str,
+    row: &Row,
+    write: RowWrite<'_>,
+    options: &UpdateOptions,
+    limiter: &RateLimiter,
+) -> Result<SimpleResponse, UpdateError> {
+    with_retries(limiter, || async move {
+        match write {
+            RowWrite::Update(properties) if options.check_conflicts => {
+                // Checking takes a request of its own.
+                limiter.wait().await;
+                update_row_checked(token, row, properties).await
+            }
+            RowWrite::Update(properties) => Ok(update_page(token, &row.id, properties).await?),
+            RowWrite::Archive => Ok(archive_page(token, &row.id).await?),
+            RowWrite::Trash => Ok(trash_page(token, &row.id).await?),
+        }
+    })
+    .await
+}
+
+/// Makes `write` to every row, retrying requests Notion rate limits, and
+/// recording each write in the journal first if there's a run.
+async fn write_rows(
+    token: &str,
+    rows: &[Row],
+    write: RowWrite<'_>,
+    options: &UpdateOptions,
+    run: Option<&JournalRun<'_>>,
+) -> Vec<RowResult> {
+    let limiter = RateLimiter::per_second(options.per_second);
+    stream::iter(rows.iter())
+        .map(|row| {
+            let limiter = &limiter;
+            async move {
+                let result = async {
+                    if let Some(run) = run {
+                        let (action, previous) = match write {
+                            RowWrite::Update(properties) => {
+                                // Without the previous values the write couldn't be undone.
+                                let previous = previous_values(row, properties).map_err(|err| {
+                                    format!(
+                                        "Couldn't record how it was, so it wasn't changed: {}",
+                                        err
+                                    )
+                                })?;
+                                (Action::Update, Some(previous))
+                            }
+                            RowWrite::Archive => (Action::Archive, None),
+                            RowWrite::Trash => (Action::Trash, None),
+                        };
+                        run.before(&row.id, action, previous.as_ref())?;
+                    }
+                    match write_row(token, row, write, options, limiter).await {
+                        Ok(response) => {
+                            if let (Some(run), Some(edited_time)) = (run, edited_time(&response)) {
+                                run.written(&row.id, &edited_time)?;
+                            }
+                            Ok(())
+                        }
+                        Err(err) => {
+                            if let Some(run) = run {
+                                run.failed(&row.id)?;
+                            }
+                            Err(err.into())
+                        }
+                    }
+                }
+                .await;
+                RowResult {
+                    row_id: row.id.clone(),
+                    title: row.title(),
+                    result,
+                }
+            }
+        })
+        .buffered(options.concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Sets `properties` on every row, retrying requests Notion rate limits, and
+/// recording each row's previous values in the journal first if there's a run.
+/// Results are in the same order as `rows`.
+pub async fn update_rows(
+    token: &str,
+    rows: &[Row],
+    properties: &HashMap<String, PropertyValue>,
+    options: &UpdateOptions,
+    run: Option<&JournalRun<'_>>,
+) -> Vec<RowResult> {
+    write_rows(token, rows, RowWrite::Update(properties), options, run).await
+}
+
+/// Archives every row, recording each in the journal first if there's a run.
+/// Conflicts aren't checked for.
+pub async fn archive_rows(
+    token: &str,
+    rows: &[Row],
+    options: &UpdateOptions,
+    run: Option<&JournalRun<'_>>,
+) -> Vec<RowResult> {
+    write_rows(token, rows, RowWrite::Archive, options, run).await
+}
+
+/// Moves every row to the trash, recording each in the journal first if
+/// there's a run. Conflicts aren't checked for.
+pub async fn trash_rows(
+    token: &str,
+    rows: &[Row],
+    options: &UpdateOptions,
+    run: Option<&JournalRun<'_>>,
+) -> Vec<RowResult> {
+    write_rows(token, rows, RowWrite::Trash, options, run).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::models::{database::fixtures::column, responses::ErrorResponse};
+
+    #[test]
+    fn assignments_are_split_at_the_first_equals_sign() {
+        let columns = [
+            column("s", "Status", "select"),
+            column("f", "Formula", "rich_text"),
+        ];
+        let assignments = parse_assignments(
+            &[" Status =Done".to_string(), "Formula=a=b".to_string()],
+            &columns,
+        )
+        .unwrap();
+        assert_eq!(
+            assignments
+                .iter()
+                .map(|(column, value)| (column.id.as_str(), value.as_str()))
+                .collect::<Vec<(&str, &str)>>(),
+            [("s", "Done"), ("f", "a=b")]
+        );
+
+        assert_eq!(
+            parse_assignments(&["Status".to_string()], &columns)
+                .err()
+                .as_deref(),
+            Some("'Status' should be Column=value")
+        );
+        assert_eq!(
+            parse_assignments(&["Stage=Done".to_string()], &columns)
+                .err()
+                .as_deref(),
+            Some("The column 'Stage' does not exist.")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_are_spaced_out_evenly() {
+        let limiter = RateLimiter::per_second(4);
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.wait().await;
+        }
+        // The first goes straight away.
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited_requests_are_retried_with_backoff() {
+        let limiter = RateLimiter::per_second(1000);
+        let attempts = AtomicU32::new(0);
+        let rate_limited = || {
+            UpdateError::Response(ErrorResponse {
+                response: SimpleResponse {
+                    status: StatusCode::TOO_MANY_REQUESTS,
+                    body: "rate_limited".to_string(),
+                },
+            })
+        };
+
+        let start = Instant::now();
+        let result = with_retries(&limiter, || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(rate_limited()),
+                _ => Ok(()),
+            }
+        })
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        // 2s, then 4s, plus the limiter's spacing between attempts.
+        assert!(start.elapsed() >= Duration::from_secs(6));
+        assert!(start.elapsed() < Duration::from_secs(7));
+
+        // Eventually it gives up.
+        attempts.store(0, Ordering::SeqCst);
+        let result: Result<(), UpdateError> = with_retries(&limiter, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(rate_limited())
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), RETRIES + 1);
+    }
+}